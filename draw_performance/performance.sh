@@ -55,7 +55,7 @@ for i in models/*railml generated/*railml; do
 
 
 	# convert to old gridvis format
-	../target/release/rolling convert/dgraph/$filename.dgraph empty empty -g convert/gridvisformat/$filename.g > /dev/null
+	../target/release/rolling graphical convert/dgraph/$filename.dgraph -o convert/gridvisformat/$filename.g
 
 	# run the direct sat (gridvis) method
 
@@ -98,7 +98,10 @@ fn infrastructure_objects(inf :&StaticInfrastructure, sections: &HashMap<String,
 }
 
 fn schematic_update(s :&str) -> Result<serde_json::Value, String> {
-    let (inf,sections) = milelang::convert_dgraph(s).map_err(|e| format!("{:?}",e))?;
+    let (inf,sections,diagnostics) = milelang::convert_dgraph(s).map_err(|e| format!("{:?}",e))?;
+    for d in &diagnostics {
+        println!("Input warning: {}", d);
+    }
     let object_data = infrastructure_objects(&inf, &sections);
     let schematic = vis_rs::convert_dgraph(&inf)?;
     let (edge_lines,node_data) = vis_rs::convert_javascript(schematic)?;
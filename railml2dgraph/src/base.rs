@@ -10,6 +10,13 @@ impl Side {
             Side::Right => "right",
         }
     }
+
+    pub fn opposite(&self) -> Self {
+        match *self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
 }
 
 impl Dir {
@@ -1,21 +1,59 @@
 use minidom; 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
 use base::*;
 
 pub struct BranchingModel {
     pub tracks:       Vec<BrTrack>,
     pub connections:  HashMap<(String,String), BrCursor>,
     pub switch_conns: HashSet<(String,String)>,
+    /// Connections through crossings, from the (ref,id) of the incoming connection
+    /// to the (ref,id) of the outgoing connection.
+    pub crossing_conns: Vec<((String,String),(String,String))>,
+    pub stops: Vec<BrStop>,
+}
+
+/// Stop location from a platform edge, from `pos` to `pos + length` on a track.
+#[derive(Clone, Debug)]
+pub struct BrStop {
+    pub name: String,
+    /// Id of the operational control point (station) of the platform.
+    pub ocp: Option<String>,
+    pub track: usize,
+    pub pos: f64,
+    pub length: f64,
 }
 
 #[derive(Clone)]
 pub struct BrTrack {
     pub name: String,
+    /// Id of the track (railML 2.x) or net element (railML 3.x).
+    pub id: String,
     pub begin :BrTrackEnd,
     pub objs :Vec<BrObject>,
     pub length: f64,
     pub end: BrTrackEnd,
+    /// Known absolute positions (kilometrage in meters) as `(pos, abs_pos)`.
+    pub mileage: Vec<(f64,f64)>,
+}
+
+/// Absolute position at a position along a track, interpolated between the
+/// track's known absolute positions. Beyond the first and last known positions,
+/// the mileage is assumed to increase along the track. Points which are not
+/// finite numbers are ignored.
+pub fn abs_pos(mileage :&[(f64,f64)], pos :f64) -> Option<f64> {
+    let mut points = mileage.iter().cloned()
+        .filter(|&(p, a)| p.is_finite() && a.is_finite())
+        .collect::<Vec<_>>();
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let first = *points.first()?;
+    let last = *points.last()?;
+    if pos <= first.0 { return Some(first.1 - (first.0 - pos)); }
+    if pos >= last.0 { return Some(last.1 + (pos - last.0)); }
+    points.windows(2)
+        .find(|w| pos <= w[1].0)
+        .map(|w| w[0].1 + (w[1].1 - w[0].1) * (pos - w[0].0) / (w[1].0 - w[0].0))
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -43,12 +81,72 @@ pub struct BrObject {
 pub enum BrObjectData {
     Signal { dir: Dir, sight: f64 },
     Sight { dir: Dir, signal: String, distance: f64 },
-    Detector,
+    /// Detection point, which only detects trains running in the given direction
+    /// when it has one.
+    Detector { dir: Option<Dir> },
+    /// Maximum velocity in m/s from here on, for trains running in the given
+    /// direction, or in both directions.
+    SpeedChange { dir: Option<Dir>, v: f64 },
+    /// Gradient in per mille from here on, uphill when running up.
+    GradientChange { slope: f64 },
+    /// End of a stop location, which gets a node of its own.
+    StopLimit,
     Switch { dir :Dir, side: Side, conn: (String,String) }, // TODO what about when continuing track is the branching track?
+    Crossing {
+        kind: CrossingKind,
+        /// Connection to the crossing track ending at the crossing.
+        incoming: (String,String),
+        /// Connection to the crossing track beginning at the crossing.
+        outgoing: (String,String),
+        /// Side of the slip curves, seen along this track, towards the
+        /// incoming and the outgoing crossing track.
+        slips: (Option<Side>, Option<Side>),
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CrossingKind {
+    Diamond,
+    SingleSlip,
+    DoubleSlip,
 }
 
 
-pub fn get_branching_model<'a>(doc :&'a minidom::Element, ns: &str) 
+/// A problem in the railML file which does not stop the conversion.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Path of the railML element, or the name of the model object.
+    pub element: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.element, self.message)
+    }
+}
+
+/// Path of an element below its parent's path, with the element id (or else its
+/// name), as in `/railml/infrastructure/tracks/track[@id="t1"]`.
+pub fn element_path(parent :&str, e :&minidom::Element) -> String {
+    match (e.attr("id"), e.attr("name")) {
+        (Some(id), _) => format!("{}/{}[@id={:?}]", parent, e.name(), id),
+        (None, Some(name)) => format!("{}/{}[@name={:?}]", parent, e.name(), name),
+        _ => format!("{}/{}", parent, e.name()),
+    }
+}
+
+pub fn required_attr<'a>(e :&'a minidom::Element, path :&str, name :&str) -> Result<&'a str, String> {
+    e.attr(name).ok_or(format!("{}: missing attribute {:?}", path, name))
+}
+
+pub fn number_attr(e :&minidom::Element, path :&str, name :&str) -> Result<f64, String> {
+    let value = required_attr(e, path, name)?;
+    value.parse::<f64>()
+        .map_err(|_| format!("{}: attribute {:?} is not a number: {:?}", path, name, value))
+}
+
+pub fn get_branching_model<'a>(doc :&'a minidom::Element, ns: &str, diagnostics :&mut Vec<Diagnostic>)
     -> Result<BranchingModel, String> {
 
     // Convert railML from XML into a similar structure that we
@@ -62,97 +160,167 @@ pub fn get_branching_model<'a>(doc :&'a minidom::Element, ns: &str)
         tracks: Vec::new(),
         connections: HashMap::new(),
         switch_conns: HashSet::new(),
+        crossing_conns: Vec::new(),
+        stops: Vec::new(),
     };
 
-    let infrastructure = {
-        if doc.name().to_lowercase() == "infrastructure" {
-            doc
-        } else if doc.name().to_lowercase() == "railml" {
-            doc.children()
-                .filter(|x| x.name().to_lowercase() == "infrastructure")
-                .nth(0)
-                .ok_or(format!("No infrastructure element found"))?
-        } else {
-            return Err(format!("No infrastructure element found"));
-        }
-    };
+    let infrastructure = infrastructure_element(doc)?;
+    let infrastructure_path = infrastructure_path(doc);
+
+    let ocps = infrastructure.get_child("operationControlPoints", ns)
+        .map(|o| o.children().filter(|x| x.name() == "ocp")
+             .filter_map(|x| x.attr("id").map(|id| (id.to_string(), x.attr("name").unwrap_or(id).to_string())))
+             .collect::<HashMap<_,_>>())
+        .unwrap_or_else(|| HashMap::new());
 
     let tracks = infrastructure.children()
         .filter(|x| x.name().to_lowercase() == "tracks")
         .nth(0)
-        .ok_or(format!("No tracks found in the infrastructure"))?;
+        .ok_or(format!("{}: no tracks found in the infrastructure", infrastructure_path))?;
+    let tracks_path = element_path(&infrastructure_path, tracks);
 
     //let conn_id_to_cursor = HashMap::new();
     //let conn_ref_to_id = HashMap::new();
 
+    let mut track_paths = Vec::new();
     for track in tracks.children().filter(|x| x.name().to_lowercase() == "track") {
         let track_idx = model.tracks.len();
-        let name = track.attr("name")
-            .ok_or(format!("No name for track"))?;
+        let path = element_path(&tracks_path, track);
+        let name = required_attr(track, &path, "name")?;
+        let id = track.attr("id").unwrap_or(name);
 
         let topology = track.get_child("trackTopology", ns)
-            .ok_or(format!("No trackTopology element in track {:?}", name))?;
-
-        let track_length = topology.get_child("trackEnd", ns)
-            .ok_or(format!("No trackEnd element on track {:?}", name))?
-            .attr("pos").ok_or(format!("No pos attribute on trackEnd on track {:?}", name))?
-            .parse::<f64>().map_err(|e| format!("{:?}", e))?;
+            .ok_or(format!("{}: no trackTopology element", path))?;
+        let topology_path = element_path(&path, topology);
 
         let track_begin = topology.get_child("trackBegin", ns)
-            .ok_or(format!("No trackBegin found on track {:?}.", name))?;
+            .ok_or(format!("{}: no trackBegin element", topology_path))?;
         let track_end = topology.get_child("trackEnd", ns)
-            .ok_or(format!("No trackEnd found on track {:?}.", name))?;
+            .ok_or(format!("{}: no trackEnd element", topology_path))?;
+        let track_length = number_attr(track_end, &element_path(&topology_path, track_end), "pos")?;
 
         let (begin,end) = {
-            let mut conv_end = |n:&minidom::Element,offset,dir| {
+            let mut conv_end = |n:&minidom::Element,offset,dir| -> Result<BrTrackEnd, String> {
+                let path = element_path(&topology_path, n);
                 if let Some(conn) = n.get_child("connection", ns) {
-                    let id = conn.attr("id").ok_or(format!("Connection has no id.")).unwrap();
-                    let ref_ = conn.attr("ref").ok_or(format!("Connection has no ref.")).unwrap();
+                    let path = element_path(&path, conn);
+                    let id = required_attr(conn, &path, "id")?;
+                    let ref_ = required_attr(conn, &path, "ref")?;
                     model.connections.insert((ref_.to_string(),id.to_string()), 
                                              BrCursor { track: track_idx, offset, dir });
-                    BrTrackEnd::Connection((id.to_string(),ref_.to_string()))
+                    Ok(BrTrackEnd::Connection((id.to_string(),ref_.to_string())))
                 } else if let Some(e) = n.get_child("openEnd", ns) {
-                    let name = e.attr("id").ok_or(format!("openEnd has no id.")).unwrap();
-                    BrTrackEnd::Boundary(name.to_string())
+                    let name = required_attr(e, &element_path(&path, e), "id")?;
+                    Ok(BrTrackEnd::Boundary(name.to_string()))
                 } else {
-                    BrTrackEnd::Stop
+                    Ok(BrTrackEnd::Stop)
                 }
             };
-            let begin = conv_end(track_begin, 0.0, Dir::Up);
-            let end   = conv_end(track_end, track_length, Dir::Down);
+            let begin = conv_end(track_begin, 0.0, Dir::Up)?;
+            let end   = conv_end(track_end, track_length, Dir::Down)?;
             (begin,end)
         };
 
         let mut objs = Vec::new();
-        add_signals(&mut objs, &track, ns);
-        add_detectors(&mut objs, &track, ns);
+        add_signals(&mut objs, &track, &path, ns, diagnostics)?;
+        add_detectors(&mut objs, &track, &path, ns, diagnostics)?;
         add_switches(&mut objs, &mut model.connections, &mut model.switch_conns,
-                     &track, track_idx, ns);
+                     &track, &path, track_idx, ns)?;
+        add_crossings(&mut objs, &mut model.crossing_conns, &track, &path, ns)?;
+        add_speed_changes(&mut objs, &track, &path, ns, diagnostics)?;
+        add_gradient_changes(&mut objs, &track, &path, ns)?;
+        add_platform_edges(&mut objs, &mut model.stops, &ocps, &track, &path, track_idx, track_length,
+                           ns, diagnostics)?;
+
+        let mut mileage = Vec::new();
+        add_mileage(&mut mileage, track);
+
+        model.tracks.push(BrTrack { name: name.to_string(), id: id.to_string(), begin, objs,
+                                    length: track_length, end, mileage });
+        track_paths.push(path);
+    }
 
-        model.tracks.push(BrTrack { name: name.to_string(), begin, objs, length: track_length, end });
+    // The branching track of each switch must begin or end at the switch.
+    for (track, path) in model.tracks.iter().zip(&track_paths) {
+        for obj in &track.objs {
+            if let BrObjectData::Switch { ref conn, .. } = obj.data {
+                if !model.connections.contains_key(conn) {
+                    return Err(format!("{}/trackTopology/connections/switch[@id={:?}]: connection {:?} \
+                                        refers to {:?}, which is not on a track begin or end",
+                                       path, obj.name, conn.0, conn.1));
+                }
+            }
+        }
+    }
+
+    // Walking straight through a crossing continues from the incoming
+    // crossing track to the outgoing crossing track, and the other way.
+    for (incoming, outgoing) in &model.crossing_conns {
+        let before = model.connections.get(&(incoming.1.clone(), incoming.0.clone())).cloned();
+        let after = model.connections.get(&(outgoing.1.clone(), outgoing.0.clone())).cloned();
+        if let Some(after) = after { model.connections.insert(incoming.clone(), after); }
+        if let Some(before) = before { model.connections.insert(outgoing.clone(), before); }
     }
 
     Ok(model)
 }
 
-fn add_signals(vec :&mut Vec<BrObject>, track :&minidom::Element, ns :&str) {
+/// The infrastructure element, which is either the document root or a child of the railML root.
+pub fn infrastructure_element(doc :&minidom::Element) -> Result<&minidom::Element, String> {
+    if doc.name().to_lowercase() == "infrastructure" {
+        Ok(doc)
+    } else if doc.name().to_lowercase() == "railml" {
+        doc.children()
+            .filter(|x| x.name().to_lowercase() == "infrastructure")
+            .nth(0)
+            .ok_or(format!("No infrastructure element found"))
+    } else {
+        Err(format!("No infrastructure element found"))
+    }
+}
+
+/// Path of the infrastructure element, for use with `element_path`.
+pub fn infrastructure_path(doc :&minidom::Element) -> String {
+    let root = element_path("", doc);
+    match infrastructure_element(doc) {
+        Ok(e) if doc.name().to_lowercase() != "infrastructure" => element_path(&root, e),
+        _ => root,
+    }
+}
+
+// Elements of the track with both a position and an absolute position.
+fn add_mileage(mileage :&mut Vec<(f64,f64)>, e :&minidom::Element) {
+    let number = |name :&str| e.attr(name).and_then(|x| x.parse::<f64>().ok());
+    if let (Some(pos), Some(abs_pos)) = (number("pos"), number("absPos")) {
+        mileage.push((pos, abs_pos));
+    }
+    for c in e.children() {
+        add_mileage(mileage, c);
+    }
+}
+
+fn add_signals(vec :&mut Vec<BrObject>, track :&minidom::Element, track_path :&str, ns :&str,
+               diagnostics :&mut Vec<Diagnostic>) -> Result<(), String> {
     let signal_elements = track.get_child("ocsElements", ns)
         .and_then(|o| o.get_child("signals", ns))
         .map(|s| s.children().filter(|x| x.name() == "signal").collect())
         .unwrap_or_else(|| Vec::new());
+    let signals_path = format!("{}/ocsElements/signals", track_path);
 
     for s in signal_elements {
-        let id = s.attr("id").expect("signal id missing");
+        let path = element_path(&signals_path, s);
+        let id = required_attr(s, &path, "id")?;
         //let _name = s.attr("name").expect("signal name missing");
         let name = id.to_string();
-        let pos = s.attr("pos").expect("signal pos missing")
-            .parse::<f64>().expect("signal pos is not a valid number");
-        let sight = s.attr("sight")
-            .map(|x| x.parse::<f64>().expect("signal sight is not a valid number"))
-            .unwrap_or_else(|| {
-                println!("Warning: no sight info for signal {:?}", name);
+        let pos = number_attr(s, &path, "pos")?;
+        let sight = match s.attr("sight") {
+            Some(_) => number_attr(s, &path, "sight")?,
+            None => {
+                diagnostics.push(Diagnostic { element: path.clone(),
+                                              message: format!("no sight info for signal, using 200 m") });
                 200.0
-            });
+            }
+        };
         let dir = match s.attr("dir") {
             Some("up") => Dir::Up,
             Some("down") => Dir::Down,
@@ -171,9 +339,11 @@ fn add_signals(vec :&mut Vec<BrObject>, track :&minidom::Element, ns :&str) {
             vec.push(BrObject { name, pos, data: BrObjectData::Signal { dir, sight } });
         }
     }
+    Ok(())
 }
 
-fn add_detectors(vec :&mut Vec<BrObject>, track :&minidom::Element, ns :&str) {
+fn add_detectors(vec :&mut Vec<BrObject>, track :&minidom::Element, track_path :&str, ns :&str,
+                 diagnostics :&mut Vec<Diagnostic>) -> Result<(), String> {
     let detector_elements = track.get_child("ocsElements", ns)
         .and_then(|o| o.get_child("trainDetectionElements", ns))
         .map(|s| {
@@ -182,25 +352,135 @@ fn add_detectors(vec :&mut Vec<BrObject>, track :&minidom::Element, ns :&str) {
                 .collect()
         })
         .unwrap_or_else(|| Vec::new());
+    let detectors_path = format!("{}/ocsElements/trainDetectionElements", track_path);
 
     for d in &detector_elements {
-        let id = d.attr("id").expect("detector id missing");
+        let path = element_path(&detectors_path, d);
+        let id = required_attr(d, &path, "id")?;
         let name = id.to_string();
-        let pos = d.attr("pos").expect("detector pos missing")
-            .parse::<f64>().expect("detector pos is not a valid number");
+        let pos = number_attr(d, &path, "pos")?;
+
+        // Track circuit borders always detect both directions.
+        let dir = match d.attr("dir") {
+            Some("up") => Some(Dir::Up),
+            Some("down") => Some(Dir::Down),
+            _ => None,
+        };
+        let dir = if dir.is_some() && d.name() == "trackCircuitBorder" {
+            diagnostics.push(Diagnostic { element: path.clone(),
+                                          message: format!("track circuit border detects both directions, ignoring dir") });
+            None
+        } else { dir };
+
+        vec.push(BrObject { name, pos, data: BrObjectData::Detector { dir } });
+    }
+    Ok(())
+}
 
-        match d.attr("dir") {
-            Some("up") | Some("down") => {
-                println!("Warning: directional detector is not supported {:?}", name);
+// Elements of a group in the trackElements (or ocsElements) of a track, with the path of the group.
+fn track_elements<'a>(track :&'a minidom::Element, track_path :&str, ns :&str, group :&str, item :&str)
+    -> Vec<(&'a minidom::Element, String)> {
+    ["trackElements", "ocsElements"].iter()
+        .filter_map(|&parent| track.get_child(parent, ns)
+                    .and_then(|e| e.get_child(group, ns))
+                    .map(|g| (g, format!("{}/{}/{}", track_path, parent, group))))
+        .flat_map(|(g, path)| g.children().filter(|x| x.name() == item).map(|x| (x, path.clone())).collect::<Vec<_>>())
+        .collect()
+}
+
+fn add_speed_changes(vec :&mut Vec<BrObject>, track :&minidom::Element, track_path :&str, ns :&str,
+                     diagnostics :&mut Vec<Diagnostic>) -> Result<(), String> {
+    for (s, parent) in track_elements(track, track_path, ns, "speedChanges", "speedChange") {
+        let path = element_path(&parent, s);
+        let name = required_attr(s, &path, "id")?.to_string();
+        let pos = number_attr(s, &path, "pos")?;
+        // Speed changes for a speed profile only apply to some trains.
+        if s.attr("profileRef").is_some() {
+            diagnostics.push(Diagnostic { element: path.clone(),
+                                          message: format!("speed change for a speed profile is ignored") });
+            continue;
+        }
+        let v = match s.attr("vMax") {
+            Some(_) => number_attr(s, &path, "vMax")? / 3.6,
+            None => {
+                diagnostics.push(Diagnostic { element: path.clone(),
+                                              message: format!("no vMax for speed change, ignoring it") });
+                continue;
             }
-            _ => {},
         };
+        let dir = match s.attr("dir") {
+            Some("up") => Some(Dir::Up),
+            Some("down") => Some(Dir::Down),
+            _ => None,
+        };
+        vec.push(BrObject { name, pos, data: BrObjectData::SpeedChange { dir, v } });
+    }
+    Ok(())
+}
 
-        vec.push(BrObject { name, pos, data: BrObjectData::Detector });
+fn add_gradient_changes(vec :&mut Vec<BrObject>, track :&minidom::Element, track_path :&str, ns :&str)
+    -> Result<(), String> {
+    for (g, parent) in track_elements(track, track_path, ns, "gradientChanges", "gradientChange") {
+        let path = element_path(&parent, g);
+        let name = required_attr(g, &path, "id")?.to_string();
+        let pos = number_attr(g, &path, "pos")?;
+        let slope = number_attr(g, &path, "slope")?;
+        // The slope is given in the direction of the element.
+        let slope = if g.attr("dir") == Some("down") { -slope } else { slope };
+        vec.push(BrObject { name, pos, data: BrObjectData::GradientChange { slope } });
     }
+    Ok(())
 }
 
-fn add_switches(vec :&mut Vec<BrObject>, connections :&mut HashMap<(String,String), BrCursor>, switch_conns :&mut HashSet<(String,String)>, track :&minidom::Element, track_idx: usize, ns :&str) {
+// Stop locations from the platform edges of a track, named after the station and
+// the platform (such as `Kolbotn_2`), or else by the platform edge id.
+fn add_platform_edges(vec :&mut Vec<BrObject>, stops :&mut Vec<BrStop>, ocps :&HashMap<String,String>,
+                      track :&minidom::Element, track_path :&str, track_idx :usize, track_length :f64,
+                      ns :&str, diagnostics :&mut Vec<Diagnostic>) -> Result<(), String> {
+    for (p, parent) in track_elements(track, track_path, ns, "platformEdges", "platformEdge") {
+        let path = element_path(&parent, p);
+        let id = required_attr(p, &path, "id")?;
+        let pos = number_attr(p, &path, "pos")?;
+        let length = match p.attr("length") {
+            Some(_) => number_attr(p, &path, "length")?,
+            None => {
+                diagnostics.push(Diagnostic { element: path.clone(),
+                                              message: format!("no length for platform edge, ignoring it") });
+                continue;
+            }
+        };
+        let end = (pos + length).min(track_length);
+        let ocp = p.attr("ocpRef").map(|x| x.to_string());
+        if let Some(ref o) = ocp {
+            if !ocps.contains_key(o) {
+                diagnostics.push(Diagnostic { element: path.clone(),
+                                              message: format!("unknown ocp {:?}", o) });
+            }
+        }
+        let name = match (ocp.as_ref().and_then(|o| ocps.get(o)), p.attr("name")) {
+            (Some(station), Some(platform)) => identifier(&format!("{} {}", station, platform)),
+            _ => id.to_string(),
+        };
+        let name = if stops.iter().any(|s| s.name == name) {
+            diagnostics.push(Diagnostic { element: path.clone(),
+                                          message: format!("stop location {:?} already exists, using the id", name) });
+            id.to_string()
+        } else { name };
+
+        for &limit in &[pos, end] {
+            vec.push(BrObject { name: format!("{}_{}", id, limit), pos: limit, data: BrObjectData::StopLimit });
+        }
+        stops.push(BrStop { name, ocp, track: track_idx, pos, length: end - pos });
+    }
+    Ok(())
+}
+
+/// Name usable as an identifier in the Rolling formats.
+pub fn identifier(name :&str) -> String {
+    name.trim().chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn add_switches(vec :&mut Vec<BrObject>, connections :&mut HashMap<(String,String), BrCursor>, switch_conns :&mut HashSet<(String,String)>, track :&minidom::Element, track_path :&str, track_idx: usize, ns :&str) -> Result<(), String> {
     let switches = track.get_child("trackTopology",ns)
         .and_then(|o| o.get_child("connections", ns))
         .map(|c| {
@@ -209,41 +489,39 @@ fn add_switches(vec :&mut Vec<BrObject>, connections :&mut HashMap<(String,Strin
                 .collect::<Vec<_>>()
         })
         .unwrap_or_else(|| Vec::new());
+    let switches_path = format!("{}/trackTopology/connections", track_path);
 
 
     for sw in &switches {
-        let id = sw.attr("id").expect("switch id missing");
+        let path = element_path(&switches_path, sw);
+        let id = required_attr(sw, &path, "id")?;
         let name = id.to_string();
-        let pos = sw.attr("pos").expect("switch pos missing")
-        .parse::<f64>().expect("switch pos is not a valid number");
+        let pos = number_attr(sw, &path, "pos")?;
 
         let conns = sw.children().filter(|x| x.name() == "connection").collect::<Vec<_>>();
         if conns.len() == 0 {
-            println!("No connection in switch {:?}", name);
-            panic!();
+            return Err(format!("{}: no connection in switch", path));
         }
         if conns.len() > 1 {
-            println!("Multiple connections in switch {:?}", name);
-            panic!();
+            return Err(format!("{}: multiple connections in switch", path));
         }
 
         let conn = conns[0];
+        let conn_path = element_path(&path, conn);
         let dir = match conn.attr("orientation") {
             Some("outgoing") => Dir::Up,
             Some("incoming") => Dir::Down,
-            _ => panic!("Switch orientation"),
+            x => return Err(format!("{}: unknown switch orientation {:?}", conn_path, x)),
         };
 
         let side = match conn.attr("course") {
             Some("left") => Side::Left,
             Some("right") => Side::Right,
-            _ => panic!("Switch course"),
+            x => return Err(format!("{}: unknown switch course {:?}", conn_path, x)),
         };
 
-        let conn_name = match (conn.attr("id"), conn.attr("ref")) {
-            (Some(id),Some(ref_)) => (id.to_string(), ref_.to_string()),
-            _ => panic!("Switch connection ids missing"),
-        };
+        let conn_name = (required_attr(conn, &conn_path, "id")?.to_string(),
+                         required_attr(conn, &conn_path, "ref")?.to_string());
 
         connections.insert((conn_name.1.clone(), conn_name.0.clone()), 
                            BrCursor { track: track_idx, offset: pos, dir: dir.opposite() });
@@ -251,8 +529,94 @@ fn add_switches(vec :&mut Vec<BrObject>, connections :&mut HashMap<(String,Strin
         switch_conns.insert((conn_name.1.clone(), conn_name.0.clone()));
         vec.push(BrObject { name, pos, data: BrObjectData::Switch { dir, side, conn: conn_name }});
     }
+    Ok(())
 }
 
+fn add_crossings(vec :&mut Vec<BrObject>, crossing_conns :&mut Vec<((String,String),(String,String))>, track :&minidom::Element, track_path :&str, ns :&str) -> Result<(), String> {
+    let crossings = track.get_child("trackTopology",ns)
+        .and_then(|o| o.get_child("connections", ns))
+        .map(|c| c.children().filter(|x| x.name() == "crossing").collect::<Vec<_>>())
+        .unwrap_or_else(|| Vec::new());
+    let crossings_path = format!("{}/trackTopology/connections", track_path);
+
+    for cr in &crossings {
+        let path = element_path(&crossings_path, cr);
+        let id = required_attr(cr, &path, "id")?;
+        let name = id.to_string();
+        let pos = number_attr(cr, &path, "pos")?;
+
+        let kind = match cr.attr("type") {
+            Some("simpleCrossing") | None => CrossingKind::Diamond,
+            Some("simpleSwitchCrossing") => CrossingKind::SingleSlip,
+            Some("doubleSwitchCrossing") => CrossingKind::DoubleSlip,
+            Some(x) => return Err(format!("{}: unknown crossing type {:?}", path, x)),
+        };
+
+        let mut incoming = None;
+        let mut outgoing = None;
+        for conn in cr.children().filter(|x| x.name() == "connection") {
+            let conn_path = element_path(&path, conn);
+            let conn_name = (required_attr(conn, &conn_path, "id")?.to_string(),
+                             required_attr(conn, &conn_path, "ref")?.to_string());
+            let side = match conn.attr("course") {
+                Some("left") => Some(Side::Left),
+                Some("right") => Some(Side::Right),
+                _ => None,
+            };
+            match conn.attr("orientation") {
+                Some("incoming") => incoming = Some((conn_name, side)),
+                Some("outgoing") => outgoing = Some((conn_name, side)),
+                x => return Err(format!("{}: unknown crossing orientation {:?}", conn_path, x)),
+            }
+        }
+
+        let ((incoming, in_side), (outgoing, out_side)) = match (incoming, outgoing) {
+            (Some(i), Some(o)) => (i, o),
+            _ => return Err(format!("{}: crossing needs an incoming and an outgoing connection", path)),
+        };
+
+        let slips = match kind {
+            CrossingKind::Diamond => (None, None),
+            CrossingKind::SingleSlip => {
+                if in_side.is_some() == out_side.is_some() {
+                    return Err(format!("{}: single slip needs a course on exactly one connection", path));
+                }
+                (in_side, out_side)
+            },
+            CrossingKind::DoubleSlip => {
+                let side = in_side.or(out_side)
+                    .ok_or(format!("{}: double slip needs a course on a connection", path))?;
+                (Some(in_side.unwrap_or(side)), Some(out_side.unwrap_or(side)))
+            },
+        };
+
+        crossing_conns.push(((incoming.1.clone(), incoming.0.clone()),
+                             (outgoing.1.clone(), outgoing.0.clone())));
+        vec.push(BrObject { name, pos, data: BrObjectData::Crossing { kind, incoming, outgoing, slips }});
+    }
+    Ok(())
+}
+
+/// Directional detectors are only supported on tracks which are signalled in
+/// one direction. Routes in the other direction could begin between the
+/// detectors joined for that direction, so the detectors detect both directions there.
+pub fn check_detector_directions(m :&mut BranchingModel, diagnostics :&mut Vec<Diagnostic>) {
+    for track in &mut m.tracks {
+        let signal_dirs = track.objs.iter().filter_map(|obj| match obj.data {
+            BrObjectData::Signal { dir, .. } => Some(dir),
+            _ => None,
+        }).collect::<Vec<_>>();
+        for obj in &mut track.objs {
+            if let BrObjectData::Detector { ref mut dir } = obj.data {
+                if dir.map(|d| signal_dirs.contains(&d.opposite())).unwrap_or(false) {
+                    diagnostics.push(Diagnostic { element: obj.name.clone(),
+                        message: format!("directional detector is not supported on a track with signals in both directions, detecting both directions") });
+                    *dir = None;
+                }
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum WalkResult {
@@ -262,10 +626,22 @@ pub enum WalkResult {
     FacingSwitch(f64, BrCursor, BrCursor, BrCursor),
 }
 
+impl WalkResult {
+    /// The result of a walk continuing from another track, with the distance
+    /// `d` walked on the tracks before it added.
+    fn continued(self, d :f64) -> WalkResult {
+        match self {
+            WalkResult::Ok(c) => WalkResult::Ok(c),
+            WalkResult::End(x, c) => WalkResult::End(x + d, c),
+            WalkResult::TrailingSwitch(x, c1, c2) => WalkResult::TrailingSwitch(x + d, c1, c2),
+            WalkResult::FacingSwitch(x, c1, c2, c3) => WalkResult::FacingSwitch(x + d, c1, c2, c3),
+        }
+    }
+}
+
 pub fn walk(m :&BranchingModel, cursor :&BrCursor, dist: f64, delta :f64) -> WalkResult {
     let track = &m.tracks[cursor.track];
     let mut objs = track.objs.clone();
-    println!("WAlk from {:?}", cursor);
     match cursor.dir {
         Dir::Up => {
             objs.sort_by(|a,b| a.pos.partial_cmp(&b.pos).unwrap());
@@ -332,7 +708,8 @@ pub fn walk(m :&BranchingModel, cursor :&BrCursor, dist: f64, delta :f64) -> Wal
                                 if m.switch_conns.contains(&c) {
                                     return WalkResult::TrailingSwitch((m.tracks[cursor.track].length - cursor.offset).abs(), end, *after);
                                 } else {
-                                    return walk(m, after, dist - (m.tracks[cursor.track].length - cursor.offset).abs(), delta);
+                                    let d = (m.tracks[cursor.track].length - cursor.offset).abs();
+                                    return walk(m, after, dist - d, delta).continued(d);
                                 }
                             }
                             None => {
@@ -408,7 +785,8 @@ pub fn walk(m :&BranchingModel, cursor :&BrCursor, dist: f64, delta :f64) -> Wal
                                     // Trailing switch over track begin connection
                                     return WalkResult::TrailingSwitch((0.0 - cursor.offset).abs(), end, *after);
                                 } else {
-                                    return walk(m, after, dist - (0.0 - cursor.offset).abs(), delta);
+                                    let d = (0.0 - cursor.offset).abs();
+                                    return walk(m, after, dist - d, delta).continued(d);
                                 }
                             }
                             None =>  {
@@ -6,11 +6,28 @@ use branching::*;
 pub struct DGraphModel {
     pub nodes: Vec<DGraphNode>,
     pub edges: Vec<Edge>,
+    /// Nodes inside each crossing, which belong to the same detection section.
+    pub crossings: Vec<(String, Vec<NodeIdx>)>,
+    /// Gradient in per mille of linear edges, uphill from the first node of the edge.
+    pub gradients: HashMap<PartNodeIdx, f64>,
+    pub stops: Vec<StopLocation>,
+}
+
+/// Named stop location, such as a platform, from `pos` to `pos + length` along a track.
+#[derive(Debug,Clone)]
+pub struct StopLocation {
+    pub name: String,
+    /// Id of the operational control point (station).
+    pub ocp: Option<String>,
+    pub track: String,
+    pub pos: f64,
+    pub length: f64,
 }
 
 impl Default for DGraphModel {
     fn default() -> Self {
-        DGraphModel { nodes: Vec::new(), edges: Vec::new() }
+        DGraphModel { nodes: Vec::new(), edges: Vec::new(), crossings: Vec::new(),
+                      gradients: HashMap::new(), stops: Vec::new() }
     }
 }
 
@@ -25,10 +42,26 @@ pub enum Edge {
 
 #[derive(Debug,Clone)]
 pub struct DGraphNode {
+    /// Id of the detector at the node, if any.
     pub name: Option<String>,
     pub a: PartNode,
     pub b: PartNode,
     pub has_detector: bool,
+    /// Direction of the trains detected by a directional detector, which
+    /// leave the node through part B when running up.
+    pub detector_dir: Option<Dir>,
+    /// Location of the node on a track of the source infrastructure.
+    pub position: Option<NodePosition>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct NodePosition {
+    /// Track id.
+    pub track: String,
+    /// Position along the track in meters.
+    pub pos: f64,
+    /// Absolute position (kilometrage) in meters.
+    pub abs_pos: Option<f64>,
 }
 
 impl DGraphNode {
@@ -52,6 +85,8 @@ pub enum PartNodeObject {
     TVDEnter(String),
     TVDExit(String),
     Sight(String,f64),
+    /// Maximum velocity in m/s from this node on.
+    Speed(f64),
 }
 
 
@@ -116,6 +151,8 @@ pub fn empty_node(name :&str) -> DGraphNode {
             objs: vec![],
         },
 		has_detector: false,
+		detector_dir: None,
+		position: None,
     }
 }
 
@@ -127,14 +164,20 @@ pub fn new_node(ns :&mut Vec<DGraphNode>) -> (PartNodeIdx, PartNodeIdx) {
     PartNodeIdx::from_node(i)
 }
 
-pub fn convert(bm :BranchingModel) -> Result<DGraphModel, String> {
-    let mut model = DGraphModel {
-        nodes: Vec::new(),
-        edges: Vec::new(),
-    };
+pub fn convert(bm :BranchingModel, diagnostics :&mut Vec<Diagnostic>) -> Result<DGraphModel, String> {
+    let mut model = DGraphModel::default();
+
+    model.stops = bm.stops.iter().map(|s| StopLocation {
+        name: s.name.clone(), ocp: s.ocp.clone(), track: bm.tracks[s.track].id.clone(),
+        pos: s.pos, length: s.length,
+    }).collect();
 
     let mut named_connections = HashMap::new();
     for mut t in bm.tracks {
+        let (track_id, mileage) = (t.id.clone(), t.mileage.clone());
+        let position = move |pos :f64| Some(NodePosition { track: track_id.clone(), pos,
+                                                           abs_pos: abs_pos(&mileage, pos) });
+        let first_node = model.nodes.len();
         let (mut na, mut nb) = new_node(&mut model.nodes);
         match t.begin {
             BrTrackEnd::Stop => {},
@@ -147,10 +190,13 @@ pub fn convert(bm :BranchingModel) -> Result<DGraphModel, String> {
 
         let mut last_node = nb;
         let mut last_pos = 0.0;
+        let mut gradient = 0.0;
 
         t.objs.sort_by(|a, b| (a.pos).partial_cmp(&b.pos).unwrap());
         for obj in t.objs {
+            let obj_node = model.nodes.len();
             let (mut na, mut nb) = new_node(&mut model.nodes);
+            let mut next_gradient = gradient;
             use branching::BrObjectData::*;
 
             match obj.data {
@@ -168,8 +214,51 @@ pub fn convert(bm :BranchingModel) -> Result<DGraphModel, String> {
                     if dir == Dir::Up { nb = nb_straight; }
                     else { na = nb_straight; }
                 },
-                Detector => {
+                Crossing { kind: _, incoming: (in_id, in_ref), outgoing: (out_id, out_ref), slips: (in_side, out_side) } => {
+                    // Ends of the crossing, each seen from the inside: a and b on this
+                    // track, c and d on the incoming and outgoing crossing track.
+                    let a = nb;
+                    let (b, b_out) = new_node(&mut model.nodes);
+                    let (c_out, c) = new_node(&mut model.nodes);
+                    let (d, d_out) = new_node(&mut model.nodes);
+                    named_connections.insert(in_id, (in_ref, c_out));
+                    named_connections.insert(out_id, (out_ref, d_out));
+
+                    // Paths through the crossing, with the side of the curve at each end.
+                    let mut paths = vec![(a, b, None, None), (c, d, None, None)];
+                    if let Some(side) = out_side { paths.push((a, d, Some(side), Some(side.opposite()))); }
+                    if let Some(side) = in_side { paths.push((b, c, Some(side), Some(side.opposite()))); }
+
+                    let mut nodes = vec![a.node_idx(), b.node_idx(), c.node_idx(), d.node_idx()];
+                    let mut legs = HashMap::new();
+                    for (end1, end2, side1, side2) in paths {
+                        let (pa, pb) = new_node(&mut model.nodes);
+                        nodes.push(pa.node_idx());
+                        legs.entry(end1).or_insert(Vec::new()).push((pa, side1));
+                        legs.entry(end2).or_insert(Vec::new()).push((pb, side2));
+                    }
+
+                    for &(end, label) in &[(a, "a"), (b, "b"), (c, "c"), (d, "d")] {
+                        let legs = &legs[&end];
+                        if legs.len() == 1 {
+                            model.edges.push(Edge::Linear(end, (legs[0].0, 0.0)));
+                        } else {
+                            let (straight, curve) = if legs[0].1.is_none() { (legs[0], legs[1]) } else { (legs[1], legs[0]) };
+                            let side = curve.1.unwrap();
+                            model.edges.push(Edge::Switch(format!("{}_{}", obj.name, label), Some(side),
+                                end,
+                                if side == Side::Left { (curve.0, 0.0) } else { (straight.0, 0.0) },
+                                if side == Side::Right { (curve.0, 0.0) } else { (straight.0, 0.0) }));
+                        }
+                    }
+
+                    model.crossings.push((obj.name, nodes));
+                    nb = b_out;
+                },
+                Detector { dir } => {
                     model.nodes[na.node_idx()].has_detector = true;
+                    model.nodes[na.node_idx()].name = Some(obj.name);
+                    model.nodes[na.node_idx()].detector_dir = dir;
                 },
                 Signal { dir, .. } => {
                     if dir == Dir::Up {
@@ -178,6 +267,18 @@ pub fn convert(bm :BranchingModel) -> Result<DGraphModel, String> {
                         model.nodes[na.node_idx()].a.objs.push(PartNodeObject::Signal(obj.name));
                     }
                 },
+                SpeedChange { dir, v } => {
+                    if dir != Some(Dir::Down) {
+                        model.nodes[nb.node_idx()].b.objs.push(PartNodeObject::Speed(v));
+                    }
+                    if dir != Some(Dir::Up) {
+                        model.nodes[na.node_idx()].a.objs.push(PartNodeObject::Speed(v));
+                    }
+                },
+                GradientChange { slope } => {
+                    next_gradient = slope;
+                },
+                StopLimit => {},
                 Sight { dir, signal, distance } => {
                     if dir == Dir::Up {
                         model.nodes[nb.node_idx()].b.objs.push(PartNodeObject::Sight(signal, distance));
@@ -187,14 +288,23 @@ pub fn convert(bm :BranchingModel) -> Result<DGraphModel, String> {
                 },
             }
 
+            // All nodes created for the object are at its position.
+            for n in &mut model.nodes[obj_node..] { n.position = position(obj.pos); }
+
             model.edges.push(Edge::Linear(last_node, (na, obj.pos - last_pos)));
+            if gradient != 0.0 { model.gradients.insert(last_node, gradient); }
+            gradient = next_gradient;
             last_pos = obj.pos;
             last_node = nb;
         }
 
+        model.nodes[first_node].position = position(0.0);
+
         // Last node
         let (mut na, mut nb) = new_node(&mut model.nodes);
+        model.nodes[na.node_idx()].position = position(t.length);
         model.edges.push(Edge::Linear(last_node, (na, t.length - last_pos)));
+        if gradient != 0.0 { model.gradients.insert(last_node, gradient); }
         match t.end {
             BrTrackEnd::Stop => {},
             BrTrackEnd::Boundary(name) => { 
@@ -210,10 +320,14 @@ pub fn convert(bm :BranchingModel) -> Result<DGraphModel, String> {
         let id1 = named_connections.keys().next().unwrap().clone();
         let (ref1, node_a) = named_connections.remove(&id1).unwrap();
         if let Some((ref2, node_b)) = named_connections.remove(&ref1) {
-            if ref2 != id1 { panic!("Inconsistent connections."); }
+            if ref2 != id1 {
+                return Err(format!("Inconsistent connections: {:?} refers to {:?}, which refers to {:?}",
+                                   id1, ref1, ref2));
+            }
             model.edges.push(Edge::Linear(node_a, (node_b, 0.0)));
         } else {
-            println!("WARNING: Inconsitent connection {:?} {:?}", id1, ref1);
+            diagnostics.push(Diagnostic { element: id1,
+                                          message: format!("connection refers to {:?}, which is missing", ref1) });
         }
     }
 
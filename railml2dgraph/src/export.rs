@@ -0,0 +1,412 @@
+use std::collections::{HashMap, HashSet};
+use base::*;
+use dgraph::*;
+use RailMLVersion;
+
+// Export of a D-graph model to railML. The D-graph is split into tracks at
+// switches, model boundaries and stops, following the linear edges from node
+// to node, so that each switch is at the end of the track leading to it and at
+// the beginning (or end) of the tracks of its two legs. Each node with objects
+// becomes a position on its track, with the signals, sight distances and
+// detectors of its two parts. Tracks are named after the track of their nodes'
+// positions. Detection section names are not exported, since sections are
+// derived from the detectors when reading railML.
+
+#[derive(Clone, Debug)]
+enum TrackEnd {
+    Stop,
+    Boundary(String),
+    /// Switch edge, and the switch leg at this end (or None for the trunk).
+    Switch(usize, Option<Side>),
+}
+
+struct Track {
+    begin: TrackEnd,
+    end: TrackEnd,
+    length: f64,
+    /// Nodes on the track with their positions, and whether running up
+    /// along the track leaves the node through part B.
+    nodes: Vec<(usize, f64, bool)>,
+}
+
+enum Object {
+    Signal { name: String, up: bool, sight: Option<f64> },
+    Detector { up: bool, down: bool },
+}
+
+fn part(model :&DGraphModel, p :PartNodeIdx) -> &PartNode {
+    model.nodes[p.node_idx()].get_part(p.node_part())
+}
+
+fn other_part(p :PartNodeIdx) -> PartNodeIdx {
+    match p.node_part() {
+        NodePart::A => PartNodeIdx::from_node_part(p.node_idx(), NodePart::B),
+        NodePart::B => PartNodeIdx::from_node_part(p.node_idx(), NodePart::A),
+    }
+}
+
+fn switch_side(model :&DGraphModel, sw :usize) -> Side {
+    match model.edges[sw] {
+        Edge::Switch(_, side, ..) => side.unwrap_or(Side::Right),
+        _ => panic!("Not a switch"),
+    }
+}
+
+fn get_tracks(model :&DGraphModel) -> Result<Vec<Track>, String> {
+    let mut linear = HashMap::new();
+    let mut ends = HashMap::new();
+    let mut leg_length = HashMap::new();
+    for (i, edge) in model.edges.iter().enumerate() {
+        match *edge {
+            Edge::Linear(a, (b, d)) => {
+                linear.insert(a, (b, d));
+                linear.insert(b, (a, d));
+            },
+            Edge::Switch(_, _, trunk, (left, dl), (right, dr)) => {
+                ends.insert(trunk, TrackEnd::Switch(i, None));
+                ends.insert(left, TrackEnd::Switch(i, Some(Side::Left)));
+                ends.insert(right, TrackEnd::Switch(i, Some(Side::Right)));
+                leg_length.insert(left, dl);
+                leg_length.insert(right, dr);
+            },
+            Edge::Boundary(p) => {
+                ends.insert(p, TrackEnd::Boundary(part(model, p).name.clone()));
+            },
+        }
+    }
+
+    let mut visited = vec![false; model.nodes.len()];
+    let mut tracks = Vec::new();
+    for n in 0..model.nodes.len() {
+        for &p in &[NodePart::A, NodePart::B] {
+            let start = PartNodeIdx::from_node_part(n, p);
+            if visited[n] || linear.contains_key(&start) { continue; }
+
+            let mut pos = leg_length.get(&start).cloned().unwrap_or(0.0);
+            let mut nodes = Vec::new();
+            let mut entry = start;
+            loop {
+                visited[entry.node_idx()] = true;
+                let exit = other_part(entry);
+                let a_to_b = match exit.node_part() { NodePart::A => false, NodePart::B => true };
+                nodes.push((entry.node_idx(), pos, a_to_b));
+                match linear.get(&exit) {
+                    Some(&(next, d)) => { pos += d; entry = next; },
+                    None => {
+                        pos += leg_length.get(&exit).cloned().unwrap_or(0.0);
+                        tracks.push(Track {
+                            begin: ends.get(&start).cloned().unwrap_or(TrackEnd::Stop),
+                            end: ends.get(&exit).cloned().unwrap_or(TrackEnd::Stop),
+                            length: pos,
+                            nodes,
+                        });
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    if let Some(n) = visited.iter().position(|v| !v) {
+        return Err(format!("Closed loop of track through node {:?} has no switch or end, and cannot be exported",
+                           model.nodes[n].a.name));
+    }
+    Ok(tracks)
+}
+
+// Longest sight distance of each signal.
+fn get_sight(model :&DGraphModel) -> HashMap<String, f64> {
+    let mut sight = HashMap::new();
+    for node in &model.nodes {
+        for obj in node.a.objs.iter().chain(node.b.objs.iter()) {
+            if let PartNodeObject::Sight(ref signal, d) = *obj {
+                let s = sight.entry(signal.clone()).or_insert(d);
+                if d > *s { *s = d; }
+            }
+        }
+    }
+    sight
+}
+
+fn get_objects(model :&DGraphModel, track :&Track, sight :&HashMap<String, f64>) -> Vec<(f64, Object)> {
+    let is_limit = |objs :&[PartNodeObject]| objs.iter().any(|o| match *o {
+        PartNodeObject::TVDEnter(_) | PartNodeObject::TVDExit(_) => true,
+        _ => false,
+    });
+
+    let mut objs = Vec::new();
+    for &(n, pos, a_to_b) in &track.nodes {
+        let node = &model.nodes[n];
+        let (backward, forward) = if a_to_b { (&node.a, &node.b) } else { (&node.b, &node.a) };
+        for &(p, up) in &[(forward, true), (backward, false)] {
+            for obj in &p.objs {
+                if let PartNodeObject::Signal(ref name) = *obj {
+                    objs.push((pos, Object::Signal { name: name.clone(), up, sight: sight.get(name).cloned() }));
+                }
+            }
+        }
+        let (up, down) = (is_limit(&forward.objs), is_limit(&backward.objs));
+        if up || down {
+            objs.push((pos, Object::Detector { up, down }));
+        }
+    }
+    objs
+}
+
+fn escape(s :&str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Write the model as a railML document of the given version (railML 2.4 or 3.1).
+pub fn railml_string(model :&DGraphModel, version :RailMLVersion) -> Result<String, String> {
+    let tracks = get_tracks(model)?;
+    Ok(match version {
+        RailMLVersion::V2 => railml2(model, &tracks),
+        RailMLVersion::V3 => railml3(model, &tracks),
+    })
+}
+
+// Track ends at each switch: the trunk, the straight leg and the branching leg.
+fn switch_ends(model :&DGraphModel, tracks :&[Track]) -> Vec<(usize, [(usize, bool); 3])> {
+    let mut ends = HashMap::new();
+    for (t, track) in tracks.iter().enumerate() {
+        for &(end, at_end) in &[(&track.begin, false), (&track.end, true)] {
+            if let TrackEnd::Switch(sw, leg) = *end {
+                let idx = match leg {
+                    None => 0,
+                    Some(side) if side != switch_side(model, sw) => 1,
+                    Some(_) => 2,
+                };
+                ends.entry(sw).or_insert([(0, false); 3])[idx] = (t, at_end);
+            }
+        }
+    }
+    let mut ends = ends.into_iter().collect::<Vec<_>>();
+    ends.sort_by_key(|&(sw, _)| sw);
+    ends
+}
+
+// Track ids, after the track (or net element) of the first node with a position
+// on each track, so that the ids of a converted railML file are kept. Tracks
+// split at switches get a number after the id.
+fn track_ids(model :&DGraphModel, tracks :&[Track], prefix :&str) -> Vec<String> {
+    let mut used = HashSet::new();
+    tracks.iter().enumerate().map(|(t, track)| {
+        let base = track.nodes.iter()
+            .filter_map(|&(n, _, _)| model.nodes[n].position.as_ref())
+            .map(|p| p.track.clone()).next()
+            .unwrap_or(format!("{}{}", prefix, t + 1));
+        let mut id = base.clone();
+        let mut k = 1;
+        while !used.insert(id.clone()) {
+            k += 1;
+            id = format!("{}_{}", base, k);
+        }
+        id
+    }).collect()
+}
+
+fn switch_name(model :&DGraphModel, sw :usize) -> &str {
+    match model.edges[sw] {
+        Edge::Switch(ref name, ..) => name,
+        _ => panic!("Not a switch"),
+    }
+}
+
+fn railml2(model :&DGraphModel, tracks :&[Track]) -> String {
+    // Connections (id, ref) at track ends and on switches. The trunk of a switch
+    // is connected to the straight leg, and the switch to the branching leg.
+    let mut conns = HashMap::new();
+    let mut switch_conns = HashMap::new();
+    let mut next_conn = 0;
+    let mut conn_pair = || {
+        next_conn += 2;
+        (format!("conn{}", next_conn - 1), format!("conn{}", next_conn))
+    };
+    for &(sw, ref ends) in &switch_ends(model, tracks) {
+        let (a, b) = conn_pair();
+        conns.insert(ends[0], (a.clone(), b.clone()));
+        conns.insert(ends[1], (b, a));
+        let (a, b) = conn_pair();
+        switch_conns.insert(sw, (a.clone(), b.clone()));
+        conns.insert(ends[2], (b, a));
+    }
+
+    let mut buf = String::new();
+    buf.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    buf.push_str("<railml xmlns=\"https://www.railml.org/schemas/2018\" version=\"2.4\">\n");
+    buf.push_str("  <infrastructure id=\"inf\">\n");
+    buf.push_str("    <tracks>\n");
+    let sight = get_sight(model);
+    let ids = track_ids(model, tracks, "track");
+    let mut num_detectors = 0;
+    for (t, track) in tracks.iter().enumerate() {
+        let id = escape(&ids[t]);
+        buf.push_str(&format!("      <track id=\"{}\" name=\"{}\">\n", id, id));
+        buf.push_str("        <trackTopology>\n");
+        for &(end, at_end, element) in &[(&track.begin, false, "trackBegin"), (&track.end, true, "trackEnd")] {
+            buf.push_str(&format!("          <{} id=\"{}_{}\" pos=\"{}\">\n", element, id,
+                                  if at_end { "end" } else { "begin" }, if at_end { track.length } else { 0.0 }));
+            match (end, conns.get(&(t, at_end))) {
+                (_, Some(&(ref c, ref r))) =>
+                    buf.push_str(&format!("            <connection id=\"{}\" ref=\"{}\"/>\n", c, r)),
+                (&TrackEnd::Boundary(ref name), _) =>
+                    buf.push_str(&format!("            <openEnd id=\"{}\"/>\n", escape(name))),
+                _ => buf.push_str("            <bufferStop/>\n"),
+            }
+            buf.push_str(&format!("          </{}>\n", element));
+        }
+        let switches = [(&track.begin, false), (&track.end, true)].iter()
+            .filter_map(|&(end, at_end)| match *end {
+                TrackEnd::Switch(sw, None) => Some((sw, at_end)),
+                _ => None,
+            }).collect::<Vec<_>>();
+        if !switches.is_empty() {
+            buf.push_str("          <connections>\n");
+            for (sw, at_end) in switches {
+                let (ref c, ref r) = switch_conns[&sw];
+                buf.push_str(&format!("            <switch id=\"{}\" pos=\"{}\">\n",
+                                      escape(switch_name(model, sw)), if at_end { track.length } else { 0.0 }));
+                buf.push_str(&format!("              <connection id=\"{}\" ref=\"{}\" orientation=\"{}\" course=\"{}\"/>\n",
+                                      c, r, if at_end { "outgoing" } else { "incoming" },
+                                      switch_side(model, sw).as_str()));
+                buf.push_str("            </switch>\n");
+            }
+            buf.push_str("          </connections>\n");
+        }
+        buf.push_str("        </trackTopology>\n");
+
+        let objs = get_objects(model, track, &sight);
+        if !objs.is_empty() {
+            buf.push_str("        <ocsElements>\n");
+            let mut signals = String::new();
+            let mut detectors = String::new();
+            for (pos, obj) in objs {
+                match obj {
+                    Object::Signal { name, up, sight } => {
+                        let sight = sight.map(|s| format!(" sight=\"{}\"", s)).unwrap_or_default();
+                        signals.push_str(&format!("            <signal id=\"{}\" name=\"{}\" pos=\"{}\" dir=\"{}\" type=\"main\"{}/>\n",
+                                                  escape(&name), escape(&name), pos, if up { "up" } else { "down" }, sight));
+                    },
+                    Object::Detector { up, down } => {
+                        num_detectors += 1;
+                        let dir = if up && down { "both" } else if up { "up" } else { "down" };
+                        detectors.push_str(&format!("            <trainDetector id=\"det{}\" pos=\"{}\" dir=\"{}\"/>\n",
+                                                    num_detectors, pos, dir));
+                    },
+                }
+            }
+            if !signals.is_empty() {
+                buf.push_str(&format!("          <signals>\n{}          </signals>\n", signals));
+            }
+            if !detectors.is_empty() {
+                buf.push_str(&format!("          <trainDetectionElements>\n{}          </trainDetectionElements>\n", detectors));
+            }
+            buf.push_str("        </ocsElements>\n");
+        }
+        buf.push_str("      </track>\n");
+    }
+    buf.push_str("    </tracks>\n");
+    buf.push_str("  </infrastructure>\n");
+    buf.push_str("</railml>\n");
+    buf
+}
+
+fn railml3(model :&DGraphModel, tracks :&[Track]) -> String {
+    let ids = track_ids(model, tracks, "ne");
+    let element = |t :usize| escape(&ids[t]);
+    let position = |at_end :bool| if at_end { "1" } else { "0" };
+    let spot = |t :usize, pos :f64, dir :Option<bool>| {
+        let coord = if tracks[t].length > 0.0 { pos / tracks[t].length } else { 0.0 };
+        let dir = match dir {
+            Some(up) => format!(" applicationDirection=\"{}\"", if up { "normal" } else { "reverse" }),
+            None => String::new(),
+        };
+        format!("<spotLocation netElementRef=\"{}\" pos=\"{}\" intrinsicCoord=\"{}\"{}/>", element(t), pos, coord, dir)
+    };
+
+    let mut buf = String::new();
+    buf.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    buf.push_str("<railML xmlns=\"https://www.railml.org/schemas/3.1\" version=\"3.1\">\n");
+    buf.push_str("  <infrastructure id=\"inf\">\n");
+    buf.push_str("    <topology>\n");
+    buf.push_str("      <netElements>\n");
+    for (t, track) in tracks.iter().enumerate() {
+        buf.push_str(&format!("        <netElement id=\"{}\" length=\"{}\"/>\n", element(t), track.length));
+    }
+    buf.push_str("      </netElements>\n");
+
+    // The trunk of each switch is related to both legs.
+    buf.push_str("      <netRelations>\n");
+    let switches = switch_ends(model, tracks);
+    for &(sw, ref ends) in &switches {
+        for leg in 1..3 {
+            let ((a, a_end), (b, b_end)) = (ends[0], ends[leg]);
+            buf.push_str(&format!("        <netRelation id=\"nr{}_{}\" positionOnA=\"{}\" positionOnB=\"{}\" navigability=\"Both\">\n",
+                                  sw, leg, position(a_end), position(b_end)));
+            buf.push_str(&format!("          <elementA ref=\"{}\"/>\n          <elementB ref=\"{}\"/>\n", element(a), element(b)));
+            buf.push_str("        </netRelation>\n");
+        }
+    }
+    buf.push_str("      </netRelations>\n");
+    buf.push_str("    </topology>\n");
+
+    let sight = get_sight(model);
+    let mut borders = String::new();
+    let mut signals = String::new();
+    let mut detectors = String::new();
+    let mut num_detectors = 0;
+    for (t, track) in tracks.iter().enumerate() {
+        for &(end, at_end) in &[(&track.begin, false), (&track.end, true)] {
+            if let TrackEnd::Boundary(ref name) = *end {
+                borders.push_str(&format!("        <border id=\"{}\" isOpenEnd=\"true\">\n          {}\n        </border>\n",
+                                          escape(name), spot(t, if at_end { track.length } else { 0.0 }, None)));
+            }
+        }
+        for (pos, obj) in get_objects(model, track, &sight) {
+            match obj {
+                Object::Signal { name, up, sight } => {
+                    let sight = sight.map(|s| format!(" sight=\"{}\"", s)).unwrap_or_default();
+                    signals.push_str(&format!("        <signalIS id=\"{}\"{}>\n          {}\n          <isTrainMovementSignal type=\"main\"/>\n        </signalIS>\n",
+                                              escape(&name), sight, spot(t, pos, Some(up))));
+                },
+                Object::Detector { up, down } => {
+                    num_detectors += 1;
+                    let dir = if up && down { None } else { Some(up) };
+                    detectors.push_str(&format!("        <trainDetectionElement id=\"det{}\" type=\"axleCounter\">\n          {}\n        </trainDetectionElement>\n",
+                                                num_detectors, spot(t, pos, dir)));
+                },
+            }
+        }
+    }
+
+    buf.push_str("    <functionalInfrastructure>\n");
+    if !borders.is_empty() {
+        buf.push_str(&format!("      <borders>\n{}      </borders>\n", borders));
+    }
+    if !signals.is_empty() {
+        buf.push_str(&format!("      <signalsIS>\n{}      </signalsIS>\n", signals));
+    }
+    if !switches.is_empty() {
+        buf.push_str("      <switchesIS>\n");
+        for &(sw, ref ends) in &switches {
+            let side = switch_side(model, sw);
+            let (trunk, at_end) = ends[0];
+            buf.push_str(&format!("        <switchIS id=\"{}\" branchCourse=\"{}\" continueCourse=\"{}\">\n",
+                                  escape(switch_name(model, sw)), side.as_str(), side.opposite().as_str()));
+            buf.push_str(&format!("          {}\n", spot(trunk, if at_end { tracks[trunk].length } else { 0.0 }, None)));
+            let (left, right) = if side == Side::Left { (2, 1) } else { (1, 2) };
+            buf.push_str(&format!("          <leftBranch netRelationRef=\"nr{}_{}\"/>\n", sw, left));
+            buf.push_str(&format!("          <rightBranch netRelationRef=\"nr{}_{}\"/>\n", sw, right));
+            buf.push_str("        </switchIS>\n");
+        }
+        buf.push_str("      </switchesIS>\n");
+    }
+    if !detectors.is_empty() {
+        buf.push_str(&format!("      <trainDetectionElements>\n{}      </trainDetectionElements>\n", detectors));
+    }
+    buf.push_str("    </functionalInfrastructure>\n");
+    buf.push_str("  </infrastructure>\n");
+    buf.push_str("</railML>\n");
+    buf
+}
@@ -0,0 +1,372 @@
+use minidom;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use base::*;
+use branching::infrastructure_element;
+use dgraph::*;
+use routes::*;
+use {railml_version, RailMLVersion};
+
+// Routes from the interlocking route table of a railML file. The route table
+// gives the entry and exit signals and the switch positions of each route, and
+// each route is built from the path through the converted model from its entry
+// to its exit which takes these switch positions. Sections, length and releases
+// are taken from the path. Detection sections in the converted model come from
+// the detectors, so railML 3.x sections are matched to them by the detectors at
+// their borders.
+
+/// A route from the interlocking route table.
+#[derive(Debug, Clone)]
+pub struct InterlockingRoute {
+    pub name: String,
+    /// Entry signal, or open end for routes from the model boundary.
+    pub entry: String,
+    /// Exit signal, or open end for routes to the model boundary.
+    pub exit: String,
+    pub switches: Vec<(String, Side)>,
+    /// Sections released together, in the order of the route (railML 3.x).
+    pub release_groups: Vec<Vec<InterlockingSection>>,
+    /// Overlaps past the exit signal (railML 3.x).
+    pub overlaps: Vec<InterlockingOverlap>,
+}
+
+/// A detection section of the interlocking, with the detectors at its borders.
+#[derive(Debug, Clone)]
+pub struct InterlockingSection {
+    pub name: String,
+    pub detectors: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterlockingOverlap {
+    pub sections: Vec<InterlockingSection>,
+    pub switches: Vec<(String, Side)>,
+    /// Release timer in seconds.
+    pub timeout: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RouteDifference {
+    /// Interlocking route which has no path in the infrastructure.
+    NoPath(String),
+    /// Interlocking route which matches several paths. The shortest is used.
+    Ambiguous(String, usize),
+    /// Route derived between two signals which is not in the interlocking.
+    NotInInterlocking(String, String, Vec<(String, Side)>),
+    /// Interlocking section which does not match a detection section of the
+    /// converted model, in the given route. The route's release groups or
+    /// overlap with the section are not used.
+    UnknownSection(String, String),
+    /// Interlocking route whose release groups do not contain exactly the
+    /// sections of its path. The releases of the path are used.
+    ReleaseGroups(String),
+}
+
+impl fmt::Display for RouteDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteDifference::NoPath(ref r) =>
+                write!(f, "interlocking route {} has no path in the infrastructure", r),
+            RouteDifference::Ambiguous(ref r, n) =>
+                write!(f, "interlocking route {} matches {} paths in the infrastructure", r, n),
+            RouteDifference::NotInInterlocking(ref entry, ref exit, ref switches) =>
+                write!(f, "derived route from {} to {} [{}] is not in the interlocking", entry, exit,
+                       switches.iter().map(|&(ref sw, pos)| format!("{} {}", sw, pos.as_str()))
+                           .collect::<Vec<_>>().join(", ")),
+            RouteDifference::UnknownSection(ref r, ref s) =>
+                write!(f, "section {} of interlocking route {} has no detection section in the infrastructure", s, r),
+            RouteDifference::ReleaseGroups(ref r) =>
+                write!(f, "release groups of interlocking route {} do not match the sections of its path", r),
+        }
+    }
+}
+
+/// Read the route table: `routes` in the infrastructure (railML 2.4), or
+/// `routes` in the `assetsForIL` of the interlocking (railML 3.x).
+/// Returns an empty list if the file has no routes.
+pub fn get_interlocking_routes(doc :&minidom::Element, ns :&str) -> Result<Vec<InterlockingRoute>, String> {
+    let mut routes = Vec::new();
+    match railml_version(ns) {
+        RailMLVersion::V2 => {
+            if let Some(r) = infrastructure_element(doc)?.get_child("routes", ns) {
+                for route in r.children().filter(|x| x.name() == "route") {
+                    routes.push(get_route(route, ns, &HashMap::new())?);
+                }
+            }
+        },
+        RailMLVersion::V3 => {
+            let assets = doc.children()
+                .filter(|x| x.name() == "interlocking")
+                .flat_map(|x| x.children().filter(|x| x.name() == "assetsForIL"));
+            for a in assets {
+                // Interlocking elements (such as signalIL) refer to the
+                // infrastructure elements (such as signalIS) used in the model.
+                let mut refs = HashMap::new();
+                for e in a.children().flat_map(|x| x.children()) {
+                    if let (Some(id), Some(r)) = (e.attr("id"), e.get_child("refersTo", ns).and_then(|x| x.attr("ref"))) {
+                        refs.insert(id.to_string(), r.to_string());
+                    }
+                }
+                let items = |group :&str, item :&str| {
+                    a.get_child(group, ns)
+                        .map(|g| g.children().filter(|x| x.name() == item).collect::<Vec<_>>())
+                        .unwrap_or_else(|| Vec::new())
+                };
+                let refs_in = |e :&minidom::Element, name :&str| {
+                    e.children().filter(|x| x.name() == name)
+                        .filter_map(|x| x.attr("ref"))
+                        .map(|r| refs.get(r).cloned().unwrap_or_else(|| r.to_string()))
+                        .collect::<Vec<_>>()
+                };
+
+                let mut sections = HashMap::new();
+                for tvd in items("tvdSections", "tvdSection") {
+                    let id = tvd.attr("id").ok_or(format!("No id for tvdSection"))?;
+                    sections.insert(id.to_string(), InterlockingSection {
+                        name: id.to_string(),
+                        detectors: refs_in(tvd, "hasDemarcatingTraindetector").into_iter().collect(),
+                    });
+                }
+                let section_list = |e :&minidom::Element, context :&str| {
+                    refs_in(e, "hasTvdSection").into_iter().map(|r| sections.get(&r).cloned()
+                        .ok_or(format!("Unknown tvdSection {:?} in {}", r, context)))
+                        .collect::<Result<Vec<_>, String>>()
+                };
+
+                let mut release_groups = HashMap::new();
+                for g in items("routeReleaseGroupsAhead", "routeReleaseGroupAhead") {
+                    let id = g.attr("id").ok_or(format!("No id for routeReleaseGroupAhead"))?;
+                    release_groups.insert(id.to_string(), section_list(g, &format!("release group {:?}", id))?);
+                }
+
+                let mut overlaps :HashMap<String,Vec<InterlockingOverlap>> = HashMap::new();
+                for o in items("overlaps", "overlap") {
+                    let id = o.attr("id").ok_or(format!("No id for overlap"))?;
+                    let overlap = InterlockingOverlap {
+                        sections: section_list(o, &format!("overlap {:?}", id))?,
+                        switches: get_switch_positions(o, ns, &refs, "requiresSwitchInPosition", id)?,
+                        timeout: match o.attr("overlapReleaseTimer") {
+                            Some(t) => Some(duration(t)
+                                .ok_or(format!("Invalid overlapReleaseTimer {:?} on overlap {:?}", t, id))?),
+                            None => None,
+                        },
+                    };
+                    for route in refs_in(o, "activeForApproachRoute") {
+                        overlaps.entry(route).or_insert(Vec::new()).push(overlap.clone());
+                    }
+                }
+
+                if let Some(r) = a.get_child("routes", ns) {
+                    for route in r.children().filter(|x| x.name() == "route") {
+                        let mut il = get_route(route, ns, &refs)?;
+                        for g in refs_in(route, "hasReleaseGroup") {
+                            il.release_groups.push(release_groups.get(&g).cloned()
+                                .ok_or(format!("Unknown release group {:?} in route {:?}", g, il.name))?);
+                        }
+                        il.overlaps = overlaps.remove(&il.name).unwrap_or_else(|| Vec::new());
+                        routes.push(il);
+                    }
+                }
+            }
+        },
+    }
+    Ok(routes)
+}
+
+// Duration in seconds, from an XML schema duration with hours, minutes and seconds
+// (such as `PT1M30S`), or from a number of seconds.
+fn duration(s :&str) -> Option<f64> {
+    if let Ok(x) = s.parse::<f64>() { return Some(x); }
+    let mut rest = if s.starts_with("PT") { &s[2..] } else { return None };
+    let mut seconds = 0.0;
+    for &(unit, factor) in &[('H', 3600.0), ('M', 60.0), ('S', 1.0)] {
+        if let Some(i) = rest.find(unit) {
+            seconds += rest[..i].parse::<f64>().ok()? * factor;
+            rest = &rest[i+1..];
+        }
+    }
+    if rest.is_empty() { Some(seconds) } else { None }
+}
+
+fn get_switch_positions(e :&minidom::Element, ns :&str, refs :&HashMap<String,String>, element :&str, name :&str)
+    -> Result<Vec<(String, Side)>, String> {
+    let mut switches = Vec::new();
+    for sw in e.children().filter(|x| x.name() == element) {
+        let switch = reference(sw, ns, refs, "switchRef")
+            .ok_or(format!("No switch reference in {:?}", name))?;
+        let pos = match sw.attr("switchPosition").or_else(|| sw.attr("inPosition")) {
+            Some("left") => Side::Left,
+            Some("right") => Side::Right,
+            x => return Err(format!("Switch position {:?} of {:?} in {:?}", x, switch, name)),
+        };
+        switches.push((switch, pos));
+    }
+    Ok(switches)
+}
+
+fn reference(e :&minidom::Element, ns :&str, refs :&HashMap<String,String>, attr :&str) -> Option<String> {
+    e.attr(attr).or_else(|| e.get_child("refersTo", ns).and_then(|x| x.attr("ref")))
+        .map(|r| refs.get(r).cloned().unwrap_or_else(|| r.to_string()))
+}
+
+fn get_route(route :&minidom::Element, ns :&str, refs :&HashMap<String,String>) -> Result<InterlockingRoute, String> {
+    let name = route.attr("id").ok_or(format!("No id for route"))?;
+    let end = |element :&str| {
+        route.get_child(element, ns).and_then(|x| reference(x, ns, refs, "ref"))
+            .ok_or(format!("No {} on route {:?}", element, name))
+    };
+
+    let mut switches = get_switch_positions(route, ns, refs, "switchAndPosition", name)?;
+    switches.extend(get_switch_positions(route, ns, refs, "facingSwitchInPosition", name)?);
+
+    Ok(InterlockingRoute {
+        name: name.to_string(),
+        entry: end("routeEntry")?,
+        exit: end("routeExit")?,
+        switches,
+        release_groups: Vec::new(),
+        overlaps: Vec::new(),
+    })
+}
+
+fn boundary_name(model :&DGraphModel, b :&RouteBoundary) -> String {
+    match *b {
+        RouteBoundary::Signal(ref s) => s.clone(),
+        RouteBoundary::ModelBoundary(n) => model.nodes[n.node_idx()].get_part(n.node_part()).name.clone(),
+    }
+}
+
+// Detection sections of the model, by the detectors at their borders.
+fn detector_sections(model :&DGraphModel) -> HashMap<BTreeSet<String>, String> {
+    let mut detectors :HashMap<String, BTreeSet<String>> = HashMap::new();
+    for n in &model.nodes {
+        if let Some(ref name) = n.name {
+            for obj in n.a.objs.iter().chain(n.b.objs.iter()) {
+                match *obj {
+                    PartNodeObject::TVDEnter(ref s) | PartNodeObject::TVDExit(ref s) => {
+                        detectors.entry(s.clone()).or_insert(BTreeSet::new()).insert(name.clone());
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+    detectors.into_iter().map(|(s, d)| (d, s)).collect()
+}
+
+// Detection sections of the model for the interlocking sections, or else the
+// name of an interlocking section which has no detection section in the model.
+fn model_sections(sections :&HashMap<BTreeSet<String>, String>, list :&[InterlockingSection])
+    -> Result<Vec<String>, String> {
+    list.iter().map(|s| sections.get(&s.detectors).cloned().ok_or(s.name.clone())).collect()
+}
+
+// Entry of the route from the model boundary or signal with the given name. Signals
+// which are not reached from the model boundaries are entered without a section.
+fn find_entry(model :&DGraphModel, entries :&[RouteEntry], name :&str) -> Option<RouteEntry> {
+    if let Some(e) = entries.iter().find(|e| boundary_name(model, &e.entry) == name) {
+        return Some(e.clone());
+    }
+    for (i, n) in model.nodes.iter().enumerate() {
+        for &(part, p) in &[(&n.a, NodePart::A), (&n.b, NodePart::B)] {
+            if part.objs.iter().any(|o| match *o { PartNodeObject::Signal(ref x) => x == name, _ => false }) {
+                return Some(RouteEntry {
+                    node: PartNodeIdx::from_node_part(i, p),
+                    entry: RouteBoundary::Signal(name.to_string()),
+                    section: None,
+                });
+            }
+        }
+    }
+    None
+}
+
+// Release groups of the interlocking route as releases of the route, combining the
+// releases of the route's sections in each group.
+fn group_releases(route :&Route, groups :&[Vec<String>]) -> Option<Vec<(String, f64, Vec<String>)>> {
+    let grouped = groups.iter().flat_map(|g| g.iter()).collect::<Vec<_>>();
+    if grouped.len() != route.sections.len() || !route.sections.iter().all(|s| grouped.contains(&s)) {
+        return None;
+    }
+    let mut groups = groups.iter()
+        .map(|g| route.releases.iter().enumerate().filter(|&(_, r)| g.contains(&r.0)).collect::<Vec<_>>())
+        .filter(|g| g.len() > 0)
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|g| g.last().map(|&(i, _)| i));
+    Some(groups.into_iter().map(|g| {
+        let trigger = (g.last().unwrap().1).0.clone();
+        let length = g.iter().map(|&(_, r)| r.1).sum();
+        let mut resources = Vec::new();
+        for &(_, r) in &g {
+            for x in &r.2 {
+                if !resources.contains(x) { resources.push(x.clone()); }
+            }
+        }
+        (trigger, length, resources)
+    }).collect())
+}
+
+/// Build the interlocking routes from the paths through the model from each route's
+/// entry to its exit which take the route's switch positions. Release groups and
+/// overlaps of the interlocking replace those of the path when their sections match
+/// the detection sections of the model. Derived routes to and from the model boundary
+/// which are not in the interlocking are kept.
+pub fn check_routes(model :&DGraphModel, interlocking :&[InterlockingRoute], entries :&[RouteEntry],
+                    derived :Vec<Route>) -> (Vec<Route>, Vec<RouteDifference>) {
+    let mut routes = Vec::new();
+    let mut differences = Vec::new();
+    let sections = detector_sections(model);
+
+    for il in interlocking {
+        let mut candidates = find_entry(model, entries, &il.entry)
+            .map(|e| entry_routes(model, &e, &il.switches))
+            .unwrap_or_else(|| Vec::new());
+        candidates.retain(|r| boundary_name(model, &r.exit) == il.exit);
+        candidates.retain(|r| il.switches.iter().all(|x| r.switches.contains(x)));
+        candidates.sort_by(|a, b| a.length.partial_cmp(&b.length).unwrap());
+        if candidates.len() > 1 {
+            differences.push(RouteDifference::Ambiguous(il.name.clone(), candidates.len()));
+        }
+        let mut route = match candidates.into_iter().next() {
+            Some(r) => r,
+            None => { differences.push(RouteDifference::NoPath(il.name.clone())); continue; },
+        };
+        route.name = Some(il.name.clone());
+
+        let groups = il.release_groups.iter().map(|g| model_sections(&sections, g)).collect::<Result<Vec<_>, _>>();
+        match groups {
+            Ok(ref groups) if groups.len() == 0 => {},
+            Ok(groups) => match group_releases(&route, &groups) {
+                Some(releases) => route.releases = releases,
+                None => differences.push(RouteDifference::ReleaseGroups(il.name.clone())),
+            },
+            Err(s) => differences.push(RouteDifference::UnknownSection(il.name.clone(), s)),
+        }
+        if let RouteBoundary::Signal(_) = route.exit {
+            for o in &il.overlaps {
+                match model_sections(&sections, &o.sections) {
+                    Ok(s) => route.overlaps.push(Overlap { sections: s, switches: o.switches.clone(), timeout: o.timeout }),
+                    Err(s) => differences.push(RouteDifference::UnknownSection(il.name.clone(), s)),
+                }
+            }
+        }
+
+        routes.push(route);
+    }
+
+    for route in derived {
+        let in_interlocking = routes.iter().any(|r :&Route|
+            boundary_name(model, &r.entry) == boundary_name(model, &route.entry) &&
+            boundary_name(model, &r.exit) == boundary_name(model, &route.exit) &&
+            r.switches.len() == route.switches.len() && route.switches.iter().all(|x| r.switches.contains(x)));
+        if in_interlocking { continue; }
+        match (&route.entry, &route.exit) {
+            (&RouteBoundary::Signal(ref entry), &RouteBoundary::Signal(ref exit)) => {
+                differences.push(RouteDifference::NotInInterlocking(entry.clone(), exit.clone(), route.switches.clone()));
+            },
+            _ => routes.push(route),
+        }
+    }
+
+    (routes, differences)
+}
@@ -3,10 +3,13 @@ extern crate petgraph;
 
 pub mod base;
 pub mod branching;
+pub mod railml3;
 pub mod sight;
 pub mod dgraph;
 pub mod sections;
 pub mod routes;
+pub mod interlocking;
+pub mod export;
 pub mod output;
 
 use std::fs::File;
@@ -30,19 +33,38 @@ pub fn get_xml_string(contents :&str, verbose: bool) -> Result<(minidom::Element
         .map_err(|e| format!("Could not parse XML: {:?}",e))?;
     let ns = doc.ns().ok_or("Missing XML namespace.")?.to_string();
     if verbose {
-        println!("Namespace {:?}", ns);
+        println!("Namespace {:?} ({:?})", ns, railml_version(&ns));
     }
 
     Ok((doc, ns))
 }
 
-pub fn convert(doc :&minidom::Element, ns :&str) -> Result<(dgraph::DGraphModel, HashMap<String,Vec<(String,String)>>), String> {
+/// railML version of a document, detected from its XML namespace.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RailMLVersion {
+    V2,
+    V3,
+}
+
+pub fn railml_version(ns :&str) -> RailMLVersion {
+    if ns.contains("railml.org/schemas/3") { RailMLVersion::V3 } else { RailMLVersion::V2 }
+}
+
+/// Convert the infrastructure of a railML document to a D-graph model, with the
+/// detection sections of the model and the warnings from the conversion.
+pub fn convert(doc :&minidom::Element, ns :&str)
+    -> Result<(dgraph::DGraphModel, HashMap<String,Vec<(String,String)>>, Vec<branching::Diagnostic>), String> {
+    let mut diagnostics = Vec::new();
     // read branching model from xml
-    let mut branching = branching::get_branching_model(&doc, &ns)?;
+    let mut branching = match railml_version(ns) {
+        RailMLVersion::V2 => branching::get_branching_model(&doc, &ns, &mut diagnostics)?,
+        RailMLVersion::V3 => railml3::get_branching_model(&doc, &ns, &mut diagnostics)?,
+    };
+    branching::check_detector_directions(&mut branching, &mut diagnostics);
     // add sight to branching model
-    sight::add_sight(&mut branching);
+    sight::add_sight(&mut branching, &mut diagnostics);
     // convert to d-graph representation
-    let mut dgraph = dgraph::convert(branching)?;
+    let mut dgraph = dgraph::convert(branching, &mut diagnostics)?;
     // convert detector locations to enter/exit objects on part-nodes
     let sections = sections::create_sections_from_detectors(&mut dgraph);
     let sections = sections.into_iter().map(|(k,v)| {
@@ -53,5 +75,25 @@ pub fn convert(doc :&minidom::Element, ns :&str) -> Result<(dgraph::DGraphModel,
                 (k,v)
         }).collect();
 
-    Ok((dgraph,sections))
+    Ok((dgraph,sections,diagnostics))
+}
+
+/// Routes for the converted model. When the railML file has an interlocking route table,
+/// its routes are used, and the differences to the routes derived from the model are returned.
+/// Overlaps are added to the routes without overlaps from the route table when overlap
+/// options are given.
+pub fn convert_routes(doc :&minidom::Element, ns :&str, model :&dgraph::DGraphModel,
+                      overlaps :Option<&routes::OverlapOptions>)
+    -> Result<(Vec<routes::Route>, Vec<interlocking::RouteDifference>), String> {
+    let (derived, _, entries) = routes::search_routes(model);
+    let table = interlocking::get_interlocking_routes(doc, ns)?;
+    let (mut routes, differences) = if table.is_empty() {
+        (derived, Vec::new())
+    } else {
+        interlocking::check_routes(model, &table, &entries, derived)
+    };
+    if let Some(opts) = overlaps {
+        routes::add_overlaps(model, &mut routes, opts);
+    }
+    Ok((routes, differences))
 }
@@ -12,12 +12,13 @@ struct Opts<'a> {
     input_fn: &'a path::Path,
     infrastructure_fn: Option<&'a path::Path>,
     routes_fn: Option<&'a path::Path>,
+    overlaps: Option<routes::OverlapOptions>,
     verbose: bool,
 }
 
 fn main() {
-    let opts = App::new("railML 2.x to Rolling converter")
-        .about("Convert railML 2.x files to rolling D-graph format")
+    let opts = App::new("railML to Rolling converter")
+        .about("Convert railML 2.x and 3.x files to rolling D-graph format")
         .arg(Arg::with_name("INPUT")
             .help("railML file")
             .required(true)
@@ -35,19 +36,38 @@ fn main() {
             .long("routes")
             .value_name("FILE")
             .help("Output rolling routes to file"))
+        .arg(Arg::with_name("overlap")
+            .long("overlap")
+            .value_name("LENGTH")
+            .help("Add overlaps of the given length past the exit signal of routes"))
+        .arg(Arg::with_name("overlap-timeout")
+            .long("overlap-timeout")
+            .value_name("SECONDS")
+            .requires("overlap")
+            .help("Release overlaps the given number of seconds after the train enters the route"))
         .get_matches();
 
+    let number = |name :&str| opts.value_of(name).map(|x| x.parse::<f64>().unwrap_or_else(|_| {
+        eprintln!("Failed: {} is not a number", name);
+        std::process::exit(1);
+    }));
+    let overlaps = number("overlap").map(|length| routes::OverlapOptions {
+        length: length,
+        timeout: number("overlap-timeout"),
+    });
+
     let opts = Opts {
         input_fn: opts.value_of("INPUT").map(|x| path::Path::new(x)).unwrap(),
         infrastructure_fn: opts.value_of("infrastructure").map(|x| path::Path::new(x)),
         routes_fn: opts.value_of("routes").map(|x| path::Path::new(x)),
+        overlaps: overlaps,
         verbose: opts.occurrences_of("v") > 0,
     };
 
     match run(&opts) {
         Ok(()) => {}
         Err(e) => {
-            println!("Failed: {}", e);
+            eprintln!("Failed: {}", e);
             std::process::exit(1);
         }
     }
@@ -55,7 +75,10 @@ fn main() {
 
 fn run(opts :&Opts) -> Result<(), String> {
     let (doc, ns) = get_xml(opts.input_fn, opts.verbose)?;
-    let (dgraph,_section_edges) = convert(&doc,&ns)?;
+    let (dgraph,_section_edges,diagnostics) = convert(&doc,&ns)?;
+    for d in &diagnostics {
+        eprintln!("Warning: {}", d);
+    }
 
 
     //println!("DGRAPH {:?}", dgraph);
@@ -78,7 +101,10 @@ fn run(opts :&Opts) -> Result<(), String> {
 
     // 10. output routes
     if let Some(f) = opts.routes_fn {
-        let routes = routes::find_routes(&dgraph);
+        let (routes, differences) = convert_routes(&doc, &ns, &dgraph, opts.overlaps.as_ref())?;
+        for d in &differences {
+            eprintln!("Warning: {}", d);
+        }
 
         let mut buffer = File::create(f).map_err(|e| e.to_string())?;
         output::print_routes(&mut buffer, &dgraph, &routes).map_err(|e| e.to_string())?;
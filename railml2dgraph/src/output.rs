@@ -1,5 +1,6 @@
 use dgraph::*;
 use routes::*;
+use branching::identifier;
 use std;
 
 pub fn repr_partnodeobject(obj: &PartNodeObject) -> String {
@@ -9,6 +10,7 @@ pub fn repr_partnodeobject(obj: &PartNodeObject) -> String {
         TVDEnter(ref x) => format!("enter {}", x),
         TVDExit(ref x) => format!("exit {}", x),
         Sight(ref x, l) => format!("sight {} {}",x,l),
+        Speed(v) => format!("speed {}", v),
     }
 }
 
@@ -24,6 +26,15 @@ pub fn repr_partnode(objs: &[PartNodeObject]) -> String {
     }
 }
 
+pub fn repr_position(position: &Option<NodePosition>) -> String {
+    match *position {
+        Some(NodePosition { ref track, pos, abs_pos: Some(abs_pos) }) =>
+            format!(" at {} {} abs {}", identifier(track), pos, abs_pos),
+        Some(NodePosition { ref track, pos, abs_pos: None }) => format!(" at {} {}", identifier(track), pos),
+        None => "".to_string(),
+    }
+}
+
 pub fn print_resources<W: std::io::Write>(buf: &mut W,
                                           _model: &DGraphModel,
                                           route: &Route)
@@ -61,6 +72,24 @@ pub fn print_releases<W: std::io::Write>(buf: &mut W,
     Ok(())
 }
 
+pub fn print_overlaps<W: std::io::Write>(buf: &mut W, route: &Route) -> std::io::Result<()> {
+    for o in &route.overlaps {
+        write!(buf,
+               "  overlap {{ sections [{}] switches [{}]",
+               o.sections.join(", "),
+               o.switches
+                   .iter()
+                   .map(|&(ref sw, pos)| format!("{} {}", sw, pos.as_str()))
+                   .collect::<Vec<_>>()
+                   .join(", "))?;
+        if let Some(t) = o.timeout {
+            write!(buf, " timeout {}", t)?;
+        }
+        writeln!(buf, " }}")?;
+    }
+    Ok(())
+}
+
 pub fn print_routes<W: std::io::Write>(buf: &mut W,
                                        model: &DGraphModel,
                                        routes: &Vec<Route>)
@@ -68,11 +97,12 @@ pub fn print_routes<W: std::io::Write>(buf: &mut W,
     let mut i = 1;
     for r in routes {
         use routes::RouteBoundary::*;
+        let name = r.name.clone().unwrap_or_else(|| format!("r{}", i));
         match (&r.entry, &r.exit) {
             (&ModelBoundary(ref b), &Signal(ref s)) => {
                 writeln!(buf,
-                         "modelentry r{} from {} {{",
-                         i,
+                         "modelentry {} from {} {{",
+                         name,
                          model.nodes[b.node_idx()].get_part(b.node_part()).name)?;
                 writeln!(buf, "  exit {}", s)?;
                 writeln!(buf, "  length {}", r.length)?;
@@ -82,8 +112,8 @@ pub fn print_routes<W: std::io::Write>(buf: &mut W,
             }
             (&Signal(ref s), &ModelBoundary(ref b)) => {
                 writeln!(buf,
-                         "modelexit r{} to {} {{",
-                         i,
+                         "modelexit {} to {} {{",
+                         name,
                          model.nodes[b.node_idx()].get_part(b.node_part()).name)?;
                 writeln!(buf, "  entry {}", s)?;
                 if let Some(s) = r.sections.get(0) {
@@ -95,7 +125,7 @@ pub fn print_routes<W: std::io::Write>(buf: &mut W,
                 writeln!(buf, "}}")?;
             }
             (&Signal(ref s1), &Signal(ref s2)) => {
-                writeln!(buf, "route r{} {{", i)?;
+                writeln!(buf, "route {} {{", name)?;
                 writeln!(buf, "  entry {}", s1)?;
                 writeln!(buf, "  exit {}", s2)?;
                 if let Some(s) = r.sections.get(0) {
@@ -104,10 +134,11 @@ pub fn print_routes<W: std::io::Write>(buf: &mut W,
                 writeln!(buf, "  length {}", r.length)?;
                 print_resources(buf, model, r)?;
                 print_releases(buf, model, r)?;
+                print_overlaps(buf, r)?;
                 writeln!(buf, "}}")?;
             }
             (&ModelBoundary(ref b1), &ModelBoundary(ref b2)) => {
-                println!("Warning: boundaries {:?} to {:?} are reachable without passing a \
+                eprintln!("Warning: boundaries {:?} to {:?} are reachable without passing a \
                           signal.",
                          b1,
                          b2);
@@ -128,20 +159,25 @@ pub fn dgraph_string(model :&DGraphModel) -> Result<String, String> {
 pub fn print_rolling<W: std::io::Write>(buf: &mut W, model: &DGraphModel) -> std::io::Result<()> {
     for n in &model.nodes {
         writeln!(buf,
-                 "node {}{}-{}{}",
+                 "node {}{}-{}{}{}",
                  n.a.name,
                  repr_partnode(&n.a.objs),
                  n.b.name,
-                 repr_partnode(&n.b.objs))?;
+                 repr_partnode(&n.b.objs),
+                 repr_position(&n.position))?;
     }
     for e in &model.edges {
         match *e {
             Edge::Linear(a, (b, d)) => {
                 writeln!(buf,
-                         "linear {}-{} {}",
+                         "linear {}-{} {}{}",
                          model.nodes[a.node_idx()].get_part(a.node_part()).name,
                          model.nodes[b.node_idx()].get_part(b.node_part()).name,
-                         d)?
+                         d,
+                         match model.gradients.get(&a) {
+                             Some(g) => format!(" (gradient {})", g),
+                             None => "".to_string(),
+                         })?
             }
             Edge::Switch(ref name, side, n1, (n2, d2), (n3, d3)) => {
                 writeln!(buf,
@@ -163,6 +199,10 @@ pub fn print_rolling<W: std::io::Write>(buf: &mut W, model: &DGraphModel) -> std
 
         }
     }
+    for s in &model.stops {
+        writeln!(buf, "stop {} at {} {} {}{}", identifier(&s.name), identifier(&s.track), s.pos, s.length,
+                 s.ocp.as_ref().map(|o| format!(" ocp {}", identifier(o))).unwrap_or(String::new()))?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,275 @@
+use minidom;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use base::*;
+use branching::*;
+
+// Reading railML 3.x infrastructure into the same branching model as railML 2.x.
+// Tracks are the micro level net elements, and their ends are connected by
+// net relations. A switch sits at the end of the net element which is common to
+// its left and right branch relations, with the branch relation leaving through
+// the switch and the continuing relation leaving through the track end.
+
+struct Relation {
+    id: String,
+    a: (usize, bool),
+    b: (usize, bool),
+}
+
+impl Relation {
+    // Connection names (id, ref) seen from side A or side B of the relation.
+    fn conn_name(&self, side_a: bool) -> (String,String) {
+        let (a, b) = (format!("{}_a", self.id), format!("{}_b", self.id));
+        if side_a { (a, b) } else { (b, a) }
+    }
+}
+
+pub fn get_branching_model<'a>(doc :&'a minidom::Element, ns: &str, diagnostics :&mut Vec<Diagnostic>)
+    -> Result<BranchingModel, String> {
+
+    let mut model = BranchingModel {
+        tracks: Vec::new(),
+        connections: HashMap::new(),
+        switch_conns: HashSet::new(),
+        crossing_conns: Vec::new(),
+        stops: Vec::new(),
+    };
+
+    let infrastructure = infrastructure_element(doc)?;
+
+    let topology = infrastructure.get_child("topology", ns)
+        .ok_or(format!("No topology found in the infrastructure"))?;
+    let net_elements = topology.get_child("netElements", ns)
+        .ok_or(format!("No netElements found in the topology"))?;
+    let topology_path = element_path(&infrastructure_path(doc), topology);
+    let net_elements_path = element_path(&topology_path, net_elements);
+
+    // Net elements which aggregate other net elements belong to the meso and macro levels.
+    let mut track_idxs = HashMap::new();
+    for e in net_elements.children().filter(|x| x.name() == "netElement") {
+        if e.children().any(|x| x.name().starts_with("elementCollection")) { continue; }
+        let path = element_path(&net_elements_path, e);
+        let id = required_attr(e, &path, "id")?;
+        let length = number_attr(e, &path, "length")?;
+        // Linear coordinates of the net element's intrinsic coordinates.
+        let mileage = e.children()
+            .filter(|x| x.name() == "associatedPositioningSystem")
+            .flat_map(|x| x.children().filter(|x| x.name() == "intrinsicCoordinate"))
+            .filter_map(|c| {
+                let intrinsic = c.attr("intrinsicCoord").and_then(|x| x.parse::<f64>().ok())?;
+                Some((intrinsic * length, linear_coordinate(c, ns)?))
+            })
+            .collect();
+        track_idxs.insert(id.to_string(), model.tracks.len());
+        model.tracks.push(BrTrack {
+            name: id.to_string(),
+            id: id.to_string(),
+            begin: BrTrackEnd::Stop,
+            objs: Vec::new(),
+            length,
+            end: BrTrackEnd::Stop,
+            mileage,
+        });
+    }
+
+    let mut relations = Vec::new();
+    let net_relations = topology.get_child("netRelations", ns)
+        .map(|r| r.children().filter(|x| x.name() == "netRelation").collect::<Vec<_>>())
+        .unwrap_or_else(|| Vec::new());
+    for r in net_relations {
+        let id = required_attr(r, &element_path(&format!("{}/netRelations", topology_path), r), "id")?;
+        if r.attr("navigability") == Some("None") { continue; }
+        let element = |name :&str, pos :&str| -> Result<Option<(usize,bool)>, String> {
+            let e = r.get_child(name, ns)
+                .and_then(|x| x.attr("ref"))
+                .ok_or(format!("No {} on netRelation {:?}", name, id))?;
+            let at_end = match r.attr(pos) {
+                Some("0") => false,
+                Some("1") => true,
+                _ => return Err(format!("No {} on netRelation {:?}", pos, id)),
+            };
+            Ok(track_idxs.get(e).map(|&t| (t, at_end)))
+        };
+        if let (Some(a), Some(b)) = (element("elementA", "positionOnA")?,
+                                     element("elementB", "positionOnB")?) {
+            relations.push(Relation { id: id.to_string(), a, b });
+        }
+    }
+
+    let functional = infrastructure.get_child("functionalInfrastructure", ns);
+    let functional_path = format!("{}/functionalInfrastructure", infrastructure_path(doc));
+    let items = |group :&str, item :&str| {
+        functional.and_then(|f| f.get_child(group, ns))
+            .map(|g| g.children().filter(|x| x.name() == item).collect::<Vec<_>>())
+            .unwrap_or_else(|| Vec::new())
+    };
+
+    // Switches
+    let mut branch_relations = HashSet::new();
+    for sw in items("switchesIS", "switchIS") {
+        let id = required_attr(sw, &element_path(&format!("{}/switchesIS", functional_path), sw), "id")?;
+        let relation = |name :&str| -> Result<&Relation, String> {
+            let r = sw.get_child(name, ns)
+                .and_then(|x| x.attr("netRelationRef"))
+                .ok_or(format!("No {} on switch {:?}", name, id))?;
+            relations.iter().find(|x| x.id == r)
+                .ok_or(format!("Unknown netRelation {:?} on switch {:?}", r, id))
+        };
+        let (left, right) = (relation("leftBranch")?, relation("rightBranch")?);
+        let side = match (sw.attr("branchCourse"), sw.attr("continueCourse")) {
+            (Some("left"), _) => Side::Left,
+            (Some("right"), _) => Side::Right,
+            (_, Some("right")) => Side::Left,
+            (_, Some("left")) => Side::Right,
+            _ => return Err(format!("No branchCourse on switch {:?}", id)),
+        };
+        let branch = if side == Side::Left { left } else { right };
+        let root = [left.a, left.b].iter().cloned()
+            .find(|x| *x == right.a || *x == right.b)
+            .ok_or(format!("Branches of switch {:?} have no common netElement", id))?;
+
+        let (track, at_end) = root;
+        let (pos, dir) = if at_end { (model.tracks[track].length, Dir::Up) } else { (0.0, Dir::Down) };
+        let conn = branch.conn_name(branch.a == root);
+        model.connections.insert((conn.1.clone(), conn.0.clone()),
+                                 BrCursor { track, offset: pos, dir: dir.opposite() });
+        model.switch_conns.insert((conn.0.clone(), conn.1.clone()));
+        model.switch_conns.insert((conn.1.clone(), conn.0.clone()));
+        branch_relations.insert((branch.id.clone(), root));
+        model.tracks[track].objs.push(BrObject {
+            name: id.to_string(), pos,
+            data: BrObjectData::Switch { dir, side, conn },
+        });
+    }
+
+    // Open ends
+    let mut open_ends = HashMap::new();
+    for b in items("borders", "border") {
+        if b.attr("isOpenEnd") != Some("true") { continue; }
+        let path = element_path(&format!("{}/borders", functional_path), b);
+        let id = required_attr(b, &path, "id")?;
+        let (track, pos, _) = spot_location(b, &path, ns, &track_idxs, &mut model.tracks)?;
+        open_ends.insert((track, pos > model.tracks[track].length / 2.0), id.to_string());
+    }
+
+    // Track ends
+    for track in 0..model.tracks.len() {
+        for &at_end in &[false, true] {
+            let here = (track, at_end);
+            let conns = relations.iter()
+                .filter(|r| r.a == here || r.b == here)
+                .filter(|r| !branch_relations.contains(&(r.id.clone(), here)))
+                .collect::<Vec<_>>();
+            if conns.len() > 1 {
+                return Err(format!("Multiple netRelations without a switch at {} of netElement {:?}",
+                                   if at_end { "end" } else { "begin" }, model.tracks[track].name));
+            }
+            let (offset, dir) = if at_end { (model.tracks[track].length, Dir::Down) } else { (0.0, Dir::Up) };
+            let track_end = if let Some(r) = conns.first() {
+                let conn = r.conn_name(r.a == here);
+                model.connections.insert((conn.1.clone(), conn.0.clone()),
+                                         BrCursor { track, offset, dir });
+                BrTrackEnd::Connection(conn)
+            } else if let Some(name) = open_ends.get(&here) {
+                BrTrackEnd::Boundary(name.clone())
+            } else {
+                BrTrackEnd::Stop
+            };
+            if at_end { model.tracks[track].end = track_end; } else { model.tracks[track].begin = track_end; }
+        }
+    }
+
+    // Signals
+    for s in items("signalsIS", "signalIS") {
+        let path = element_path(&format!("{}/signalsIS", functional_path), s);
+        let name = required_attr(s, &path, "id")?.to_string();
+        let (track, pos, dir) = spot_location(s, &path, ns, &track_idxs, &mut model.tracks)?;
+        let dir = match dir {
+            Some(dir) => dir,
+            None => {
+                diagnostics.push(Diagnostic {
+                    element: path,
+                    message: format!("no applicationDirection for signal, ignoring it"),
+                });
+                continue;
+            }
+        };
+        let sight = match s.attr("sight") {
+            Some(_) => number_attr(s, &path, "sight")?,
+            None => {
+                diagnostics.push(Diagnostic {
+                    element: path,
+                    message: format!("no sight info for signal, using 200 m"),
+                });
+                200.0
+            }
+        };
+        let relevant_type = match s.get_child("isTrainMovementSignal", ns) {
+            Some(m) => match m.attr("type") {
+                Some("main") | Some("combined") | None => true,
+                Some(_) => false,
+            },
+            None => !s.children().any(|x| x.name().starts_with("is")),
+        };
+        if relevant_type {
+            model.tracks[track].objs.push(BrObject { name, pos, data: BrObjectData::Signal { dir, sight } });
+        }
+    }
+
+    // Train detectors
+    for d in items("trainDetectionElements", "trainDetectionElement") {
+        let path = element_path(&format!("{}/trainDetectionElements", functional_path), d);
+        let name = required_attr(d, &path, "id")?.to_string();
+        let (track, pos, dir) = spot_location(d, &path, ns, &track_idxs, &mut model.tracks)?;
+        // Track circuit borders always detect both directions.
+        let track_circuit = match d.attr("type") {
+            Some("insulatedRailJoint") | Some("trackCircuit") => true,
+            _ => false,
+        };
+        let dir = if dir.is_some() && track_circuit {
+            diagnostics.push(Diagnostic {
+                element: path,
+                message: format!("track circuit border detects both directions, ignoring applicationDirection"),
+            });
+            None
+        } else { dir };
+        model.tracks[track].objs.push(BrObject { name, pos, data: BrObjectData::Detector { dir } });
+    }
+
+    Ok(model)
+}
+
+// Measure of the linear coordinate of a location, i.e. its absolute position.
+fn linear_coordinate(e :&minidom::Element, ns :&str) -> Option<f64> {
+    e.get_child("linearCoordinate", ns)
+        .and_then(|x| x.attr("measure"))
+        .and_then(|x| x.parse::<f64>().ok())
+}
+
+// Track, position and direction of the first spotLocation of an element at `path`.
+// The position is taken from the pos attribute (meters from the beginning of the
+// net element), or else from the intrinsic coordinate. A linear coordinate of the
+// location is added to the mileage of the track.
+fn spot_location(e :&minidom::Element, path :&str, ns :&str, track_idxs :&HashMap<String,usize>,
+                 tracks :&mut [BrTrack]) -> Result<(usize, f64, Option<Dir>), String> {
+    let loc = e.get_child("spotLocation", ns)
+        .ok_or(format!("{}: no spotLocation", path))?;
+    let path = element_path(path, loc);
+    let net_element = required_attr(loc, &path, "netElementRef")?;
+    let track = *track_idxs.get(net_element)
+        .ok_or(format!("{}: unknown netElement {:?}", path, net_element))?;
+    let pos = match (loc.attr("pos"), loc.attr("intrinsicCoord")) {
+        (Some(_), _) => number_attr(loc, &path, "pos")?,
+        (None, Some(_)) => number_attr(loc, &path, "intrinsicCoord")? * tracks[track].length,
+        _ => return Err(format!("{}: no pos or intrinsicCoord", path)),
+    };
+    let dir = match loc.attr("applicationDirection") {
+        Some("normal") => Some(Dir::Up),
+        Some("reverse") => Some(Dir::Down),
+        _ => None,
+    };
+    if let Some(abs_pos) = linear_coordinate(loc, ns) {
+        tracks[track].mileage.push((pos, abs_pos));
+    }
+    Ok((track, pos, dir))
+}
@@ -15,12 +15,32 @@ pub enum RouteBoundary {
 
 #[derive(Debug, Clone)]
 pub struct Route {
+    /// Name from the interlocking, for imported routes.
+    pub name: Option<String>,
     pub entry: RouteBoundary,
     pub exit: RouteBoundary,
     pub sections: Vec<String>,
     pub switches: Vec<(String, Side)>,
     pub releases: Vec<(String, f64, Vec<String>)>,
     pub length: f64,
+    pub overlaps: Vec<Overlap>,
+}
+
+/// Sections and switch positions past the exit signal of a route,
+/// which are reserved together with the route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlap {
+    pub sections: Vec<String>,
+    pub switches: Vec<(String, Side)>,
+    pub timeout: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OverlapOptions {
+    /// Length of the overlap past the exit signal.
+    pub length: f64,
+    /// Time after the train enters the route when the overlap is released.
+    pub timeout: Option<f64>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,7 +80,7 @@ pub fn convert_state_to_route(state: &Path, entry: RouteBoundary,
     let section_tolerance = 15.0;
 
     if state.length < section_tolerance {
-        println!("Warning: route too short");
+        eprintln!("Warning: route too short");
         return None;
     }
 
@@ -86,16 +106,18 @@ pub fn convert_state_to_route(state: &Path, entry: RouteBoundary,
 
     let release_length = releases.iter().map(|&(_,l,_)| l).sum::<f64>();
     if releases.len() > 0 && release_length != state.length {
-        println!("Release length and route length differ by {} {} {:?} {:?}", state.length, release_length, entry, exit);
+        eprintln!("Warning: release length and route length differ by {} {} {:?} {:?}", state.length, release_length, entry, exit);
         releases.last_mut().unwrap().1 += state.length - release_length;
     }
     let route = Route {
+        name: None,
         length: state.length,
         entry: entry,
         exit: exit,
         sections: sections.iter().map(|&(ref x,_,_)| x.clone()).collect(),
         switches: state.switches.iter().map(|&(ref x,_,s)| (x.clone(), s)).collect(),
         releases: releases,
+        overlaps: Vec::new(),
     };
     //println!("Route {:?}", route);
 
@@ -135,8 +157,17 @@ pub enum ConvertRouteIssue {
 }
 
 pub fn convert_routes(model: &DGraphModel) -> (Vec<Route>,Vec<ConvertRouteIssue>) {
+    let (routes, issues, _) = search_routes(model);
+    (routes, issues)
+}
+
+/// Derive the routes of the model, starting from the model boundaries and
+/// continuing from each signal which is reached. Also returns the entries
+/// which the routes start from.
+pub fn search_routes(model: &DGraphModel) -> (Vec<Route>,Vec<ConvertRouteIssue>,Vec<RouteEntry>) {
     let mut routes = Vec::new();
     let mut issues = Vec::new();
+    let mut entries = Vec::new();
     let dir_edges = convert_model(&model);
     let boundary_nodes = model.edges.iter().filter_map(|x| if let Edge::Boundary(n) = *x {
         Some(n)
@@ -147,7 +178,6 @@ pub fn convert_routes(model: &DGraphModel) -> (Vec<Route>,Vec<ConvertRouteIssue>
 
     let mut entry_visited = HashSet::new();
     for boundary in boundary_nodes {
-        println!("Boundary start {:?}", boundary);
 
         let mut entry_stack = Vec::new();
         entry_stack.push(RouteEntry {
@@ -159,145 +189,261 @@ pub fn convert_routes(model: &DGraphModel) -> (Vec<Route>,Vec<ConvertRouteIssue>
 
         while entry_stack.len() > 0 {
             let entry = entry_stack.pop().unwrap();
-            let mut search_stack = Vec::new();
-
-            //println!("Entry from {:?}", entry);
-
-            // A route path may only visit a given switch in a given direction
-            // (trailing or facing) once, because using a given switch
-            // several times would mean either:
-            //  a. The path goes over the same switch in the same position twice,
-            //     which means that there was no end point between the visits,
-            //     and since all switches between these visits need to be in the
-            //     same position.
-            //  b. The path goes over the switch twice in different directions,
-            //     which is not something a route should be able to provide.
-            //     All movable elements must be locked in place to activate a route
-            //     so it would not be possible to require a switch to be in
-            //     two positions at once.
-
-            let mut switches_path_visited :BTreeSet<BTreeSet<(String,Side)>> = BTreeSet::new();
-
-            search_stack.push(Path {
-                node: entry.node,
-                entered_sections: entry.section.iter().map(|x| (x.clone(), 0.0)).collect(),
-                exited_sections: vec![],
-                switches: vec![],
-                length: 0.0,
+            search_paths(model, &dir_edges, &entry, &[], |state, exit| {
+                if let RouteBoundary::Signal(ref x) = exit {
+                    if entry_visited.insert(state.node) {
+                        entry_stack.push(RouteEntry {
+                            node: state.node,
+                            entry: RouteBoundary::Signal(x.clone()),
+                            section: state.entered_sections.iter().nth(0).map(|x| x.0.clone()),
+                        });
+                    }
+                }
+                match convert_state_to_route(state, entry.entry.clone(), exit.clone()) {
+                    Some(route) => routes.push(route),
+                    None => match exit {
+                        RouteBoundary::Signal(_) => eprintln!("Warning: Route conversion failed"),
+                        RouteBoundary::ModelBoundary(_) => eprintln!("Warning: Could not convert route"),
+                    },
+                }
             });
+            entries.push(entry);
+        }
+    }
+    for r in &mut routes {
+        remove_unacquired_releases(r);
+    }
 
-            while search_stack.len() > 0 {
-                let mut curr_state = search_stack.pop().unwrap();
-
-                loop {
-                    let mut is_exit = false;
-                    if curr_state.node != entry.node {
-
-                        // Check what is in here
-                        //
-                        let node = model.nodes[curr_state.node.node_idx()]
-                            .get_part(curr_state.node.node_part());
-
-                        for obj in &node.objs {
-                            use dgraph::PartNodeObject::*;
-                            match *obj {
-                                Signal(ref x) => {
-
-                                    if let Some(route) = convert_state_to_route(&curr_state,
-                                                            entry.entry.clone(), RouteBoundary::Signal(x.clone())) {
-                                        routes.push(route);
-                                    } else {
-                                        println!("Warning: Route conversion failed");
-                                    }
-
-                                    if entry_visited.insert(curr_state.node) {
-                                        entry_stack.push(RouteEntry {
-                                            node: curr_state.node,
-                                            entry: RouteBoundary::Signal(x.clone()),
-                                            section: curr_state.entered_sections.iter().nth(0).map(|x| x.0.clone()),
-                                        });
-                                    }
-
-                                    is_exit = true;
-                                }
-                                TVDEnter(ref x) => {
-                                    curr_state.entered_sections.push((x.clone(), curr_state.length));
-                                }
-                                TVDExit(ref x) => {
-                                    if let Some(i) = curr_state.entered_sections.iter().position(|y| y.0 == *x) {
-                                        let e = curr_state.entered_sections.remove(i);
-                                        curr_state.exited_sections.push((e.0, e.1, curr_state.length));
-                                    } else {
-                                        panic!("Exited unexpected section");
-                                    }
-                                }
-                                Sight(_,_) => {}
-                            }
-                        }
-                    }
+    if !(entry_visited.len() > 0) { issues.push(ConvertRouteIssue::NoBoundaries); }
 
-                    if is_exit { break; }
-
-                    match dir_edges.get(&curr_state.node).cloned() {
-                        Some(DirEdge::Linear((other,d))) => {
-                            curr_state.node = other.opposite();
-                            curr_state.length += d;
-                        },
-                        Some(DirEdge::TrailingSwitch(sw,pos,(other,d))) => {
-                            curr_state.node = other.opposite();
-                            curr_state.length += d;
-                            curr_state.switches.push((sw,curr_state.length,pos));
-                            //println!("Switch list {:?}", switch_list(&curr_state.switches));
-                            if !switches_path_visited.insert(switch_list(&curr_state.switches).into_iter().collect()) {
-                                // We have been here before. Abort without adding a new route,
-                                // to avoid having routes with loops.
-                                break;
-                            }
-                        },
-                        Some(DirEdge::FacingSwitch(sw, (other1, d1), (other2, d2))) => {
-                            let mut right_state = curr_state.clone();
-
-                            curr_state.node = other1.opposite();
-                            curr_state.switches.push((sw.clone(), curr_state.length, Side::Left));
-                            curr_state.length += d1;
-                            right_state.node = other2.opposite();
-                            right_state.switches.push((sw, curr_state.length, Side::Right));
-                            right_state.length += d2;
-
-                            if switches_path_visited.insert(switch_list(&right_state.switches).into_iter().collect()) {
-                                search_stack.push(right_state);
-                            }
-                            if !switches_path_visited.insert(switch_list(&curr_state.switches).into_iter().collect()) {
-                                break;
-                            }
-                        },
-                        Some(DirEdge::Boundary) => {
-                            if let Some(route) = convert_state_to_route(&curr_state,
-                                        entry.entry.clone(), RouteBoundary::ModelBoundary(curr_state.node)) {
-                                routes.push(route);
-                                break;
+    (routes,issues,entries)
+}
+
+/// Routes from a single entry to the next signals and model boundaries,
+/// using only the given switch positions.
+pub fn entry_routes(model: &DGraphModel, entry: &RouteEntry, positions: &[(String, Side)]) -> Vec<Route> {
+    let dir_edges = convert_model(model);
+    let mut routes = Vec::new();
+    search_paths(model, &dir_edges, entry, positions, |state, exit| {
+        if let Some(route) = convert_state_to_route(state, entry.entry.clone(), exit) {
+            routes.push(route);
+        }
+    });
+    for r in &mut routes {
+        remove_unacquired_releases(r);
+    }
+    routes
+}
+
+// Remove release of resources that were not aquired
+fn remove_unacquired_releases(r: &mut Route) {
+    let resources = r.sections.iter().chain(r.switches.iter().map(|&(ref sw,_)| sw)).cloned().collect::<Vec<_>>();
+    for &mut (_,_,ref mut res) in &mut r.releases {
+        res.retain(|x| resources.contains(x));
+    }
+}
+
+// Search the paths from a route entry to the next signals and model boundaries,
+// calling `found` with the path and the exit at the end of each path. At facing
+// switches, both legs are searched unless the switch has one of the given positions,
+// and paths over trailing switches in another position are not used.
+fn search_paths<F>(model: &DGraphModel, dir_edges: &HashMap<PartNodeIdx, DirEdge>,
+                   entry: &RouteEntry, positions: &[(String, Side)], mut found: F)
+    where F: FnMut(&Path, RouteBoundary) {
+
+    let mut search_stack = Vec::new();
+    let other_position = |sw: &str, side: Side| positions.iter().any(|&(ref x, s)| x == sw && s != side);
+
+    // A route path may only visit a given switch in a given direction
+    // (trailing or facing) once, because using a given switch
+    // several times would mean either:
+    //  a. The path goes over the same switch in the same position twice,
+    //     which means that there was no end point between the visits,
+    //     and since all switches between these visits need to be in the
+    //     same position.
+    //  b. The path goes over the switch twice in different directions,
+    //     which is not something a route should be able to provide.
+    //     All movable elements must be locked in place to activate a route
+    //     so it would not be possible to require a switch to be in
+    //     two positions at once.
+
+    let mut switches_path_visited :BTreeSet<BTreeSet<(String,Side)>> = BTreeSet::new();
+
+    search_stack.push(Path {
+        node: entry.node,
+        entered_sections: entry.section.iter().map(|x| (x.clone(), 0.0)).collect(),
+        exited_sections: vec![],
+        switches: vec![],
+        length: 0.0,
+    });
+
+    while search_stack.len() > 0 {
+        let mut curr_state = search_stack.pop().unwrap();
+
+        loop {
+            let mut is_exit = false;
+            if curr_state.node != entry.node {
+
+                // Check what is in here
+                //
+                let node = model.nodes[curr_state.node.node_idx()]
+                    .get_part(curr_state.node.node_part());
+
+                for obj in &node.objs {
+                    use dgraph::PartNodeObject::*;
+                    match *obj {
+                        Signal(ref x) => {
+                            found(&curr_state, RouteBoundary::Signal(x.clone()));
+                            is_exit = true;
+                        }
+                        TVDEnter(ref x) => {
+                            curr_state.entered_sections.push((x.clone(), curr_state.length));
+                        }
+                        TVDExit(ref x) => {
+                            if let Some(i) = curr_state.entered_sections.iter().position(|y| y.0 == *x) {
+                                let e = curr_state.entered_sections.remove(i);
+                                curr_state.exited_sections.push((e.0, e.1, curr_state.length));
                             } else {
-                                println!("Warning: Could not convert route");
+                                panic!("Exited unexpected section");
                             }
-                        },
-                        None => {
-                            break;
                         }
+                        Sight(_,_) | Speed(_) => {}
                     }
                 }
             }
+
+            if is_exit { break; }
+
+            match dir_edges.get(&curr_state.node).cloned() {
+                Some(DirEdge::Linear((other,d))) => {
+                    curr_state.node = other.opposite();
+                    curr_state.length += d;
+                },
+                Some(DirEdge::TrailingSwitch(sw,pos,(other,d))) => {
+                    if other_position(&sw, pos) { break; }
+                    curr_state.node = other.opposite();
+                    curr_state.length += d;
+                    curr_state.switches.push((sw,curr_state.length,pos));
+                    //println!("Switch list {:?}", switch_list(&curr_state.switches));
+                    if !switches_path_visited.insert(switch_list(&curr_state.switches).into_iter().collect()) {
+                        // We have been here before. Abort without adding a new route,
+                        // to avoid having routes with loops.
+                        break;
+                    }
+                },
+                Some(DirEdge::FacingSwitch(sw, (other1, d1), (other2, d2))) => {
+                    let mut right_state = curr_state.clone();
+
+                    curr_state.node = other1.opposite();
+                    curr_state.switches.push((sw.clone(), curr_state.length, Side::Left));
+                    curr_state.length += d1;
+                    right_state.node = other2.opposite();
+                    right_state.switches.push((sw.clone(), curr_state.length, Side::Right));
+                    right_state.length += d2;
+
+                    if !other_position(&sw, Side::Right) &&
+                        switches_path_visited.insert(switch_list(&right_state.switches).into_iter().collect()) {
+                        search_stack.push(right_state);
+                    }
+                    if other_position(&sw, Side::Left) ||
+                        !switches_path_visited.insert(switch_list(&curr_state.switches).into_iter().collect()) {
+                        break;
+                    }
+                },
+                Some(DirEdge::Boundary) => {
+                    found(&curr_state, RouteBoundary::ModelBoundary(curr_state.node));
+                    break;
+                },
+                None => {
+                    break;
+                }
+            }
         }
     }
-    // Remove release of resources that were not aquired
-    for r in &mut routes {
-        let resources = r.sections.iter().chain(r.switches.iter().map(|&(ref sw,_)| sw)).collect::<Vec<_>>();
-        for &mut (_,_,ref mut res) in &mut r.releases {
-            res.retain(|x| resources.contains(&x));
+}
+
+
+/// Add overlaps to routes between two signals. Each continuation from the exit
+/// signal gives an overlap with the sections entered and the switches passed
+/// within the overlap length, starting with the sections entered at the position
+/// of the exit signal. Routes from the model boundary get no overlaps, because
+/// overlaps are only released by a timeout started from the route's entry signal.
+pub fn add_overlaps(model: &DGraphModel, routes: &mut [Route], opts: &OverlapOptions) {
+    let dir_edges = convert_model(model);
+    let mut signals = HashMap::new();
+    for (i, n) in model.nodes.iter().enumerate() {
+        for &(part, p) in &[(&n.a, NodePart::A), (&n.b, NodePart::B)] {
+            for obj in &part.objs {
+                if let PartNodeObject::Signal(ref x) = *obj {
+                    signals.insert(x.clone(), PartNodeIdx::from_node_part(i, p));
+                }
+            }
         }
     }
 
-    if !(entry_visited.len() > 0) { issues.push(ConvertRouteIssue::NoBoundaries); }
+    for route in routes.iter_mut() {
+        // Overlaps from the interlocking route table are kept.
+        if route.overlaps.len() > 0 { continue; }
+        let start = match (&route.entry, &route.exit) {
+            (&RouteBoundary::Signal(_), &RouteBoundary::Signal(ref x)) => match signals.get(x) {
+                Some(&n) => n,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        // Detectors at the same position as the signal may be on neighbouring nodes.
+        let mut at_signal = Vec::new();
+        let mut node = start.opposite();
+        while let Some(DirEdge::Linear((prev, d))) = dir_edges.get(&node).cloned() {
+            if d > 0.0 { break; }
+            for obj in &model.nodes[prev.node_idx()].get_part(prev.node_part()).objs {
+                if let PartNodeObject::TVDEnter(ref x) = *obj {
+                    if !at_signal.contains(x) { at_signal.push(x.clone()); }
+                }
+            }
+            node = prev.opposite();
+        }
 
-    (routes,issues)
-}
+        let mut overlaps = Vec::new();
+        let mut stack = vec![(start, 0.0, at_signal, Vec::new())];
+        while let Some((mut node, mut length, mut sections, mut switches)) = stack.pop() {
+            loop {
+                if length >= opts.length { break; }
+                let objs = &model.nodes[node.node_idx()].get_part(node.node_part()).objs;
+                for obj in objs {
+                    if let PartNodeObject::TVDEnter(ref x) = *obj {
+                        if !sections.contains(x) { sections.push(x.clone()); }
+                    }
+                }
+
+                match dir_edges.get(&node).cloned() {
+                    Some(DirEdge::Linear((other, d))) => {
+                        node = other.opposite();
+                        length += d;
+                    },
+                    Some(DirEdge::TrailingSwitch(sw, pos, (other, d))) => {
+                        if switches.iter().any(|x: &(String, Side)| x.0 == sw) { break; }
+                        switches.push((sw, pos));
+                        node = other.opposite();
+                        length += d;
+                    },
+                    Some(DirEdge::FacingSwitch(sw, (other1, d1), (other2, d2))) => {
+                        if switches.iter().any(|x: &(String, Side)| x.0 == sw) { break; }
+                        let mut right_switches = switches.clone();
+                        right_switches.push((sw.clone(), Side::Right));
+                        stack.push((other2.opposite(), length + d2, sections.clone(), right_switches));
+                        switches.push((sw, Side::Left));
+                        node = other1.opposite();
+                        length += d1;
+                    },
+                    Some(DirEdge::Boundary) | None => break,
+                }
+            }
 
+            let overlap = Overlap { sections, switches, timeout: opts.timeout };
+            if !overlaps.contains(&overlap) { overlaps.push(overlap); }
+        }
+        route.overlaps = overlaps;
+    }
+}
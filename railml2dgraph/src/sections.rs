@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use petgraph;
+use base::*;
 use dgraph::*;
 
 pub fn create_sections_from_detectors(m: &mut DGraphModel) -> HashMap<String, Vec<(PartNodeIdx,PartNodeIdx)>> {
@@ -21,6 +22,13 @@ pub fn create_sections_from_detectors(m: &mut DGraphModel) -> HashMap<String, Ve
         }
     }
 
+    // Paths through a crossing cannot be occupied at the same time.
+    for &(_, ref nodes) in &m.crossings {
+        for n in nodes {
+            sets.union(2 * nodes[0] + 1, 2 * n + 1);
+        }
+    }
+
     for edge in &m.edges {
         use dgraph::Edge::*;
         match *edge {
@@ -34,7 +42,49 @@ pub fn create_sections_from_detectors(m: &mut DGraphModel) -> HashMap<String, Ve
     }
     let mut sec_num_counter = 0;
     let mut sec_num_map = HashMap::new();
+    let mut sec_roots = Vec::new();
+    for node_idx in 0..m.nodes.len() {
+        if !m.nodes[node_idx].has_detector { continue; }
+        for &idx in &[2 * node_idx + 1, 2 * node_idx + 2] {
+            let section = sets.find(idx);
+            if section != sets.find(is_boundary_idx) && sec_num_map.get(&section).is_none() {
+                sec_num_map.insert(section, sec_num_counter);
+                sec_roots.push(section);
+                sec_num_counter += 1;
+            }
+        }
+    }
 
+    // Directional detectors only limit the sections for trains running up
+    // (leaving through part B) or down (leaving through part A). For trains
+    // running the other way, the sections on both sides of the detector are
+    // joined into a region, which they enter and exit as a whole, so that the
+    // sections are not left occupied. Detectors next to a model boundary
+    // detect both directions, since trains are not detected entering the model.
+    let detector_dirs = m.nodes.iter().enumerate().map(|(node_idx, node)| {
+        let (a_idx, b_idx) = (2 * node_idx + 1, 2 * node_idx + 2);
+        if sets.find(a_idx) == sets.find(is_boundary_idx) || sets.find(b_idx) == sets.find(is_boundary_idx) {
+            None
+        } else {
+            node.detector_dir
+        }
+    }).collect::<Vec<_>>();
+    let regions = |dir :Dir| {
+        let mut regions = petgraph::unionfind::UnionFind::new(num_nodes);
+        for idx in 0..num_nodes { regions.union(idx, sets.find(idx)); }
+        for (node_idx, node) in m.nodes.iter().enumerate() {
+            if node.has_detector && detector_dirs[node_idx] == Some(dir.opposite()) {
+                regions.union(2 * node_idx + 1, 2 * node_idx + 2);
+            }
+        }
+        let mut sections :HashMap<usize, Vec<String>> = HashMap::new();
+        for (num, &root) in sec_roots.iter().enumerate() {
+            sections.entry(regions.find(root)).or_insert(Vec::new()).push(format!("sec{}", num));
+        }
+        (regions, sections)
+    };
+    let (up_regions, up_sections) = regions(Dir::Up);
+    let (down_regions, down_sections) = regions(Dir::Down);
 
     // Go back to each node and insert tvd entry/exit
     for (node_idx, node) in m.nodes.iter_mut().enumerate() {
@@ -45,28 +95,35 @@ pub fn create_sections_from_detectors(m: &mut DGraphModel) -> HashMap<String, Ve
             let a_section = sets.find(a_idx);
             let b_section = sets.find(b_idx);
 
+            let up = detector_dirs[node_idx] != Some(Dir::Down);
+            let down = detector_dirs[node_idx] != Some(Dir::Up);
 
             if a_section != sets.find(is_boundary_idx) {
-                if sec_num_map.get(&a_section).is_none() {
-                    sec_num_map.insert(a_section, sec_num_counter);
-                    sec_num_counter += 1;
+                if down {
+                    for name in &down_sections[&down_regions.find(a_idx)] {
+                        node.a.objs.push(PartNodeObject::TVDEnter(name.clone()));
+                    }
+                }
+                if up {
+                    for name in &up_sections[&up_regions.find(a_idx)] {
+                        node.b.objs.push(PartNodeObject::TVDExit(name.clone()));
+                    }
                 }
-                let a_section_name = format!("sec{}", sec_num_map[&a_section]);
-                node.a.objs.push(PartNodeObject::TVDEnter(a_section_name.clone()));
-                node.b.objs.push(PartNodeObject::TVDExit(a_section_name));
             } else {
                 // println!("Side A of detector {:?} is boundary", node);
             }
 
             if b_section != sets.find(is_boundary_idx) {
-                if sec_num_map.get(&b_section).is_none() {
-                    sec_num_map.insert(b_section, sec_num_counter);
-                    sec_num_counter += 1;
+                if up {
+                    for name in &up_sections[&up_regions.find(b_idx)] {
+                        node.b.objs.push(PartNodeObject::TVDEnter(name.clone()));
+                    }
+                }
+                if down {
+                    for name in &down_sections[&down_regions.find(b_idx)] {
+                        node.a.objs.push(PartNodeObject::TVDExit(name.clone()));
+                    }
                 }
-
-                let b_section_name = format!("sec{}", sec_num_map[&b_section]);
-                node.b.objs.push(PartNodeObject::TVDEnter(b_section_name.clone()));
-                node.a.objs.push(PartNodeObject::TVDExit(b_section_name));
             } else {
                 // println!("Side B of detector {:?} is boundary", node);
             }
@@ -1,6 +1,6 @@
 use branching::*;
 
-pub fn add_sight(m :&mut BranchingModel) {
+pub fn add_sight(m :&mut BranchingModel, diagnostics :&mut Vec<Diagnostic>) {
     let mut signals = m.tracks.iter().enumerate()
         .flat_map(|(i,x)| {
             x.objs.iter()
@@ -20,11 +20,9 @@ pub fn add_sight(m :&mut BranchingModel) {
         let (cursor, total_dist, remaining_dist, name) = signals.pop().unwrap();
         use branching::WalkResult;
         let res = walk(&m, &cursor, remaining_dist, delta);
-        println!("walk result {:?} {:?}", name, res);
         match res {
             WalkResult::Ok(cursor) => {
                 // Just insert
-                println!("Inserting on track {:?}", m.tracks[cursor.track].name);
                 m.tracks[cursor.track].objs.push(BrObject {
                     name: format!("sight_{}",name),
                     pos: cursor.offset,
@@ -36,8 +34,9 @@ pub fn add_sight(m :&mut BranchingModel) {
                 // Insert sight here, truncating the sight distance, because
                 // we don't know what to do for a driver which sees two different signals.
                 let deficiency = remaining_dist - d;
-                println!("Warning: sight distance for {:?} truncated at trailing switch from {} to {}", name, total_dist, total_dist - deficiency);
-                m.tracks[cursor.track].objs.push(BrObject {
+                diagnostics.push(Diagnostic { element: name.clone(),
+                    message: format!("sight distance truncated at trailing switch from {} to {}", total_dist, total_dist - deficiency) });
+                m.tracks[before.track].objs.push(BrObject {
                     name: format!("sight_{}", name),
                     pos: before.offset,
                     data: BrObjectData::Sight { 
@@ -51,7 +50,8 @@ pub fn add_sight(m :&mut BranchingModel) {
             },
             WalkResult::End(d, cursor) => {
                 let deficiency = remaining_dist - d;
-                println!("Warning: sight distance for {:?} truncated at model boundary/stop from {} to {}", name, total_dist, total_dist - deficiency);
+                diagnostics.push(Diagnostic { element: name.clone(),
+                    message: format!("sight distance truncated at model boundary/stop from {} to {}", total_dist, total_dist - deficiency) });
                 m.tracks[cursor.track].objs.push(BrObject {
                     name: format!("sight_{}", name),
                     pos: cursor.offset,
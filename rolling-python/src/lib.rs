@@ -0,0 +1,332 @@
+//! Python bindings for the rolling railway simulator.
+//!
+//! ```python
+//! import pyrolling
+//! inf = pyrolling.load_infrastructure("infrastructure")
+//! routes = pyrolling.load_routes(inf, "routes")
+//! plan = pyrolling.load_dispatch("plan")
+//! history = pyrolling.evaluate_plan(inf, routes, plan, timestep=1.0)
+//! for train in history.trains: print(train["name"], train["events"][-1]["time"])
+//! ```
+
+// The pyo3 0.22 function macros trigger this lint on `PyResult` return types.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use rolling::input::dispatch;
+use rolling::input::staticinfrastructure::{InfNames, Routes, StaticInfrastructure, SwitchPosition};
+use rolling::output::history::{self, InfrastructureLogEvent, RouteStatus, TrainLogEvent};
+use rolling::output::json::Names;
+use rolling::railway::dynamics::{DriverAction, TrainParams};
+use rolling::railway::SignallingMode;
+use std::path::Path;
+
+fn to_py_err(e: failure::Error) -> PyErr {
+    if e.downcast_ref::<std::io::Error>().is_some() {
+        PyIOError::new_err(e.to_string())
+    } else {
+        PyValueError::new_err(e.to_string())
+    }
+}
+
+/// Static infrastructure with node and object names.
+#[pyclass]
+pub struct Infrastructure {
+    inf: StaticInfrastructure,
+    names: InfNames<String>,
+}
+
+#[pymethods]
+impl Infrastructure {
+    /// Node names.
+    #[getter]
+    fn nodes(&self) -> Vec<String> {
+        let mut nodes: Vec<_> = self.names.node_names.keys().cloned().collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Object names (signals, switches, detection sections, sights).
+    #[getter]
+    fn objects(&self) -> Vec<String> {
+        let mut objects: Vec<_> = self.names.object_names.keys().cloned().collect();
+        objects.sort();
+        objects
+    }
+}
+
+/// Routes for an infrastructure.
+#[pyclass]
+pub struct RouteSet {
+    routes: Routes<String>,
+}
+
+#[pymethods]
+impl RouteSet {
+    /// Route names.
+    #[getter]
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.routes.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// Dispatch plan.
+#[pyclass]
+pub struct Dispatch {
+    dispatch: dispatch::Dispatch<String>,
+}
+
+/// Simulation history.
+///
+/// `infrastructure` is a list of infrastructure events, and `trains` a list of trains,
+/// each with its `name`, `params` and list of `events`. Events are dicts with
+/// an `event` type, the simulation `time` and event-specific fields,
+/// with node and object names resolved.
+#[pyclass]
+pub struct History {
+    #[pyo3(get)]
+    infrastructure: Py<PyList>,
+    #[pyo3(get)]
+    trains: Py<PyList>,
+    history: history::History,
+    inf: Py<Infrastructure>,
+}
+
+#[pymethods]
+impl History {
+    /// Node visits as a list of `(train, time, node)` tuples.
+    fn visits(&self, py: Python) -> PyResult<Vec<(String, f64, String)>> {
+        let visits = history::visits(&self.inf.borrow(py).names, &self.history).map_err(to_py_err)?;
+        Ok(visits.lines().filter_map(|l| {
+            let mut w = l.split(' ');
+            match (w.next(), w.next().and_then(|t| t.parse().ok()), w.next()) {
+                (Some(train), Some(time), Some(node)) => Some((train.to_string(), time, node.to_string())),
+                _ => None,
+            }
+        }).collect())
+    }
+
+    /// The history in the JSON history format.
+    fn json(&self, py: Python) -> PyResult<String> {
+        let inf = self.inf.borrow(py);
+        let mut json = Vec::new();
+        rolling::output::json::json_history(&inf.inf, &inf.names, &self.history, &mut json).map_err(to_py_err)?;
+        Ok(String::from_utf8_lossy(&json).into_owned())
+    }
+}
+
+fn named(name: &str) -> Option<&str> {
+    if name.is_empty() { None } else { Some(name) }
+}
+
+fn params_dict<'py>(py: Python<'py>, p: &TrainParams) -> PyResult<Bound<'py, PyDict>> {
+    let d = PyDict::new_bound(py);
+    d.set_item("length", p.length)?;
+    d.set_item("max_acc", p.max_acc)?;
+    d.set_item("max_brk", p.max_brk)?;
+    d.set_item("max_vel", p.max_vel)?;
+    Ok(d)
+}
+
+fn infrastructure_events<'py>(py: Python<'py>, names: &Names, h: &history::History)
+                              -> PyResult<Bound<'py, PyList>> {
+    let list = PyList::empty_bound(py);
+    let object = |id| named(names.object(id));
+    let mut t = 0.0;
+    for ev in &h.inf {
+        let d = PyDict::new_bound(py);
+        d.set_item("time", t)?;
+        match *ev {
+            InfrastructureLogEvent::Wait(dt) => {
+                t += dt;
+                continue;
+            }
+            InfrastructureLogEvent::Route(id, status) => {
+                d.set_item("event", "route")?;
+                d.set_item("route", id)?;
+                d.set_item("status", match status {
+                    RouteStatus::Pending => "pending",
+                    RouteStatus::Active => "active",
+                    RouteStatus::Released => "released",
+                })?;
+            }
+            InfrastructureLogEvent::Authority(id, (authority, overlap)) => {
+                d.set_item("event", "authority")?;
+                d.set_item("signal", object(id))?;
+                d.set_item("authority", authority)?;
+                d.set_item("overlap", overlap)?;
+            }
+            InfrastructureLogEvent::Reserved(id, x) => {
+                d.set_item("event", "reserved")?;
+                d.set_item("tvd", object(id))?;
+                d.set_item("value", x)?;
+            }
+            InfrastructureLogEvent::Occupied(id, x, node, train) => {
+                d.set_item("event", "occupied")?;
+                d.set_item("tvd", object(id))?;
+                d.set_item("value", x)?;
+                d.set_item("node", named(names.node(node)))?;
+                d.set_item("train", h.trains.get(train).map(|t| t.0.clone()))?;
+            }
+            InfrastructureLogEvent::Position(id, pos) => {
+                d.set_item("event", "position")?;
+                d.set_item("switch", object(id))?;
+                d.set_item("position", match pos {
+                    SwitchPosition::Left => "left",
+                    SwitchPosition::Right => "right",
+                })?;
+            }
+        }
+        list.append(d)?;
+    }
+    Ok(list)
+}
+
+fn train_events<'py>(py: Python<'py>, names: &Names, events: &[TrainLogEvent])
+                     -> PyResult<Bound<'py, PyList>> {
+    let list = PyList::empty_bound(py);
+    let node = |id| named(names.node(id));
+    let mut t = 0.0;
+    let mut x = 0.0;
+    for ev in events {
+        let d = PyDict::new_bound(py);
+        match *ev {
+            TrainLogEvent::Wait(dt) => {
+                t += dt;
+                continue;
+            }
+            TrainLogEvent::Node(n) => {
+                d.set_item("event", "node")?;
+                d.set_item("node", node(n))?;
+            }
+            TrainLogEvent::Edge(a, b) => {
+                d.set_item("event", "edge")?;
+                d.set_item("start", node(a))?;
+                d.set_item("end", b.and_then(&node))?;
+            }
+            TrainLogEvent::Sight(id, value) => {
+                d.set_item("event", "sight")?;
+                d.set_item("signal", named(names.object(id)))?;
+                d.set_item("value", value)?;
+            }
+            TrainLogEvent::Move(dt, action, update) => {
+                t += dt;
+                x += update.dx;
+                d.set_item("event", "move")?;
+                d.set_item("dt", dt)?;
+                d.set_item("action", match action {
+                    DriverAction::Accel => "accel",
+                    DriverAction::Brake => "brake",
+                    DriverAction::Coast => "coast",
+                })?;
+                d.set_item("dx", update.dx)?;
+                d.set_item("v", update.v)?;
+            }
+        }
+        d.set_item("time", t)?;
+        d.set_item("x", x)?;
+        list.append(d)?;
+    }
+    Ok(list)
+}
+
+/// Parse infrastructure in the D-graph format.
+#[pyfunction]
+fn parse_infrastructure(input: &str) -> PyResult<Infrastructure> {
+    let (inf, names) = rolling::get_infrastructure_string(input).map_err(to_py_err)?;
+    Ok(Infrastructure { inf, names })
+}
+
+/// Load infrastructure in the D-graph format from a file.
+#[pyfunction]
+fn load_infrastructure(filename: &str) -> PyResult<Infrastructure> {
+    let (inf, names) = rolling::get_infrastructure(Path::new(filename)).map_err(to_py_err)?;
+    Ok(Infrastructure { inf, names })
+}
+
+/// Parse routes for the given infrastructure.
+#[pyfunction]
+fn parse_routes(inf: &Infrastructure, input: &str) -> PyResult<RouteSet> {
+    let routes = rolling::get_routes_string(input, &inf.names).map_err(to_py_err)?;
+    Ok(RouteSet { routes })
+}
+
+/// Load routes for the given infrastructure from a file.
+#[pyfunction]
+fn load_routes(inf: &Infrastructure, filename: &str) -> PyResult<RouteSet> {
+    let routes = rolling::get_routes(Path::new(filename), &inf.names).map_err(to_py_err)?;
+    Ok(RouteSet { routes })
+}
+
+/// Parse a dispatch plan.
+#[pyfunction]
+fn parse_dispatch(input: &str) -> PyResult<Dispatch> {
+    let dispatch = dispatch::parse_dispatch(input).map_err(|e| to_py_err(e.into()))?;
+    Ok(Dispatch { dispatch })
+}
+
+/// Load a dispatch plan from a file.
+#[pyfunction]
+fn load_dispatch(filename: &str) -> PyResult<Dispatch> {
+    let dispatch = rolling::get_dispatch(Path::new(filename)).map_err(to_py_err)?;
+    Ok(Dispatch { dispatch })
+}
+
+/// Simulate a dispatch plan. The signalling mode is `lineside`, `continuous` or `movingblock`.
+/// Routes in the plan which are not in the route set raise `ValueError`.
+#[pyfunction]
+#[pyo3(signature = (inf, routes, dispatch, timestep=None, signalling="lineside"))]
+fn evaluate_plan(py: Python, inf: Py<Infrastructure>, routes: &RouteSet, dispatch: &Dispatch,
+                 timestep: Option<f64>, signalling: &str) -> PyResult<History> {
+    let signalling: SignallingMode = signalling.parse().map_err(PyValueError::new_err)?;
+    let unknown = dispatch::unknown_routes(&dispatch.dispatch, &routes.routes);
+    if !unknown.is_empty() {
+        return Err(PyValueError::new_err(format!("unknown routes: {}", unknown.join(", "))));
+    }
+    let inf_ref = inf.borrow(py);
+    let (static_inf, routes, dispatch) = (&inf_ref.inf, &routes.routes, &dispatch.dispatch);
+    let h = py.allow_threads(|| rolling::evaluate_plan(static_inf, routes, dispatch, timestep, signalling));
+    let names = Names::new(&inf_ref.inf, &inf_ref.names);
+
+    let trains = PyList::empty_bound(py);
+    for (name, params, events) in &h.trains {
+        let d = PyDict::new_bound(py);
+        d.set_item("name", name)?;
+        d.set_item("params", params_dict(py, params)?)?;
+        d.set_item("events", train_events(py, &names, events)?)?;
+        trains.append(d)?;
+    }
+
+    let infrastructure = infrastructure_events(py, &names, &h)?.unbind();
+    drop(inf_ref);
+    Ok(History {
+        infrastructure,
+        trains: trains.unbind(),
+        history: h,
+        inf,
+    })
+}
+
+#[cfg(test)]
+mod tests;
+
+#[pymodule]
+fn pyrolling(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Infrastructure>()?;
+    m.add_class::<RouteSet>()?;
+    m.add_class::<Dispatch>()?;
+    m.add_class::<History>()?;
+    m.add_function(wrap_pyfunction!(parse_infrastructure, m)?)?;
+    m.add_function(wrap_pyfunction!(load_infrastructure, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_routes, m)?)?;
+    m.add_function(wrap_pyfunction!(load_routes, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_dispatch, m)?)?;
+    m.add_function(wrap_pyfunction!(load_dispatch, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_plan, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use pyo3::prelude::*;
+use crate::*;
+
+const INFRASTRUCTURE: &str = include_str!("../../docs/examples/simplestop/infrastructure");
+const ROUTES: &str = include_str!("../../docs/examples/simplestop/routes");
+
+fn simulate(plan: &str) -> PyResult<Vec<(String, f64, String)>> {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let inf = Py::new(py, parse_infrastructure(INFRASTRUCTURE)?)?;
+        let routes = parse_routes(&inf.borrow(py), ROUTES)?;
+        let history = evaluate_plan(py, inf, &routes, &parse_dispatch(plan)?, None, "lineside")?;
+        history.visits(py)
+    })
+}
+
+#[test]
+fn evaluate_plan_visits() {
+    let visits = simulate("train t1 l=35.0 a=1.0 b=1.0 v=10.0 ri\nwait 30.0\nroute re").unwrap();
+    let nodes = visits.iter().map(|v| v.2.as_str()).collect::<Vec<_>>();
+    assert_eq!(nodes.first(), Some(&"b1"));
+    assert_eq!(nodes.last(), Some(&"b2"));
+    assert!(visits.iter().all(|v| v.0 == "t1"));
+}
+
+#[test]
+fn evaluate_plan_unknown_routes() {
+    let err = simulate("train t1 l=35.0 a=1.0 b=1.0 v=10.0 ri\nroute rx\nroute ry").unwrap_err();
+    Python::with_gil(|py| {
+        assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+        assert_eq!(err.value_bound(py).to_string(), "unknown routes: rx, ry");
+    });
+}
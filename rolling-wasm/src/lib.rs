@@ -0,0 +1,98 @@
+//! WebAssembly interface to the rolling railway simulator.
+//!
+//! The infrastructure and routes are parsed once into a `Simulator`, which can then
+//! evaluate dispatch plans as they are edited, returning the history in the JSON history
+//! format used by the web viewer:
+//!
+//! ```js
+//! const sim = new Simulator(infrastructureText, routesText);
+//! const data = JSON.parse(sim.evaluate(dispatchText, 1.0, "lineside"));
+//! ```
+//!
+//! Errors are thrown as JavaScript exceptions with the error message. Dispatch plans
+//! are checked for unknown routes before the simulation, which would otherwise abort
+//! the WebAssembly instance.
+
+use wasm_bindgen::prelude::*;
+
+use rolling::input::dispatch::{self, Dispatch};
+use rolling::input::staticinfrastructure::{InfNames, Routes, StaticInfrastructure};
+use rolling::railway::SignallingMode;
+
+fn error<E: std::fmt::Display>(context: &str, e: E) -> JsValue {
+    JsValue::from_str(&format!("{}: {}", context, e))
+}
+
+fn parse_dispatch_text(input: &str) -> Result<Dispatch<String>, String> {
+    dispatch::parse_dispatch(input).map_err(|e| format!("error parsing dispatch plan: {}", e))
+}
+
+#[wasm_bindgen]
+pub struct Simulator {
+    inf: StaticInfrastructure,
+    names: InfNames<String>,
+    routes: Routes<String>,
+}
+
+impl Simulator {
+    /// Parse infrastructure and routes, reporting errors as strings.
+    pub fn parse(infrastructure: &str, routes: &str) -> Result<Simulator, String> {
+        let (inf, names) = rolling::get_infrastructure_string(infrastructure)
+            .map_err(|e| format!("error parsing infrastructure: {}", e))?;
+        let routes = rolling::get_routes_string(routes, &names)
+            .map_err(|e| format!("error parsing routes: {}", e))?;
+        Ok(Simulator { inf, names, routes })
+    }
+
+    /// Evaluate a dispatch plan, returning the JSON history, reporting errors as strings.
+    pub fn evaluate_json(&self, dispatch: &str, timestep: Option<f64>, signalling: &str) -> Result<String, String> {
+        let signalling: SignallingMode = signalling.parse()?;
+        let dispatch = parse_dispatch_text(dispatch)?;
+        let unknown = dispatch::unknown_routes(&dispatch, &self.routes);
+        if !unknown.is_empty() {
+            return Err(format!("unknown routes: {}", unknown.join(", ")));
+        }
+        let history = rolling::evaluate_plan(&self.inf, &self.routes, &dispatch, timestep, signalling);
+        let mut json = Vec::new();
+        rolling::output::json::json_history(&self.inf, &self.names, &history, &mut json)
+            .map_err(|e| format!("error writing history: {}", e))?;
+        String::from_utf8(json).map_err(|e| e.to_string())
+    }
+}
+
+#[wasm_bindgen]
+impl Simulator {
+    /// Parse infrastructure (D-graph format) and routes.
+    #[wasm_bindgen(constructor)]
+    pub fn new(infrastructure: &str, routes: &str) -> Result<Simulator, JsValue> {
+        Simulator::parse(infrastructure, routes).map_err(JsValue::from)
+    }
+
+    /// Node names of the infrastructure.
+    #[wasm_bindgen(js_name = nodeNames)]
+    pub fn node_names(&self) -> Vec<JsValue> {
+        let mut names: Vec<_> = self.names.node_names.keys().collect();
+        names.sort();
+        names.into_iter().map(|n| JsValue::from_str(n)).collect()
+    }
+
+    /// Route names.
+    #[wasm_bindgen(js_name = routeNames)]
+    pub fn route_names(&self) -> Vec<JsValue> {
+        let mut names: Vec<_> = self.routes.keys().collect();
+        names.sort();
+        names.into_iter().map(|n| JsValue::from_str(n)).collect()
+    }
+
+    /// Evaluate a dispatch plan and return the history as JSON.
+    /// The signalling mode is `lineside`, `continuous` or `movingblock`.
+    pub fn evaluate(&self, dispatch: &str, timestep: Option<f64>, signalling: &str) -> Result<String, JsValue> {
+        self.evaluate_json(dispatch, timestep, signalling).map_err(JsValue::from)
+    }
+}
+
+/// Check a dispatch plan for syntax errors.
+#[wasm_bindgen(js_name = validateDispatch)]
+pub fn validate_dispatch(dispatch: &str) -> Result<(), JsValue> {
+    parse_dispatch_text(dispatch).map(|_| ()).map_err(|e| error("invalid dispatch plan", e))
+}
@@ -0,0 +1,37 @@
+//! Headless test of the WebAssembly interface, run with `wasm-pack test --node`.
+
+use rolling_wasm::*;
+use wasm_bindgen_test::*;
+
+const INFRASTRUCTURE: &str = include_str!("../../docs/examples/overlap/infrastructure");
+const ROUTES: &str = include_str!("../../docs/examples/overlap/routes");
+const PLAN: &str = include_str!("../../docs/examples/overlap/plan");
+
+#[wasm_bindgen_test]
+fn evaluate_example() {
+    let sim = Simulator::new(INFRASTRUCTURE, ROUTES).unwrap();
+    let json = sim.evaluate(PLAN, Some(1.0), "lineside").unwrap();
+    assert!(json.starts_with("{\"version\":"));
+    assert!(json.contains("\"trains\":{\"t1\":"));
+}
+
+#[wasm_bindgen_test]
+fn edited_dispatch() {
+    let sim = Simulator::new(INFRASTRUCTURE, ROUTES).unwrap();
+    let first = sim.evaluate(PLAN, Some(1.0), "lineside").unwrap();
+    let edited = PLAN.lines().take(1).collect::<Vec<_>>().join("\n");
+    let second = sim.evaluate(&edited, Some(1.0), "lineside").unwrap();
+    assert!(second.len() < first.len());
+}
+
+#[wasm_bindgen_test]
+fn errors() {
+    assert!(Simulator::new("node a --", ROUTES).is_err());
+    assert!(validate_dispatch("unknown statement").is_err());
+    assert!(validate_dispatch(PLAN).is_ok());
+    let sim = Simulator::new(INFRASTRUCTURE, ROUTES).unwrap();
+    assert!(sim.evaluate(PLAN, None, "cabsignal").is_err());
+    assert!(sim.evaluate("route nonexistent", None, "lineside").is_err());
+    let after = sim.evaluate(PLAN, Some(1.0), "lineside");
+    assert!(after.is_ok());
+}
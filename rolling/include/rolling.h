@@ -0,0 +1,174 @@
+/* C interface to the rolling railway simulator.
+ *
+ * Generated from src/ffi.rs with cbindgen (see cbindgen.toml and `make header`).
+ * Link with librolling.a (and -lpthread -ldl -lm on Linux).
+ *
+ * Functions returning a pointer return NULL on failure, and functions returning
+ * an ErrorCode return ErrorCode_Ok on success. The error code and message of the
+ * last failure on the calling thread are available from last_error_code and
+ * last_error_message. */
+
+#ifndef ROLLING_H
+#define ROLLING_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum ErrorCode {
+  ErrorCode_Ok = 0,
+  ErrorCode_NullPointer = 1,
+  ErrorCode_InvalidString = 2,
+  ErrorCode_Io = 3,
+  ErrorCode_Parse = 4,
+  ErrorCode_Evaluation = 5,
+  ErrorCode_InvalidArgument = 6,
+} ErrorCode;
+
+typedef enum InfrastructureEventKind {
+  InfrastructureEventKind_Wait = 0,
+  InfrastructureEventKind_Route = 1,
+  InfrastructureEventKind_Authority = 2,
+  InfrastructureEventKind_Reserved = 3,
+  InfrastructureEventKind_Occupied = 4,
+  InfrastructureEventKind_Position = 5,
+} InfrastructureEventKind;
+
+typedef enum TrainEventKind {
+  TrainEventKind_Wait = 0,
+  TrainEventKind_Node = 1,
+  TrainEventKind_Edge = 2,
+  TrainEventKind_Sight = 3,
+  TrainEventKind_Move = 4,
+} TrainEventKind;
+
+typedef struct Dispatch Dispatch;
+
+typedef struct History History;
+
+typedef struct Infrastructure Infrastructure;
+
+typedef struct Routes Routes;
+
+typedef struct TrainParams {
+  double length;
+  double max_acc;
+  double max_brk;
+  double max_vel;
+} TrainParams;
+
+/* Infrastructure event. Fields which are not used by the event kind are zero. */
+typedef struct InfrastructureEvent {
+  InfrastructureEventKind kind;
+  /* Time delta (Wait). */
+  double dt;
+  /* Route index (Route), signal (Authority), TVD (Reserved, Occupied) or switch (Position) object id. */
+  size_t id;
+  /* Route status (0 pending, 1 active, 2 released), reserved/occupied (0/1),
+   * switch position (0 left, 1 right), or authority given (0/1). */
+  int value;
+  /* Authority length (Authority), or NaN if none. */
+  double authority;
+  /* Overlap length (Authority), or NaN if none. */
+  double overlap;
+  /* Node where the train entered or left the section (Occupied). */
+  size_t node;
+  /* Index of the train (Occupied). */
+  size_t train;
+} InfrastructureEvent;
+
+/* Train event. Fields which are not used by the event kind are zero. */
+typedef struct TrainEvent {
+  TrainEventKind kind;
+  /* Time delta (Wait, Move). */
+  double dt;
+  /* Node id (Node), edge start node (Edge) or signal object id (Sight). */
+  size_t id;
+  /* Edge end node (Edge), or -1 if the train is exiting the model. */
+  int64_t other_node;
+  /* Sight gained/lost (0/1) (Sight), or driver action (0 accelerate, 1 brake, 2 coast) (Move). */
+  int value;
+  /* Distance travelled (Move). */
+  double dx;
+  /* Velocity after the movement (Move). */
+  double v;
+} TrainEvent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Error code of the last failed call on this thread, or ErrorCode_Ok if the last call succeeded. */
+ErrorCode last_error_code(void);
+
+/* Error message of the last failed call on this thread, or NULL if the last call succeeded.
+ * The string is owned by the library and is valid until the next call on this thread. */
+const char *last_error_message(void);
+
+Infrastructure *parse_infrastructure_file(const char *filename);
+
+Infrastructure *parse_infrastructure(const char *input);
+
+Routes *parse_routes_file(Infrastructure *inf, const char *filename);
+
+Dispatch *parse_dispatch(const char *input);
+
+Dispatch *parse_dispatch_file(const char *filename);
+
+/* Evaluate a dispatch plan. A timestep which is zero or negative
+ * uses the default (unlimited) time step. The signalling mode is
+ * 0 for lineside, 1 for continuous and 2 for moving block signalling. */
+History *eval_history(Infrastructure *inf,
+                      Routes *routes,
+                      Dispatch *dis,
+                      double timestep,
+                      int signalling);
+
+char *eval_simplified(Infrastructure *inf, Routes *routes, Dispatch *dis);
+
+/* Evaluate a dispatch plan and return the node visits (see history_visits). */
+char *eval_simplified_timestep(Infrastructure *inf, Routes *routes, Dispatch *dis, double timestep);
+
+/* Node visits, one per line on the format `trainname time nodename`. */
+char *history_visits(Infrastructure *inf, History *history);
+
+/* The full history as JSON (see the JSON history format in the documentation). */
+char *history_json(Infrastructure *inf, History *history);
+
+/* Name of a node, or NULL if there is no node with the given id. */
+char *node_name(Infrastructure *inf, size_t node);
+
+/* Name of an infrastructure object, or NULL if there is no object with the given id. */
+char *object_name(Infrastructure *inf, size_t object);
+
+size_t history_num_infrastructure_events(History *history);
+
+ErrorCode history_infrastructure_event(History *history, size_t index, InfrastructureEvent *out);
+
+size_t history_num_trains(History *history);
+
+char *history_train_name(History *history, size_t train);
+
+ErrorCode history_train_params(History *history, size_t train, TrainParams *out);
+
+size_t history_train_num_events(History *history, size_t train);
+
+ErrorCode history_train_event(History *history, size_t train, size_t index, TrainEvent *out);
+
+void free_string(char *s);
+
+void free_infrastructure(Infrastructure *x);
+
+void free_routes(Routes *x);
+
+void free_dispatch(Dispatch *x);
+
+void free_history(History *x);
+
+#ifdef __cplusplus
+} /* extern "C" */
+#endif
+
+#endif /* ROLLING_H */
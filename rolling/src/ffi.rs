@@ -1,94 +1,527 @@
+//! C interface to the parsers and the simulator.
+//!
+//! Objects are created and freed through this interface and passed around as opaque pointers.
+//! Functions returning a pointer return null on failure, and functions returning an
+//! `ErrorCode` return `ErrorCode::Ok` on success. In both cases, the error code and message
+//! of the last failure on the calling thread can be retrieved with `last_error_code` and
+//! `last_error_message`.
+//!
+//! The C declarations are in `include/rolling.h`.
+
 use super::*;
 use input::*;
 use input::staticinfrastructure::*;
+use output::history::{History, TrainLogEvent, InfrastructureLogEvent, RouteStatus};
+use railway::dynamics::DriverAction;
+use railway::SignallingMode;
 
 use std;
+use std::cell::RefCell;
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+type Infrastructure = (StaticInfrastructure, InfNames<String>);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidString = 2,
+    Io = 3,
+    Parse = 4,
+    Evaluation = 5,
+    InvalidArgument = 6,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<(ErrorCode, Option<CString>)> = RefCell::new((ErrorCode::Ok, None));
+}
+
+fn set_error(code: ErrorCode, msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).ok();
+    LAST_ERROR.with(|e| *e.borrow_mut() = (code, msg));
+}
 
+fn clear_error() {
+    LAST_ERROR.with(|e| *e.borrow_mut() = (ErrorCode::Ok, None));
+}
+
+/// Classify an error from the parsers, which may also fail on reading files.
+fn parse_error(context: &str, e: failure::Error) -> ErrorCode {
+    let code = if e.downcast_ref::<std::io::Error>().is_some() { ErrorCode::Io } else { ErrorCode::Parse };
+    set_error(code, format!("{}: {}", context, e));
+    code
+}
+
+macro_rules! check_null {
+    ($ret:expr, $($p:ident),*) => {
+        $(if $p.is_null() {
+            set_error(ErrorCode::NullPointer, format!("null pointer given for {}", stringify!($p)));
+            return $ret;
+        })*
+    }
+}
+
+unsafe fn get_str<'a>(s: *const c_char) -> Result<&'a str, ()> {
+    CStr::from_ptr(s).to_str().map_err(|e| {
+        set_error(ErrorCode::InvalidString, format!("string is not valid UTF-8: {}", e));
+    })
+}
+
+fn new_string(s: String) -> *mut c_char {
+    match CString::new(s) {
+        Ok(x) => x.into_raw(),
+        Err(e) => {
+            set_error(ErrorCode::InvalidString, format!("string contains a null byte: {}", e));
+            ptr::null_mut()
+        }
+    }
+}
+
+fn signalling_mode(mode: c_int) -> Option<SignallingMode> {
+    match mode {
+        0 => Some(SignallingMode::Lineside),
+        1 => Some(SignallingMode::Continuous),
+        2 => Some(SignallingMode::MovingBlock),
+        _ => {
+            set_error(ErrorCode::InvalidArgument, format!("unknown signalling mode {}", mode));
+            None
+        }
+    }
+}
+
+/// Error code of the last failed call on this thread, or `Ok` if the last call succeeded.
+#[no_mangle]
+pub extern fn last_error_code() -> ErrorCode {
+    LAST_ERROR.with(|e| e.borrow().0)
+}
+
+/// Error message of the last failed call on this thread, or null if the last call succeeded.
+/// The string is owned by the library and is valid until the next call on this thread.
+#[no_mangle]
+pub extern fn last_error_message() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().1.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()))
+}
 
 #[no_mangle]
-pub unsafe extern fn parse_infrastructure_file(filename :*const c_char) 
-    -> *mut (StaticInfrastructure,InfNames<String>) {
-    let filename =  CStr::from_ptr(filename).to_str().unwrap();
-    println!("rolling ffi: loading {}",filename);
+pub unsafe extern fn parse_infrastructure_file(filename :*const c_char) -> *mut Infrastructure {
+    check_null!(ptr::null_mut(), filename);
+    let filename = match get_str(filename) { Ok(x) => x, Err(()) => return ptr::null_mut() };
     match get_infrastructure(Path::new(filename)) {
-        Ok(inf) => Box::into_raw(Box::new(inf)),
+        Ok(inf) => { clear_error(); Box::into_raw(Box::new(inf)) },
         Err(e) => {
-            println!("Error parsing infrastructure: {}", e);
-            std::ptr::null_mut()
+            parse_error("error parsing infrastructure", e);
+            ptr::null_mut()
         }
     }
 }
 
+#[no_mangle]
+pub unsafe extern fn parse_infrastructure(input :*const c_char) -> *mut Infrastructure {
+    check_null!(ptr::null_mut(), input);
+    let input = match get_str(input) { Ok(x) => x, Err(()) => return ptr::null_mut() };
+    match get_infrastructure_string(input) {
+        Ok(inf) => { clear_error(); Box::into_raw(Box::new(inf)) },
+        Err(e) => {
+            parse_error("error parsing infrastructure", e);
+            ptr::null_mut()
+        }
+    }
+}
 
 #[no_mangle]
-pub unsafe extern fn parse_routes_file(inf :*mut (StaticInfrastructure, InfNames<String>), 
-                                       filename :*const c_char) 
+pub unsafe extern fn parse_routes_file(inf :*mut Infrastructure, filename :*const c_char)
     -> *mut Routes<String> {
-    let filename =  CStr::from_ptr(filename).to_str().unwrap();
+    check_null!(ptr::null_mut(), inf, filename);
+    let filename = match get_str(filename) { Ok(x) => x, Err(()) => return ptr::null_mut() };
     match get_routes(Path::new(filename), &((*inf).1)) {
-        Ok(routes) => Box::into_raw(Box::new(routes)),
+        Ok(routes) => { clear_error(); Box::into_raw(Box::new(routes)) },
         Err(e) => {
-            println!("Error parsing routes: {:?}", e);
-            std::ptr::null_mut()
+            parse_error("error parsing routes", e);
+            ptr::null_mut()
         }
     }
 }
 
 #[no_mangle]
 pub unsafe extern fn parse_dispatch(input :*const c_char) -> *mut dispatch::Dispatch<String> {
-    let input = CStr::from_ptr(input).to_str().unwrap();
+    check_null!(ptr::null_mut(), input);
+    let input = match get_str(input) { Ok(x) => x, Err(()) => return ptr::null_mut() };
     match dispatch::parse_dispatch(input) {
-        Ok(dispatch) => Box::into_raw(Box::new(dispatch)),
+        Ok(dispatch) => { clear_error(); Box::into_raw(Box::new(dispatch)) },
         Err(e) => {
-            println!("Error parsing dispatch plan: {:?}", e);
-            std::ptr::null_mut()
+            parse_error("error parsing dispatch plan", e.into());
+            ptr::null_mut()
         }
     }
 }
 
 #[no_mangle]
-pub unsafe extern fn eval_simplified(inf :*mut (StaticInfrastructure, InfNames<String>),
-                                    routes: *mut Routes<String>,
-                             dis :*mut dispatch::Dispatch<String>) -> *mut c_char {
-    let result = evaluate_plan(&((*inf).0),&*routes,&*dis, None);
-    let result = output::history::visits(&((*inf).1), &result);
+pub unsafe extern fn parse_dispatch_file(filename :*const c_char) -> *mut dispatch::Dispatch<String> {
+    check_null!(ptr::null_mut(), filename);
+    let filename = match get_str(filename) { Ok(x) => x, Err(()) => return ptr::null_mut() };
+    match get_dispatch(Path::new(filename)) {
+        Ok(dispatch) => { clear_error(); Box::into_raw(Box::new(dispatch)) },
+        Err(e) => {
+            parse_error("error parsing dispatch plan", e);
+            ptr::null_mut()
+        }
+    }
+}
 
+/// Evaluate a dispatch plan. A `timestep` which is zero or negative
+/// uses the default (unlimited) time step. The `signalling` mode is
+/// 0 for lineside, 1 for continuous and 2 for moving block signalling.
+#[no_mangle]
+pub unsafe extern fn eval_history(inf :*mut Infrastructure,
+                                  routes: *mut Routes<String>,
+                                  dis :*mut dispatch::Dispatch<String>,
+                                  timestep :f64,
+                                  signalling :c_int) -> *mut History {
+    check_null!(ptr::null_mut(), inf, routes, dis);
+    let signalling = match signalling_mode(signalling) { Some(x) => x, None => return ptr::null_mut() };
+    let timestep = if timestep > 0.0 { Some(timestep) } else { None };
+    let result = std::panic::catch_unwind(|| {
+        evaluate_plan(&((*inf).0), &*routes, &*dis, timestep, signalling)
+    });
     match result {
-        Ok(string) => {
-          let x = CString::new(string).unwrap();
-          x.into_raw()
-        },
+        Ok(history) => { clear_error(); Box::into_raw(Box::new(history)) },
+        Err(panic) => {
+            let msg = panic.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "simulation failed".to_string());
+            set_error(ErrorCode::Evaluation, format!("error evaluating plan: {}", msg));
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern fn eval_simplified(inf :*mut Infrastructure,
+                                     routes: *mut Routes<String>,
+                                     dis :*mut dispatch::Dispatch<String>) -> *mut c_char {
+    eval_simplified_timestep(inf, routes, dis, 0.0)
+}
+
+/// Evaluate a dispatch plan and return the node visits (see `history_visits`).
+#[no_mangle]
+pub unsafe extern fn eval_simplified_timestep(inf :*mut Infrastructure,
+                                              routes: *mut Routes<String>,
+                                              dis :*mut dispatch::Dispatch<String>,
+                                              timestep :f64) -> *mut c_char {
+    let history = eval_history(inf, routes, dis, timestep, 0);
+    if history.is_null() { return ptr::null_mut(); }
+    let result = history_visits(inf, history);
+    drop(Box::from_raw(history));
+    result
+}
+
+/// Node visits, one per line on the format `trainname time nodename`.
+#[no_mangle]
+pub unsafe extern fn history_visits(inf :*mut Infrastructure, history :*mut History) -> *mut c_char {
+    check_null!(ptr::null_mut(), inf, history);
+    match output::history::visits(&((*inf).1), &*history) {
+        Ok(string) => { clear_error(); new_string(string) },
         Err(e) => {
-            println!("Error evaluating plan: {:?}", e);
-            std::ptr::null_mut()
+            set_error(ErrorCode::Evaluation, format!("error writing visits: {}", e));
+            ptr::null_mut()
         }
     }
 }
 
+/// The full history as JSON (see the JSON history format in the documentation).
+#[no_mangle]
+pub unsafe extern fn history_json(inf :*mut Infrastructure, history :*mut History) -> *mut c_char {
+    check_null!(ptr::null_mut(), inf, history);
+    let mut buf = Vec::new();
+    match output::json::json_history(&((*inf).0), &((*inf).1), &*history, &mut buf) {
+        Ok(()) => { clear_error(); new_string(String::from_utf8_lossy(&buf).into_owned()) },
+        Err(e) => {
+            set_error(ErrorCode::Evaluation, format!("error writing JSON history: {}", e));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Name of a node, or null if there is no node with the given id.
+#[no_mangle]
+pub unsafe extern fn node_name(inf :*mut Infrastructure, node :usize) -> *mut c_char {
+    check_null!(ptr::null_mut(), inf);
+    match (&*inf).1.node_names.iter().find(|&(_, &v)| v == node) {
+        Some((name, _)) => { clear_error(); new_string(name.clone()) },
+        None => {
+            set_error(ErrorCode::InvalidArgument, format!("unknown node id {}", node));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Name of an infrastructure object, or null if there is no object with the given id.
+#[no_mangle]
+pub unsafe extern fn object_name(inf :*mut Infrastructure, object :usize) -> *mut c_char {
+    check_null!(ptr::null_mut(), inf);
+    match (&*inf).1.object_names.iter().find(|&(_, &v)| v == object) {
+        Some((name, _)) => { clear_error(); new_string(name.clone()) },
+        None => {
+            set_error(ErrorCode::InvalidArgument, format!("unknown object id {}", object));
+            ptr::null_mut()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TrainParams {
+    pub length: f64,
+    pub max_acc: f64,
+    pub max_brk: f64,
+    pub max_vel: f64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InfrastructureEventKind {
+    Wait = 0,
+    Route = 1,
+    Authority = 2,
+    Reserved = 3,
+    Occupied = 4,
+    Position = 5,
+}
+
+/// Infrastructure event. Fields which are not used by the event kind are zero.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct InfrastructureEvent {
+    pub kind: InfrastructureEventKind,
+    /// Time delta (`Wait`).
+    pub dt: f64,
+    /// Route index (`Route`), signal (`Authority`), TVD (`Reserved`, `Occupied`) or switch (`Position`) object id.
+    pub id: usize,
+    /// Route status (0 pending, 1 active, 2 released), reserved/occupied (0/1),
+    /// switch position (0 left, 1 right), or authority given (0/1).
+    pub value: c_int,
+    /// Authority length (`Authority`), or NaN if none.
+    pub authority: f64,
+    /// Overlap length (`Authority`), or NaN if none.
+    pub overlap: f64,
+    /// Node where the train entered or left the section (`Occupied`).
+    pub node: usize,
+    /// Index of the train (`Occupied`).
+    pub train: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrainEventKind {
+    Wait = 0,
+    Node = 1,
+    Edge = 2,
+    Sight = 3,
+    Move = 4,
+}
+
+/// Train event. Fields which are not used by the event kind are zero.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TrainEvent {
+    pub kind: TrainEventKind,
+    /// Time delta (`Wait`, `Move`).
+    pub dt: f64,
+    /// Node id (`Node`), edge start node (`Edge`) or signal object id (`Sight`).
+    pub id: usize,
+    /// Edge end node (`Edge`), or -1 if the train is exiting the model.
+    pub other_node: i64,
+    /// Sight gained/lost (0/1) (`Sight`), or driver action (0 accelerate, 1 brake, 2 coast) (`Move`).
+    pub value: c_int,
+    /// Distance travelled (`Move`).
+    pub dx: f64,
+    /// Velocity after the movement (`Move`).
+    pub v: f64,
+}
+
+#[no_mangle]
+pub unsafe extern fn history_num_infrastructure_events(history :*mut History) -> usize {
+    check_null!(0, history);
+    clear_error();
+    (&*history).inf.len()
+}
+
+#[no_mangle]
+pub unsafe extern fn history_infrastructure_event(history :*mut History, index :usize,
+                                                  out :*mut InfrastructureEvent) -> ErrorCode {
+    check_null!(ErrorCode::NullPointer, history, out);
+    let ev = match (&*history).inf.get(index) {
+        Some(ev) => ev,
+        None => {
+            set_error(ErrorCode::InvalidArgument, format!("infrastructure event index {} out of range", index));
+            return ErrorCode::InvalidArgument;
+        }
+    };
+    let mut r = InfrastructureEvent {
+        kind: InfrastructureEventKind::Wait,
+        dt: 0.0, id: 0, value: 0, authority: 0.0, overlap: 0.0, node: 0, train: 0,
+    };
+    match *ev {
+        InfrastructureLogEvent::Wait(dt) => r.dt = dt,
+        InfrastructureLogEvent::Route(id, status) => {
+            r.kind = InfrastructureEventKind::Route;
+            r.id = id;
+            r.value = match status {
+                RouteStatus::Pending => 0,
+                RouteStatus::Active => 1,
+                RouteStatus::Released => 2,
+            };
+        },
+        InfrastructureLogEvent::Authority(id, (authority, overlap)) => {
+            r.kind = InfrastructureEventKind::Authority;
+            r.id = id;
+            r.value = authority.is_some() as c_int;
+            r.authority = authority.unwrap_or(std::f64::NAN);
+            r.overlap = overlap.unwrap_or(std::f64::NAN);
+        },
+        InfrastructureLogEvent::Reserved(id, x) => {
+            r.kind = InfrastructureEventKind::Reserved;
+            r.id = id;
+            r.value = x as c_int;
+        },
+        InfrastructureLogEvent::Occupied(id, x, node, train) => {
+            r.kind = InfrastructureEventKind::Occupied;
+            r.id = id;
+            r.value = x as c_int;
+            r.node = node;
+            r.train = train;
+        },
+        InfrastructureLogEvent::Position(id, pos) => {
+            r.kind = InfrastructureEventKind::Position;
+            r.id = id;
+            r.value = match pos { SwitchPosition::Left => 0, SwitchPosition::Right => 1 };
+        },
+    }
+    *out = r;
+    clear_error();
+    ErrorCode::Ok
+}
+
+#[no_mangle]
+pub unsafe extern fn history_num_trains(history :*mut History) -> usize {
+    check_null!(0, history);
+    clear_error();
+    (&*history).trains.len()
+}
+
+fn train_index(history: &History, train: usize) -> Result<&(String, railway::dynamics::TrainParams, Vec<TrainLogEvent>), ErrorCode> {
+    history.trains.get(train).ok_or_else(|| {
+        set_error(ErrorCode::InvalidArgument, format!("train index {} out of range", train));
+        ErrorCode::InvalidArgument
+    })
+}
+
+#[no_mangle]
+pub unsafe extern fn history_train_name(history :*mut History, train :usize) -> *mut c_char {
+    check_null!(ptr::null_mut(), history);
+    match train_index(&*history, train) {
+        Ok(&(ref name, _, _)) => { clear_error(); new_string(name.clone()) },
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern fn history_train_params(history :*mut History, train :usize,
+                                          out :*mut TrainParams) -> ErrorCode {
+    check_null!(ErrorCode::NullPointer, history, out);
+    match train_index(&*history, train) {
+        Ok(&(_, ref p, _)) => {
+            *out = TrainParams { length: p.length, max_acc: p.max_acc, max_brk: p.max_brk, max_vel: p.max_vel };
+            clear_error();
+            ErrorCode::Ok
+        },
+        Err(code) => code,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern fn history_train_num_events(history :*mut History, train :usize) -> usize {
+    check_null!(0, history);
+    match train_index(&*history, train) {
+        Ok(&(_, _, ref events)) => { clear_error(); events.len() },
+        Err(_) => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern fn history_train_event(history :*mut History, train :usize, index :usize,
+                                         out :*mut TrainEvent) -> ErrorCode {
+    check_null!(ErrorCode::NullPointer, history, out);
+    let events = match train_index(&*history, train) {
+        Ok(&(_, _, ref events)) => events,
+        Err(code) => return code,
+    };
+    let ev = match events.get(index) {
+        Some(ev) => ev,
+        None => {
+            set_error(ErrorCode::InvalidArgument, format!("train event index {} out of range", index));
+            return ErrorCode::InvalidArgument;
+        }
+    };
+    let mut r = TrainEvent { kind: TrainEventKind::Wait, dt: 0.0, id: 0, other_node: 0, value: 0, dx: 0.0, v: 0.0 };
+    match *ev {
+        TrainLogEvent::Wait(dt) => r.dt = dt,
+        TrainLogEvent::Node(n) => {
+            r.kind = TrainEventKind::Node;
+            r.id = n;
+        },
+        TrainLogEvent::Edge(a, b) => {
+            r.kind = TrainEventKind::Edge;
+            r.id = a;
+            r.other_node = b.map(|b| b as i64).unwrap_or(-1);
+        },
+        TrainLogEvent::Sight(id, x) => {
+            r.kind = TrainEventKind::Sight;
+            r.id = id;
+            r.value = x as c_int;
+        },
+        TrainLogEvent::Move(dt, action, update) => {
+            r.kind = TrainEventKind::Move;
+            r.dt = dt;
+            r.value = match action {
+                DriverAction::Accel => 0,
+                DriverAction::Brake => 1,
+                DriverAction::Coast => 2,
+            };
+            r.dx = update.dx;
+            r.v = update.v;
+        },
+    }
+    *out = r;
+    clear_error();
+    ErrorCode::Ok
+}
+
 #[no_mangle]
 pub unsafe extern fn free_string(s :*mut c_char) {
-    println!("freeing string");
-     CString::from_raw(s); 
+    if !s.is_null() { drop(CString::from_raw(s)); }
 }
 
 #[no_mangle]
-pub unsafe extern fn free_infrastructure(x :*mut StaticInfrastructure) {
-    println!("Freeing infrastructure");
-    Box::from_raw(x);
+pub unsafe extern fn free_infrastructure(x :*mut Infrastructure) {
+    if !x.is_null() { drop(Box::from_raw(x)); }
 }
 
 #[no_mangle]
 pub unsafe extern fn free_routes(x :*mut Routes<String>) {
-    println!("freeing routes");
-    Box::from_raw(x);
+    if !x.is_null() { drop(Box::from_raw(x)); }
 }
 
 #[no_mangle]
 pub unsafe extern fn free_dispatch(x :*mut dispatch::Dispatch<String>) {
-    println!("freeing dispatch");
-    Box::from_raw(x);
+    if !x.is_null() { drop(Box::from_raw(x)); }
 }
 
+#[no_mangle]
+pub unsafe extern fn free_history(x :*mut History) {
+    if !x.is_null() { drop(Box::from_raw(x)); }
+}
@@ -1,5 +1,7 @@
 use railway::dynamics::TrainParams;
+use railway::driver::{DriverProfile, CoastingStrategy};
 use regex::Regex;
+use std::collections::HashMap;
 
 type TrainName = String;
 
@@ -12,7 +14,7 @@ pub struct Dispatch<RouteRef> {
 pub enum DispatchAction<RouteRef> {
     Wait(Option<f64>),
     Route(RouteRef),
-    Train(TrainName, TrainParams, RouteRef), // train name, train params, entry route name
+    Train(TrainName, TrainParams, DriverProfile, RouteRef), // train name, train params, driver, entry route name
 }
 
 
@@ -24,6 +26,36 @@ pub enum ParseError {
     NumberError,
     #[fail(display = "unrecognized dispatch: {}", _0)]
     Unrecognized(String),
+    #[fail(display = "unknown driver profile: {}", _0)]
+    UnknownDriver(String),
+    #[fail(display = "invalid driver parameter: {}", _0)]
+    InvalidDriverParameter(String),
+}
+
+/// Driver profile from the `name=value` parameters of a `driver` statement.
+/// The acceleration and coasting fractions must be in (0, 1].
+pub fn driver_profile(param_re: &Regex, params: &str, line: &str) -> Result<DriverProfile, ParseError> {
+    let mut profile = DriverProfile::default();
+    for param in param_re.captures_iter(params) {
+        let number = || param[2].parse::<f64>().map_err(|_e| ParseError::NumberError);
+        match &param[1] {
+            "margin" => profile.safety_margin = number()?,
+            "reaction" => profile.reaction_time = number()?,
+            "accel" => profile.acceleration_fraction = number()?,
+            "coast" => profile.coasting = match &param[2] {
+                "never" => CoastingStrategy::Never,
+                _ => CoastingStrategy::AtVelocity(number()?),
+            },
+            _ => return Err(ParseError::Unrecognized(line.to_string())),
+        }
+    }
+    let fraction = |f: f64| f > 0.0 && f <= 1.0;
+    if !(profile.safety_margin >= 0.0 && profile.reaction_time >= 0.0 &&
+         fraction(profile.acceleration_fraction) &&
+         match profile.coasting { CoastingStrategy::AtVelocity(f) => fraction(f), _ => true }) {
+        return Err(ParseError::InvalidDriverParameter(line.to_string()));
+    }
+    Ok(profile)
 }
 
 /// Parses dispatch plan format
@@ -31,6 +63,8 @@ pub enum ParseError {
 /// * wait 10.0
 /// * route rb1
 /// * train t1 (b1 -> 200.0) l=200.0 a=1.0 b=0.5 v=10.0
+/// * driver cautious margin=50.0 reaction=2.0 accel=0.8 coast=0.9
+/// * train t1 l=200.0 a=1.0 b=0.5 v=10.0 driver=cautious b1
 ///
 pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
     let mut actions = Vec::new();
@@ -45,8 +79,15 @@ pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
             a \s* = \s* (?P<acc>[\d\.]+) \s+
             b \s* = \s* (?P<brk>[\d\.]+) \s+
             v \s* = \s* (?P<vel>[\d\.]+) \s+
+            (?: driver \s* = \s* (?P<driver>\w+) \s+ )?
             (?P<route>\w+) \s*
             $").map_err(|e| ParseError::RegexError(format!("{:?}", e)))?;
+    let driver_re = Regex::new(r"(?x) ^ \s* driver \s+ (?P<name>\w+)
+            (?P<params> (?: \s+ \w+ \s* = \s* [\w\.]+ )* ) \s*
+            $").map_err(|e| ParseError::RegexError(format!("{:?}", e)))?;
+    let param_re = Regex::new(r"(\w+)\s*=\s*([\w\.]+)")
+        .map_err(|e| ParseError::RegexError(format!("{:?}",e)))?;
+    let mut drivers = HashMap::new();
     for line in input.lines() {
         if let Some(groups) = wait_time_re.captures(line) {
             let time = groups[1].parse::<f64>().map_err(|_e| ParseError::NumberError)?;
@@ -61,7 +102,17 @@ pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
             actions.push(DispatchAction::Route(groups[1].to_string()));
             continue;
         }
+        if let Some(groups) = driver_re.captures(line) {
+            let profile = driver_profile(&param_re, &groups["params"], line)?;
+            drivers.insert(groups["name"].to_string(), profile);
+            continue;
+        }
         if let Some(groups) = train_re.captures(line) {
+            let profile = match groups.name("driver") {
+                Some(name) => *drivers.get(name.as_str())
+                    .ok_or_else(|| ParseError::UnknownDriver(name.as_str().to_string()))?,
+                None => DriverProfile::default(),
+            };
             actions.push(DispatchAction::Train(groups["name"].to_string(),
                                                TrainParams {
                                                    length: groups["len"].parse::<f64>()
@@ -73,6 +124,7 @@ pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
                                                    max_vel: groups["vel"].parse::<f64>()
                                                        .map_err(|_e| ParseError::NumberError)?,
                                                },
+                                               profile,
                                                groups["route"].to_string()));
             continue;
         }
@@ -81,3 +133,43 @@ pub fn parse_dispatch(input: &str) -> Result<Dispatch<String>, ParseError> {
 
     Ok(Dispatch { actions: actions })
 }
+
+/// Routes used in the dispatch plan which are not in the given set of routes.
+pub fn unknown_routes<'a, R>(dispatch: &'a Dispatch<String>, routes: &HashMap<String, R>) -> Vec<&'a str> {
+    dispatch.actions.iter().filter_map(|a| match *a {
+        DispatchAction::Route(ref r) | DispatchAction::Train(_, _, _, ref r) if !routes.contains_key(r) => Some(r.as_str()),
+        _ => None,
+    }).collect()
+}
+
+/// Write a dispatch plan in the dispatch plan format. Trains with a driver profile other
+/// than the default are given a driver statement named after the train.
+pub fn dispatch_string(dispatch: &Dispatch<String>) -> String {
+    let default = DriverProfile::default();
+    let mut s = String::new();
+    for action in &dispatch.actions {
+        match *action {
+            DispatchAction::Wait(Some(dt)) => s.push_str(&format!("wait {:?}\n", dt)),
+            DispatchAction::Wait(None) => s.push_str("wait\n"),
+            DispatchAction::Route(ref r) => s.push_str(&format!("route {}\n", r)),
+            DispatchAction::Train(ref name, ref p, ref d, ref r) => {
+                let is_default = d.safety_margin == default.safety_margin &&
+                    d.reaction_time == default.reaction_time &&
+                    d.acceleration_fraction == default.acceleration_fraction &&
+                    match d.coasting { CoastingStrategy::Never => true, _ => false };
+                let driver = if is_default { String::new() } else {
+                    let coast = match d.coasting {
+                        CoastingStrategy::Never => "never".to_string(),
+                        CoastingStrategy::AtVelocity(f) => format!("{:?}", f),
+                    };
+                    s.push_str(&format!("driver {} margin={:?} reaction={:?} accel={:?} coast={}\n", name,
+                                        d.safety_margin, d.reaction_time, d.acceleration_fraction, coast));
+                    format!("driver={} ", name)
+                };
+                s.push_str(&format!("train {} l={:?} a={:?} b={:?} v={:?} {}{}\n", name,
+                                    p.length, p.max_acc, p.max_brk, p.max_vel, driver, r));
+            },
+        }
+    }
+    s
+}
@@ -2,4 +2,5 @@ pub mod staticinfrastructure;
 pub mod staticinfrastructure_parser;
 pub mod route_parser;
 pub mod dispatch;
+pub mod timetable;
 mod parser_utils;
@@ -5,6 +5,8 @@ use std::fmt::Debug;
 pub enum LexerError {
     #[fail(display = "unexpected char at {}: {}", i, c)]
     UnexpectedChar { i: usize, c: String },
+    #[fail(display = "invalid number at {}: {}", i, s)]
+    InvalidNumber { i: usize, s: String },
     #[fail(display = "unexpected EOF")]
     UnexpectedEOF,
 }
@@ -10,6 +10,47 @@ pub type NameMap<Ref> = HashMap<Ref, usize>;
 pub struct InfNames<Ref : Hash + Eq + Debug> {
     pub node_names: NameMap<Ref>,
     pub object_names: NameMap<Ref>,
+    /// Locations of nodes on the tracks of the source infrastructure, for both
+    /// nodes of each double node which has a location.
+    pub node_positions: HashMap<NodeId, NodePosition>,
+    /// Named stop locations, such as platforms.
+    pub stop_locations: HashMap<Ref, StopLocation>,
+}
+
+/// Stop location from `pos` to `pos + length` along a track, given by the `stop` statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopLocation {
+    pub track: String,
+    pub pos: f64,
+    pub length: f64,
+    /// Id of the operational control point (station).
+    pub ocp: Option<String>,
+}
+
+impl<Ref : Hash + Eq + Debug> InfNames<Ref> {
+    /// Nodes within a stop location, found by their positions. Both sides of
+    /// each node are included.
+    pub fn stop_nodes(&self, stop: &StopLocation) -> Vec<NodeId> {
+        let tolerance = 1e-3;
+        let mut nodes = self.node_positions.iter()
+            .filter(|&(_, p)| p.track == stop.track && p.pos >= stop.pos - tolerance &&
+                    p.pos <= stop.pos + stop.length + tolerance)
+            .map(|(&n, _)| n)
+            .collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    }
+}
+
+/// Location of a node given by the `at` annotation of the node statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePosition {
+    /// Track id.
+    pub track: String,
+    /// Position along the track in meters.
+    pub pos: f64,
+    /// Absolute position (kilometrage) in meters.
+    pub abs_pos: Option<f64>,
 }
 
 
@@ -34,6 +75,8 @@ pub struct Node {
     pub other_node: NodeId,
     pub edges: Edges,
     pub objects: SmallVec<[ObjectId; 2]>,
+    /// Gradient in per mille of the edge leaving the node, uphill positive.
+    pub gradient: f64,
 }
 
 #[derive(Debug)]
@@ -52,12 +95,18 @@ pub enum StaticObject {
         left_link: (NodeId, f64),
         right_link: (NodeId, f64),
         branch_side: SwitchPosition,
+        /// Time in seconds to throw the switch to another position.
+        throw_time: f64,
+        /// Maximum velocity for trains travelling on the branch side leg.
+        branch_max_velocity: Option<f64>,
     },
     TVDLimit {
         enter: Option<ObjectId>,
         exit: Option<ObjectId>,
     },
     TVDSection,
+    /// Maximum velocity for trains passing the node, until the next speed limit.
+    SpeedLimit { max_velocity: f64 },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -1,7 +1,7 @@
 use smallvec::SmallVec;
 
 use super::staticinfrastructure;
-use super::staticinfrastructure::{Dist, SwitchPosition, InfNames};
+use super::staticinfrastructure::{Dist, SwitchPosition, InfNames, NodePosition, StopLocation};
 
 use super::parser_utils::*;
 
@@ -11,10 +11,22 @@ use super::parser_utils::*;
 type NodeName = String;
 #[derive(Debug)]
 pub enum Statement {
-    DoubleNode(PartNode, PartNode),
+    DoubleNode(PartNode, PartNode, Option<NodePosition>),
     Boundary(NodeName),
-    Linear(NodeName, NodeName, Dist),
-    Switch(String, SwitchPosition, NodeName, Vec<(NodeName, Dist)>),
+    Linear(NodeName, NodeName, Dist, Vec<LinearProperty>),
+    Switch(String, SwitchPosition, NodeName, Vec<(NodeName, Dist)>, Vec<SwitchProperty>),
+    Stop(String, StopLocation),
+}
+
+#[derive(Debug)]
+pub enum SwitchProperty {
+    ThrowTime(f64),
+    BranchMaxVelocity(f64),
+}
+
+#[derive(Debug)]
+pub enum LinearProperty {
+    Gradient(f64),
 }
 
 #[derive(Debug)]
@@ -29,6 +41,7 @@ pub enum Object {
     Signal(String),
     Exit(String),
     Enter(String),
+    Speed(f64),
 }
 
 // LEXER
@@ -69,7 +82,7 @@ pub fn lexer(x: &mut Iterator<Item = char>) -> Result<Vec<Token>, LexerError> {
                     })
                     .into_iter()
                     .collect();
-                tokens.push(Token::Number(num.parse::<f64>().unwrap()));
+                tokens.push(Token::Number(lex_number(&num, line)?));
             }
             x if x.is_alphabetic() => {
                 let s: String = consume_while(&mut input, |a| a == '_' || a.is_alphanumeric())
@@ -104,14 +117,24 @@ pub fn lexer(x: &mut Iterator<Item = char>) -> Result<Vec<Token>, LexerError> {
             '-' => {
                 input.next().unwrap();
                 let mut comment = false;
+                let mut negative = false;
                 if let Some(&x) = input.peek() {
                     if x == '-' { 
                         // Comment 
                         consume_while(&mut input, |a| a != '\n');
                         comment = true;
                     }
+                    // Node names start with a letter, so this is a negative number.
+                    negative = x.is_numeric();
                 }
-                if !comment { tokens.push(Token::Arrow); }
+                if negative {
+                    let num: String = consume_while(&mut input, |a| {
+                            a.is_numeric() || a == '-' || a == 'e' || a == 'E' || a == '.'
+                        })
+                        .into_iter()
+                        .collect();
+                    tokens.push(Token::Number(-lex_number(&num, line)?));
+                } else if !comment { tokens.push(Token::Arrow); }
             }
             ' ' | '\r' | '\t' => {
                 input.next().unwrap();
@@ -132,6 +155,10 @@ pub fn lexer(x: &mut Iterator<Item = char>) -> Result<Vec<Token>, LexerError> {
     Ok(tokens)
 }
 
+fn lex_number(s: &str, line: usize) -> Result<f64, LexerError> {
+    s.parse::<f64>().map_err(|_| LexerError::InvalidNumber { i: line, s: s.to_string() })
+}
+
 fn consume_while<F>(it: &mut Peekable<&mut Iterator<Item = char>>, x: F) -> Vec<char>
     where F: Fn(char) -> bool
 {
@@ -197,6 +224,7 @@ pub fn parse_statement(i: &mut usize, t: &[Token]) -> Result<Statement, ParseErr
         must_match(i, t, Token::Arrow)?;
         let n2 = identifier(i, t)?;
         let n2_objects = parse_opt_object_list(i, t)?;
+        let position = parse_opt_position(i, t)?;
         Ok(Statement::DoubleNode(PartNode {
                                      name: n1,
                                      contents: n1_objects,
@@ -204,7 +232,8 @@ pub fn parse_statement(i: &mut usize, t: &[Token]) -> Result<Statement, ParseErr
                                  PartNode {
                                      name: n2,
                                      contents: n2_objects,
-                                 }))
+                                 },
+                                 position))
     },
           &|i, t| {
         must_match(i, t, Token::Linear)?;
@@ -212,7 +241,8 @@ pub fn parse_statement(i: &mut usize, t: &[Token]) -> Result<Statement, ParseErr
         must_match(i, t, Token::Arrow)?;
         let n2 = identifier(i, t)?;
         let dist = number(i, t)?;
-        Ok(Statement::Linear(n1, n2, dist))
+        let properties = parse_opt_linear_properties(i, t)?;
+        Ok(Statement::Linear(n1, n2, dist, properties))
     },
           &|i, t| {
         must_match(i, t, Token::Switch)?;
@@ -221,7 +251,22 @@ pub fn parse_statement(i: &mut usize, t: &[Token]) -> Result<Statement, ParseErr
         let facenode = identifier(i, t)?;
         must_match(i, t, Token::Arrow)?;
         let branches = parse_switch_branches(i, t)?;
-        Ok(Statement::Switch(name, pos, facenode, branches))
+        let properties = parse_opt_switch_properties(i, t)?;
+        Ok(Statement::Switch(name, pos, facenode, branches, properties))
+    },
+          &|i, t| {
+        must_match(i, t, Token::Identifier("stop".to_string()))?;
+        let name = identifier(i, t)?;
+        must_match(i, t, Token::Identifier("at".to_string()))?;
+        let track = identifier(i, t)?;
+        let pos = number(i, t)?;
+        let length = number(i, t)?;
+        let ocp = if matches(i, t, Token::Identifier("ocp".to_string())) {
+            Some(identifier(i, t)?)
+        } else {
+            None
+        };
+        Ok(Statement::Stop(name, StopLocation { track, pos, length, ocp }))
     }])
 }
 
@@ -253,6 +298,66 @@ pub fn parse_switch_branches(i: &mut usize,
     Ok(branches)
 }
 
+pub fn parse_opt_switch_properties(i: &mut usize,
+                                   t: &[Token])
+                                   -> Result<Vec<SwitchProperty>, ParseError> {
+    let mut props = Vec::new();
+    if !matches(i, t, Token::OpenList) {
+        return Ok(props);
+    }
+    loop {
+        let name = identifier(i, t)?;
+        let value = number(i, t)?;
+        props.push(match name.as_ref() {
+            "throw" => SwitchProperty::ThrowTime(value),
+            "speed" => SwitchProperty::BranchMaxVelocity(value),
+            _ => return Err(ParseError::UnknownName(name, "switch property".to_string())),
+        });
+        if !matches(i, t, Token::ListSep) {
+            break;
+        }
+    }
+    must_match(i, t, Token::CloseList)?;
+    Ok(props)
+}
+
+/// Optional node location: `at <track> <pos> [abs <abs_pos>]`.
+pub fn parse_opt_position(i: &mut usize, t: &[Token]) -> Result<Option<NodePosition>, ParseError> {
+    if !matches(i, t, Token::Identifier("at".to_string())) {
+        return Ok(None);
+    }
+    let track = identifier(i, t)?;
+    let pos = number(i, t)?;
+    let abs_pos = if matches(i, t, Token::Identifier("abs".to_string())) {
+        Some(number(i, t)?)
+    } else {
+        None
+    };
+    Ok(Some(NodePosition { track, pos, abs_pos }))
+}
+
+pub fn parse_opt_linear_properties(i: &mut usize,
+                                   t: &[Token])
+                                   -> Result<Vec<LinearProperty>, ParseError> {
+    let mut props = Vec::new();
+    if !matches(i, t, Token::OpenList) {
+        return Ok(props);
+    }
+    loop {
+        let name = identifier(i, t)?;
+        let value = number(i, t)?;
+        props.push(match name.as_ref() {
+            "gradient" => LinearProperty::Gradient(value),
+            _ => return Err(ParseError::UnknownName(name, "linear edge property".to_string())),
+        });
+        if !matches(i, t, Token::ListSep) {
+            break;
+        }
+    }
+    must_match(i, t, Token::CloseList)?;
+    Ok(props)
+}
+
 pub fn parse_opt_object_list(i: &mut usize, t: &[Token]) -> Result<Vec<Object>, ParseError> {
     let mut objs = Vec::new();
     if !matches(i, t, Token::OpenList) {
@@ -288,6 +393,10 @@ pub fn parse_object(i: &mut usize, t: &[Token]) -> Result<Object, ParseError> {
           &|i, t| {
               must_match(i, t, Token::Enter)?;
               Ok(Object::Enter(identifier(i, t)?))
+          },
+          &|i, t| {
+              must_match(i, t, Token::Identifier("speed".to_string()))?;
+              Ok(Object::Speed(number(i, t)?))
           }])
 }
 
@@ -301,6 +410,9 @@ pub fn parse_object(i: &mut usize, t: &[Token]) -> Result<Object, ParseError> {
 
 
 
+/// Switch throw time used when the switch statement does not give one.
+pub const DEFAULT_SWITCH_THROW_TIME: f64 = 5.0;
+
 #[derive(Debug,Clone,Fail)]
 pub enum ModelError {
     #[fail(display = "too many switch legs in {}", _0)]
@@ -346,6 +458,7 @@ fn get_or_create_node(mnodes: &mut Vec<staticinfrastructure::Node>,
             other_node: 0,
             edges: staticinfrastructure::Edges::Nothing,
             objects: SmallVec::new(),
+            gradient: 0.0,
         });
     }
     idx
@@ -361,6 +474,8 @@ pub fn model_from_ast(stmts: &[Statement])
     let mut names = InfNames {
         node_names: HashMap::new(),
         object_names: HashMap::new(),
+        node_positions: HashMap::new(),
+        stop_locations: HashMap::new(),
     };
 
     for s in stmts {
@@ -370,13 +485,21 @@ pub fn model_from_ast(stmts: &[Statement])
                 let node_idx = get_or_create_node(&mut model.nodes, &mut names.node_names, name);
                 model.nodes[node_idx].edges = Edges::ModelBoundary;
             }
-            Linear(ref name1, ref name2, dist) => {
+            Linear(ref name1, ref name2, dist, ref properties) => {
                 let n1 = get_or_create_node(&mut model.nodes, &mut names.node_names, name1);
                 let n2 = get_or_create_node(&mut model.nodes, &mut names.node_names, name2);
                 model.nodes[n1].edges = Edges::Single(n2, dist);
                 model.nodes[n2].edges = Edges::Single(n1, dist);
+                for p in properties {
+                    match *p {
+                        LinearProperty::Gradient(g) => {
+                            model.nodes[n1].gradient = g;
+                            model.nodes[n2].gradient = -g;
+                        }
+                    }
+                }
             }
-            Switch(ref name, ref side, ref node, ref legs) => {
+            Switch(ref name, ref side, ref node, ref legs, ref properties) => {
                 let node_idx = get_or_create_node(&mut model.nodes, &mut names.node_names, node);
                 if legs.len() != 2 {
                     return Err(ModelError::SwitchLegs(name.clone()));
@@ -385,10 +508,20 @@ pub fn model_from_ast(stmts: &[Statement])
                 let l1idx = get_or_create_node(&mut model.nodes, &mut names.node_names, l1name);
                 let (ref l2name, l2dist) = legs[1];
                 let l2idx = get_or_create_node(&mut model.nodes, &mut names.node_names, l2name);
+                let mut throw_time = DEFAULT_SWITCH_THROW_TIME;
+                let mut branch_max_velocity = None;
+                for p in properties {
+                    match *p {
+                        SwitchProperty::ThrowTime(t) => throw_time = t,
+                        SwitchProperty::BranchMaxVelocity(v) => branch_max_velocity = Some(v),
+                    }
+                }
                 let switch = staticinfrastructure::StaticObject::Switch {
                     left_link: (l1idx, l1dist),
                     right_link: (l2idx, l2dist),
                     branch_side: *side,
+                    throw_time: throw_time,
+                    branch_max_velocity: branch_max_velocity,
                 };
                 let sw_idx =
                     insert_object(&mut model.objects, &mut names.object_names, switch, name);
@@ -397,12 +530,20 @@ pub fn model_from_ast(stmts: &[Statement])
                 model.nodes[l1idx].edges = Edges::Single(node_idx, l1dist);
                 model.nodes[l2idx].edges = Edges::Single(node_idx, l2dist);
             }
-            DoubleNode(ref n1, ref n2) => {
+            Stop(ref name, ref stop) => {
+                names.stop_locations.insert(name.clone(), stop.clone());
+            }
+            DoubleNode(ref n1, ref n2, ref position) => {
                 // Create cross references
                 //
                 let n1_idx = get_or_create_node(&mut model.nodes, &mut names.node_names, &n1.name);
                 let n2_idx = get_or_create_node(&mut model.nodes, &mut names.node_names, &n2.name);
 
+                if let Some(ref position) = *position {
+                    names.node_positions.insert(n1_idx, position.clone());
+                    names.node_positions.insert(n2_idx, position.clone());
+                }
+
                 model.nodes[n1_idx].other_node = n2_idx;
                 model.nodes[n2_idx].other_node = n1_idx;
 
@@ -483,6 +624,15 @@ pub fn model_from_ast(stmts: &[Statement])
                                     exit: None,
                                 });
 
+                                let m: &mut staticinfrastructure::Node = &mut model.nodes[node];
+                                m.objects.push(idx);
+                            }
+                            Object::Speed(v) => {
+                                let idx = model.objects.len();
+                                model.objects.push(staticinfrastructure::StaticObject::SpeedLimit {
+                                    max_velocity: v,
+                                });
+
                                 let m: &mut staticinfrastructure::Node = &mut model.nodes[node];
                                 m.objects.push(idx);
                             }
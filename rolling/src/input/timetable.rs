@@ -0,0 +1,207 @@
+use railway::dynamics::TrainParams;
+use railway::driver::DriverProfile;
+use input::dispatch::{self, Dispatch, DispatchAction, ParseError};
+use input::staticinfrastructure::{StaticInfrastructure, InfNames, NodeId, Routes, RouteEntryExit};
+use regex::Regex;
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct Timetable {
+    pub trains: Vec<TimetableTrain>,
+}
+
+#[derive(Debug)]
+pub struct TimetableTrain {
+    pub name: String,
+    /// Planned time of entering the model.
+    pub entry: f64,
+    pub params: TrainParams,
+    pub profile: DriverProfile,
+    /// Routes taken by the train, starting with its entry route.
+    pub routes: Vec<String>,
+    pub points: Vec<TimingPoint>,
+}
+
+/// Planned times at a node or signal.
+#[derive(Debug, Clone)]
+pub struct TimingPoint {
+    pub location: String,
+    pub arrival: Option<f64>,
+    pub departure: Option<f64>,
+}
+
+#[derive(Debug, Fail)]
+pub enum TimetableError {
+    #[fail(display = "{}", _0)]
+    Parse(#[cause] ParseError),
+    #[fail(display = "unknown train: {}", _0)]
+    UnknownTrain(String),
+    #[fail(display = "unknown location: {}", _0)]
+    UnknownLocation(String),
+    #[fail(display = "unknown route: {}", _0)]
+    UnknownRoute(String),
+}
+
+impl From<ParseError> for TimetableError {
+    fn from(e: ParseError) -> TimetableError { TimetableError::Parse(e) }
+}
+
+/// Parses timetable format
+///
+/// * driver cautious margin=50.0 reaction=2.0
+/// * train t1 at 0.0 l=200.0 a=1.0 b=0.5 v=10.0 driver=cautious routes rentry r1 rexit
+/// * arrive t1 n4a 60.0
+/// * depart t1 sig1 90.0
+///
+pub fn parse_timetable(input: &str) -> Result<Timetable, TimetableError> {
+    let regex = |r: &str| Regex::new(r).map_err(|e| ParseError::RegexError(format!("{:?}", e)));
+    let train_re = regex(r"(?x) ^ \s* train \s+ (?P<name>\w+) \s+
+            at \s+ (?P<time>[\d\.]+) \s+
+            l \s* = \s* (?P<len>[\d\.]+) \s+
+            a \s* = \s* (?P<acc>[\d\.]+) \s+
+            b \s* = \s* (?P<brk>[\d\.]+) \s+
+            v \s* = \s* (?P<vel>[\d\.]+) \s+
+            (?: driver \s* = \s* (?P<driver>\w+) \s+ )?
+            routes (?P<routes> (?: \s+ [\w\.]+ )+ ) \s*
+            $")?;
+    let time_re = regex(r"^\s*(arrive|depart)\s+(\w+)\s+([\w\.]+)\s+([\d\.]+)\s*$")?;
+    let driver_re = regex(r"(?x) ^ \s* driver \s+ (?P<name>\w+)
+            (?P<params> (?: \s+ \w+ \s* = \s* [\w\.]+ )* ) \s*
+            $")?;
+    let param_re = regex(r"(\w+)\s*=\s*([\w\.]+)")?;
+    let number = |s: &str| s.parse::<f64>().map_err(|_e| ParseError::NumberError);
+
+    let mut drivers = HashMap::new();
+    let mut trains: Vec<TimetableTrain> = Vec::new();
+    for line in input.lines() {
+        if line.trim().is_empty() { continue; }
+        if let Some(groups) = driver_re.captures(line) {
+            let profile = dispatch::driver_profile(&param_re, &groups["params"], line)?;
+            drivers.insert(groups["name"].to_string(), profile);
+            continue;
+        }
+        if let Some(groups) = train_re.captures(line) {
+            let profile = match groups.name("driver") {
+                Some(name) => *drivers.get(name.as_str())
+                    .ok_or_else(|| ParseError::UnknownDriver(name.as_str().to_string()))?,
+                None => DriverProfile::default(),
+            };
+            trains.push(TimetableTrain {
+                name: groups["name"].to_string(),
+                entry: number(&groups["time"])?,
+                params: TrainParams {
+                    length: number(&groups["len"])?,
+                    max_acc: number(&groups["acc"])?,
+                    max_brk: number(&groups["brk"])?,
+                    max_vel: number(&groups["vel"])?,
+                },
+                profile: profile,
+                routes: groups["routes"].split_whitespace().map(|r| r.to_string()).collect(),
+                points: Vec::new(),
+            });
+            continue;
+        }
+        if let Some(groups) = time_re.captures(line) {
+            let time = number(&groups[4])?;
+            let train = trains.iter_mut().find(|t| t.name == &groups[2])
+                .ok_or_else(|| TimetableError::UnknownTrain(groups[2].to_string()))?;
+            let location = groups[3].to_string();
+            let idx = match train.points.iter().position(|p| p.location == location) {
+                Some(i) => i,
+                None => {
+                    train.points.push(TimingPoint { location: location, arrival: None, departure: None });
+                    train.points.len() - 1
+                }
+            };
+            if &groups[1] == "arrive" {
+                train.points[idx].arrival = Some(time);
+            } else {
+                train.points[idx].departure = Some(time);
+            }
+            continue;
+        }
+        return Err(ParseError::Unrecognized(line.to_string()).into());
+    }
+
+    Ok(Timetable { trains: trains })
+}
+
+/// Nodes at a location, given as a node name, an object name (such as a signal),
+/// or a stop location name. Both sides of a node are included.
+pub fn location_nodes(inf: &StaticInfrastructure, names: &InfNames<String>, location: &str)
+                      -> Result<Vec<NodeId>, TimetableError> {
+    if let Some(&n) = names.node_names.get(location) {
+        return Ok(vec![n, inf.nodes[n].other_node]);
+    }
+    if let Some(stop) = names.stop_locations.get(location) {
+        let nodes = names.stop_nodes(stop);
+        if nodes.len() > 0 { return Ok(nodes); }
+    }
+    if let Some(&obj) = names.object_names.get(location) {
+        let nodes = object_nodes(inf, obj);
+        if nodes.len() > 0 { return Ok(nodes); }
+    }
+    Err(TimetableError::UnknownLocation(location.to_string()))
+}
+
+fn object_nodes(inf: &StaticInfrastructure, obj: usize) -> Vec<NodeId> {
+    let mut nodes = Vec::new();
+    for (i, n) in inf.nodes.iter().enumerate() {
+        if n.objects.contains(&obj) {
+            nodes.push(i);
+            nodes.push(n.other_node);
+        }
+    }
+    nodes
+}
+
+/// Convert a timetable into a dispatch plan. Each train enters at its planned entry time.
+/// A route starting at a signal where the train has a planned departure is requested
+/// at the departure time, so that the train is held at the signal until then.
+/// All other routes are requested when the train enters.
+pub fn timetable_dispatch(tt: &Timetable, inf: &StaticInfrastructure, names: &InfNames<String>,
+                          routes: &Routes<String>) -> Result<Dispatch<String>, TimetableError> {
+    let mut events = Vec::new();
+    for train in &tt.trains {
+        let entry_route = match train.routes.first() {
+            Some(r) => r,
+            None => continue,
+        };
+        if !routes.contains_key(entry_route) { return Err(TimetableError::UnknownRoute(entry_route.clone())); }
+        events.push((train.entry, DispatchAction::Train(train.name.clone(), train.params,
+                                                        train.profile, entry_route.clone())));
+
+        let mut departures = Vec::new();
+        for p in &train.points {
+            if let Some(t) = p.departure {
+                departures.push((location_nodes(inf, names, &p.location)?, t));
+            }
+        }
+        for r in &train.routes[1..] {
+            let route = routes.get(r).ok_or_else(|| TimetableError::UnknownRoute(r.clone()))?;
+            let signal = match route.entry {
+                RouteEntryExit::Signal(s) | RouteEntryExit::SignalTrigger { signal: s, .. } => Some(s),
+                RouteEntryExit::Boundary(_) => None,
+            };
+            let departure = signal.and_then(|s| {
+                let nodes = object_nodes(inf, s);
+                departures.iter().find(|d| d.0.iter().any(|n| nodes.contains(n))).map(|d| d.1)
+            });
+            let time = departure.map(|t| t.max(train.entry)).unwrap_or(train.entry);
+            events.push((time, DispatchAction::Route(r.clone())));
+        }
+    }
+
+    // Stable sort keeps the timetable order for actions at the same time.
+    events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let mut actions = Vec::new();
+    let mut t = 0.0;
+    for (time, action) in events {
+        if time > t {
+            actions.push(DispatchAction::Wait(Some(time - t)));
+            t = time;
+        }
+        actions.push(action);
+    }
+    Ok(Dispatch { actions: actions })
+}
@@ -4,6 +4,10 @@ extern crate ordered_float;
 extern crate regex;
 extern crate failure;
 #[macro_use] extern crate failure_derive;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate railml2dgraph;
 
 pub mod input;
 pub mod output;
@@ -15,7 +19,7 @@ pub mod ffi;
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::cell::RefCell;
-use output::history::InfrastructureLogEvent;
+use output::history::HistoryLogger;
 use std::hash::Hash;
 use std::fmt::Debug;
 
@@ -24,38 +28,57 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                      //names: &input::staticinfrastructure::InfNames<InfRef>,
                      routes: &HashMap<RouteRef,input::staticinfrastructure::Route>,
                      dispatch: &input::dispatch::Dispatch<RouteRef>, 
-                     timestep :Option<f64>) -> output::history::History {
+                     timestep :Option<f64>,
+                     signalling :railway::SignallingMode) -> output::history::History {
+    let history = Rc::new(RefCell::new(output::history::History::default()));
+    evaluate_plan_logged(staticinfrastructure, routes, dispatch, timestep, signalling, history.clone());
+    let h = history.replace(Default::default());
+    h
+}
 
+/// Evaluate the dispatch plan, sending the history to the given logger as the simulation runs.
+pub fn evaluate_plan_logged<RouteRef : Hash + Eq + Debug + Clone, L : HistoryLogger + 'static>
+                    (staticinfrastructure: &input::staticinfrastructure::StaticInfrastructure,
+                     routes: &HashMap<RouteRef,input::staticinfrastructure::Route>,
+                     dispatch: &input::dispatch::Dispatch<RouteRef>, 
+                     timestep :Option<f64>,
+                     signalling :railway::SignallingMode,
+                     log :Rc<RefCell<L>>) {
 
-    let mut train_logs = Vec::new();
-    let inf_log = Rc::new(RefCell::new(Vec::new()));
+    let mut num_trains = 0;
     let time_log = {
-        let log = inf_log.clone();
-        Box::new(move |t| if t > 0.0 { log.borrow_mut().push(InfrastructureLogEvent::Wait(t)); })
+        let log = log.clone();
+        Box::new(move |t| log.borrow_mut().time(t))
     };
 
     let mut scheduler = eventsim::Scheduler::new();
-    let world_log = inf_log.clone();
+    let world_log = log.clone();
     let world = railway::infrastructure::Infrastructure::new(
-        &mut scheduler, staticinfrastructure, 
-        Box::new(move |i| world_log.borrow_mut().push(i))); 
+        &mut scheduler, staticinfrastructure, signalling,
+        Box::new(move |i| world_log.borrow_mut().infrastructure(i))); 
     let mut sim = eventsim::Simulation::new_with_scheduler(world, scheduler);
     sim.set_time_log(time_log);
 
+    // Routes conflict on the sections and switches of the route and of all its overlaps,
+    // so that a route is not overtaken by a later route which takes its overlap.
+    fn route_objects(r :&input::staticinfrastructure::Route) -> Vec<input::staticinfrastructure::ObjectId> {
+        r.resources.sections.iter().cloned()
+            .chain(r.resources.switch_positions.iter().map(|&(x,_)| x))
+            .chain(r.overlaps.iter().flat_map(|o| o.sections.iter().cloned()))
+            .chain(r.overlaps.iter().flat_map(|o| o.switch_positions.iter().map(|&(x,_)| x)))
+            .collect()
+    }
+
     let mut resource_routes : HashMap<input::staticinfrastructure::ObjectId, HashSet<RouteRef>> = HashMap::new();
     for (name,route) in routes.iter() {
-        let objs = route.resources.sections.iter().chain(
-            route.resources.switch_positions.iter().map(|(x,_)| x));
-        for obj in objs {
-            resource_routes.entry(*obj).or_insert(HashSet::new()).insert(name.clone());
+        for obj in route_objects(route) {
+            resource_routes.entry(obj).or_insert(HashSet::new()).insert(name.clone());
         }
     }
     fn get_conflicting_routes<RouteRef : Hash + Clone + Eq>(res :&HashMap<input::staticinfrastructure::ObjectId,HashSet<RouteRef>>, r :&input::staticinfrastructure::Route) -> HashSet<RouteRef> {
         let mut set = HashSet::new();
-        let objs = r.resources.sections.iter().chain(
-            r.resources.switch_positions.iter().map(|(x,_)| x));
-        for o in objs {
-            if let Some(obj_route_set) = res.get(o) {
+        for o in route_objects(r) {
+            if let Some(obj_route_set) = res.get(&o) {
                 set.extend(obj_route_set.iter().cloned());
             }
         }
@@ -89,7 +112,7 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                 },
                 _ => panic!("Unknown route \"{:?}\"", route_name),
             },
-            Train(ref name, ref params, ref route_name) =>  {
+            Train(ref name, ref params, ref profile, ref route_name) =>  {
                 let (activated, node_idx, auth_dist) = match routes.get(route_name) {
                     Some(route) => {
 
@@ -116,31 +139,24 @@ pub fn evaluate_plan<RouteRef : Hash + Eq + Debug + Clone >
                     _ => panic!("Unknown route \"{:?}\"", route_name),
                 };
 
-                let train_log = Rc::new(RefCell::new(Vec::new()));
-                train_logs.push((name.clone(), params.clone(), train_log.clone()));
+                let train_id = num_trains;
+                num_trains += 1;
+                log.borrow_mut().train_start(name, params);
+                let train_log = log.clone();
                 let logger = Box::new(move |i| {
                     //println!(" --- {:?}", i);
-                    train_log.borrow_mut().push(i);
+                    train_log.borrow_mut().train(train_id, i);
                 });
 
-                let train_id = train_logs.len()-1;
                 let driver = Box::new(
                     railway::driver::Driver::new(&mut sim, train_id, activated, node_idx, auth_dist, 
-                          *params, logger, timestep));
+                          *params, *profile, logger, timestep));
                 sim.start_process(driver);
             }
         }
     }
 
     sim.run();
-
-
-    let h = output::history::History {
-        inf: inf_log.replace(Vec::new()),
-        trains: train_logs.into_iter().map(|(n,p,v)| (n, p, v.replace(Vec::new()))).collect()
-    };
-
-    h
 }
 
 
@@ -176,9 +192,14 @@ pub fn get_infrastructure_string(s :&str) -> AppResult<(staticinfrastructure::St
 
 pub fn get_routes(s :&Path, inf :&staticinfrastructure::InfNames<String>) 
     -> AppResult<staticinfrastructure::Routes<String>> {
-    use input::route_parser::{parse, lexer};
     let contents = read_file(s)?;
-    let lex = lexer(&mut contents.chars())?;
+    get_routes_string(&contents, inf)
+}
+
+pub fn get_routes_string(s :&str, inf :&staticinfrastructure::InfNames<String>) 
+    -> AppResult<staticinfrastructure::Routes<String>> {
+    use input::route_parser::{parse, lexer};
+    let lex = lexer(&mut s.chars())?;
     let rs = parse(&lex, inf)?;
     Ok(rs)
 }
@@ -188,3 +209,9 @@ pub fn get_dispatch(s :&Path) -> AppResult<dispatch::Dispatch<String>> {
     let d = dispatch::parse_dispatch(&contents)?;
     Ok(d)
 }
+
+pub fn get_timetable(s :&Path) -> AppResult<input::timetable::Timetable> {
+    let contents = read_file(s)?;
+    let t = input::timetable::parse_timetable(&contents)?;
+    Ok(t)
+}
@@ -1,26 +1,187 @@
 extern crate rolling;
 extern crate failure;
 extern crate structopt;
+extern crate railml2dgraph;
 
 use rolling::*;
-use std::path::PathBuf;
+use rolling::input::staticinfrastructure::{StaticInfrastructure, StaticObject, Edges, InfNames, Routes, RouteEntryExit};
+use rolling::input::dispatch::Dispatch;
+use rolling::output::history::History;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use structopt::StructOpt;
+use structopt::clap;
+
+/// Exit code for errors in the input files.
+const EXIT_INVALID_INPUT: i32 = 1;
+/// Exit code for invalid command-line usage.
+const EXIT_USAGE: i32 = 2;
+/// Exit code for errors reading or writing files.
+const EXIT_IO: i32 = 3;
 
 /// Rolling -- simple railway simulation
 #[derive(StructOpt, Debug)]
 #[structopt(name="rolling")]
 struct Opt {
-    // A flag, true if used in the command line. Note doc comment will
-    // be used for the help message of the flag.
-    // /// Activate debug mode
-    // #[structopt(short = "d", long = "debug")]
-    // debug: bool,
-
-    // // The number of occurences of the `v/verbose` flag
     /// Verbose mode (-v, -vv)
-    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences), global = true)]
     verbose: u8,
 
+    /// Quiet mode, only errors are printed
+    #[structopt(short = "q", long = "quiet", global = true)]
+    quiet: bool,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Simulate a dispatch plan and write the history
+    #[structopt(name = "simulate")]
+    Simulate {
+        #[structopt(flatten)]
+        input: Input,
+        #[structopt(flatten)]
+        sim: SimOpt,
+        #[structopt(flatten)]
+        output: SimOutput,
+    },
+
+    /// Check infrastructure, routes and dispatch files for errors
+    #[structopt(name = "validate")]
+    Validate {
+        /// Static infrastructure file in the Rolling D-Graph format
+        #[structopt(parse(from_os_str))]
+        infrastructure: PathBuf,
+        /// Route file in the Rolling Route format
+        #[structopt(parse(from_os_str))]
+        routes: Option<PathBuf>,
+        /// Dispatch file in the Rolling Dispatch format
+        #[structopt(parse(from_os_str))]
+        dispatch: Option<PathBuf>,
+    },
+
+    /// Output directed graph for graphical conversion
+    #[structopt(name = "graphical")]
+    Graphical {
+        /// Static infrastructure file in the Rolling D-Graph format
+        #[structopt(parse(from_os_str))]
+        infrastructure: PathBuf,
+        /// Output file (default: standard output)
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Generate routes between signals and model boundaries
+    #[structopt(name = "routes")]
+    Routes {
+        /// Static infrastructure file in the Rolling D-Graph format
+        #[structopt(parse(from_os_str))]
+        infrastructure: PathBuf,
+        /// Output file (default: standard output)
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Print statistics for the infrastructure, routes and trains
+    #[structopt(name = "stats")]
+    Stats {
+        /// Static infrastructure file in the Rolling D-Graph format
+        #[structopt(parse(from_os_str))]
+        infrastructure: PathBuf,
+        /// Route file in the Rolling Route format
+        #[structopt(parse(from_os_str))]
+        routes: Option<PathBuf>,
+        /// Dispatch file in the Rolling Dispatch format
+        #[structopt(parse(from_os_str))]
+        dispatch: Option<PathBuf>,
+        #[structopt(flatten)]
+        sim: SimOpt,
+    },
+
+    /// Simulate a timetable and report punctuality
+    #[structopt(name = "timetable")]
+    Timetable {
+        /// Static infrastructure file in the Rolling D-Graph format
+        #[structopt(parse(from_os_str))]
+        infrastructure: PathBuf,
+        /// Route file in the Rolling Route format
+        #[structopt(parse(from_os_str))]
+        routes: PathBuf,
+        /// Timetable file
+        #[structopt(parse(from_os_str))]
+        timetable: PathBuf,
+        #[structopt(flatten)]
+        sim: SimOpt,
+        #[structopt(flatten)]
+        output: TimetableOutput,
+    },
+
+    /// Compare the simulation of a dispatch plan on two model variants
+    #[structopt(name = "compare")]
+    Compare {
+        /// Static infrastructure file of the first variant
+        #[structopt(parse(from_os_str))]
+        infrastructure_a: PathBuf,
+        /// Route file of the first variant
+        #[structopt(parse(from_os_str))]
+        routes_a: PathBuf,
+        /// Static infrastructure file of the second variant
+        #[structopt(parse(from_os_str))]
+        infrastructure_b: PathBuf,
+        /// Route file of the second variant
+        #[structopt(parse(from_os_str))]
+        routes_b: PathBuf,
+        /// Dispatch file in the Rolling Dispatch format
+        #[structopt(parse(from_os_str))]
+        dispatch: PathBuf,
+        #[structopt(flatten)]
+        sim: SimOpt,
+        #[structopt(flatten)]
+        output: CompareOutput,
+    },
+
+    /// Convert railML 2.x or 3.x infrastructure to the Rolling D-Graph and route formats
+    #[structopt(name = "convert")]
+    Convert {
+        /// railML file
+        #[structopt(parse(from_os_str))]
+        railml: PathBuf,
+        /// Output infrastructure file in the Rolling D-Graph format (default: standard output)
+        #[structopt(short = "o", long = "infrastructure", parse(from_os_str))]
+        infrastructure: Option<PathBuf>,
+        /// Output route file in the Rolling Route format
+        #[structopt(short = "r", long = "routes", parse(from_os_str))]
+        routes: Option<PathBuf>,
+        /// Add overlaps of this length in meters past the exit signal of routes
+        #[structopt(long = "overlap")]
+        overlap: Option<f64>,
+        /// Release overlaps this many seconds after the train enters the route
+        #[structopt(long = "overlap-timeout", requires = "overlap")]
+        overlap_timeout: Option<f64>,
+    },
+
+    /// Export infrastructure in the Rolling D-Graph format to railML
+    #[structopt(name = "export")]
+    Export {
+        /// Static infrastructure file in the Rolling D-Graph format
+        #[structopt(parse(from_os_str))]
+        infrastructure: PathBuf,
+        /// Output railML file (default: standard output)
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// railML version: 2 | 3
+        #[structopt(long = "railml-version", default_value = "2", possible_values = &["2", "3"])]
+        version: u8,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+struct Input {
     /// Static infrastructure file in the Rolling D-Graph format
     #[structopt(parse(from_os_str))]
     infrastructure: PathBuf,
@@ -28,40 +189,180 @@ struct Opt {
     /// Route file in the Rolling Route format
     #[structopt(parse(from_os_str))]
     routes: PathBuf,
-    
+
     /// Dispatch file in the Rolling Dispatch format
     #[structopt(parse(from_os_str))]
     dispatch: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+struct SimOpt {
+    /// Maximum time step
+    #[structopt(short = "d", long = "time-step")]
+    timestep: Option<f64>,
+
+    /// Signalling mode: lineside | continuous | movingblock
+    #[structopt(short = "s", long = "signalling", default_value = "lineside")]
+    signalling: rolling::railway::SignallingMode,
+}
+
+#[derive(StructOpt, Debug)]
+struct SimOutput {
+    /// Output full history as text
+    #[structopt(long = "history", parse(from_os_str))]
+    history: Option<PathBuf>,
 
     /// Output JSON history file
     #[structopt(short = "j", long = "json", parse(from_os_str))]
     json: Option<PathBuf>,
 
+    /// Output JSON lines history file, written during simulation
+    #[structopt(long = "json-lines", parse(from_os_str))]
+    json_lines: Option<PathBuf>,
+
     /// Output JSON history as JavaScript
     #[structopt(short = "J", long = "javascript", parse(from_os_str))]
     javascript: Option<PathBuf>,
 
-    /// Output format profile: full | timing
-    #[structopt(short = "f", long = "format")]
-    format: Option<String>,
-
     /// Output node visit times to file
     #[structopt(short = "n", long = "visits", parse(from_os_str))]
     visits: Option<PathBuf>,
 
-    /// Output directed graph for graphical conversion
-    #[structopt(short = "g", long = "graphical", parse(from_os_str))]
-    graphical: Option<PathBuf>,
+    /// Output train velocity profiles to file
+    #[structopt(short = "p", long = "profile", parse(from_os_str))]
+    profile: Option<PathBuf>,
 
-    /// Maximum time step 
-    #[structopt(short = "d", long = "time-step")]
-    timestep: Option<f64>,
+    /// Velocity profile format
+    #[structopt(long = "profile-format", default_value = "csv", possible_values = &["csv", "json"])]
+    profile_format: String,
+
+    /// Velocity profile sampling interval in seconds
+    #[structopt(long = "resolution", default_value = "1.0", parse(try_from_str = positive_number))]
+    resolution: f64,
+
+    /// Output train energy use to file
+    #[structopt(short = "e", long = "energy", parse(from_os_str))]
+    energy: Option<PathBuf>,
+
+    /// Train mass in kg for energy calculation
+    #[structopt(long = "mass", default_value = "200000", parse(try_from_str = positive_number))]
+    mass: f64,
+
+    /// Traction efficiency for energy calculation, greater than 0 and at most 1
+    #[structopt(long = "efficiency", default_value = "0.85", parse(try_from_str = efficiency))]
+    efficiency: f64,
+
+    /// Fraction of braking energy regenerated, from 0 to 1
+    #[structopt(long = "regeneration", default_value = "0.0", parse(try_from_str = fraction))]
+    regeneration: f64,
+
+    /// Output time-distance diagram as SVG to file
+    #[structopt(short = "t", long = "time-distance", parse(from_os_str))]
+    timedistance: Option<PathBuf>,
+
+    /// Nodes to pass in the time-distance diagram (comma separated)
+    #[structopt(long = "path", use_delimiter = true)]
+    path: Vec<String>,
+
+    /// Output summary report of trains and infrastructure to file
+    #[structopt(short = "r", long = "report", parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    /// Summary report format
+    #[structopt(long = "report-format", default_value = "table", possible_values = &["table", "json"])]
+    report_format: String,
 }
 
-fn run(opt :&Opt) -> AppResult<()> {
-    // 
-    // Infrastructure
-    let (infrastructure,names) = get_infrastructure(&opt.infrastructure)?;
+#[derive(StructOpt, Debug)]
+struct TimetableOutput {
+    /// Output punctuality report to file (default: standard output)
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Output the dispatch plan generated from the timetable to file
+    #[structopt(long = "dispatch", parse(from_os_str))]
+    dispatch: Option<PathBuf>,
+
+    /// Largest delay in seconds counted as on time
+    #[structopt(long = "tolerance", default_value = "180.0")]
+    tolerance: f64,
+}
+
+#[derive(StructOpt, Debug)]
+struct CompareOutput {
+    /// Output file (default: standard output)
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Output time-distance diagram of both variants as SVG to file
+    #[structopt(short = "t", long = "time-distance", parse(from_os_str))]
+    timedistance: Option<PathBuf>,
+
+    /// Nodes to pass in the time-distance diagram (comma separated)
+    #[structopt(long = "path", use_delimiter = true)]
+    path: Vec<String>,
+}
+
+impl SimOutput {
+    /// Whether any output needs the full history after the simulation.
+    fn needs_history(&self) -> bool {
+        self.history.is_some() || self.json.is_some() || self.javascript.is_some() ||
+            self.visits.is_some() || self.profile.is_some() || self.energy.is_some() ||
+            self.timedistance.is_some() || self.report.is_some()
+    }
+}
+
+/// Parse a command-line number which must be greater than zero.
+fn positive_number(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 => Ok(x),
+        Ok(_) => Err(format!("must be greater than zero: {}", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse a command-line efficiency, which must be greater than zero and at most one.
+fn efficiency(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x <= 1.0 => Ok(x),
+        Ok(_) => Err(format!("must be greater than 0 and at most 1: {}", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse a command-line fraction, which must be from zero to one.
+fn fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x >= 0.0 && x <= 1.0 => Ok(x),
+        Ok(_) => Err(format!("must be from 0 to 1: {}", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Write to the given file, or to standard output if no file is given.
+fn write_output(path: Option<&Path>, contents: &str) -> AppResult<()> {
+    match path {
+        Some(path) => {
+            let file = File::create(path)?;
+            let mut writer = BufWriter::new(&file);
+            write!(writer, "{}", contents)?;
+        },
+        None => {
+            let stdout = std::io::stdout();
+            write!(stdout.lock(), "{}", contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prefix the error message with the file name, keeping the error as the cause.
+fn in_file(path: &Path, e: failure::Error) -> failure::Error {
+    let msg = format!("{}: {}", path.display(), e);
+    e.context(msg).into()
+}
+
+fn load_infrastructure(opt: &Opt, path: &Path) -> AppResult<(StaticInfrastructure, InfNames<String>)> {
+    let (infrastructure, names) = get_infrastructure(path).map_err(|e| in_file(path, e))?;
     if opt.verbose >= 2 {
         println!("Infrastructure:");
         println!("  Nodes:");
@@ -73,86 +374,391 @@ fn run(opt :&Opt) -> AppResult<()> {
             println!("    * {:?}", x);
         }
     }
+    Ok((infrastructure, names))
+}
+
+fn load_routes(opt: &Opt, path: &Path, names: &InfNames<String>) -> AppResult<Routes<String>> {
+    let routes = get_routes(path, names).map_err(|e| in_file(path, e))?;
+    if opt.verbose >= 2 {
+        println!("Routes:");
+        for x in &routes { println!("  - {:?}", x); }
+    }
+    Ok(routes)
+}
 
-    if opt.graphical.is_none() {
-        // Routes
-        let routes = get_routes(&opt.routes, &names)?;
-        if opt.verbose >= 2 {
-            println!("Routes:");
-            for x in &routes { println!("  - {:?}", x); }
+fn load_dispatch(opt: &Opt, path: &Path, routes: Option<&Routes<String>>) -> AppResult<Dispatch<String>> {
+    let dispatch = get_dispatch(path).map_err(|e| in_file(path, e))?;
+    if let Some(routes) = routes {
+        let unknown = rolling::input::dispatch::unknown_routes(&dispatch, routes);
+        if unknown.len() > 0 {
+            return Err(failure::err_msg(format!("{}: unknown routes: {}", path.display(), unknown.join(", "))));
         }
+    }
+    if opt.verbose >= 1 {
+        println!("Dispatch:");
+        for x in &dispatch.actions { println!("  - {:?}", x); }
+        println!("");
+    }
+    Ok(dispatch)
+}
 
-        // Dispatch
-        let dispatch = get_dispatch(&opt.dispatch)?;
-        if opt.verbose >= 1 {
-            println!("Dispatch:");
-            for x in &dispatch.actions { println!("  - {:?}", x); }
-            println!("");
+fn print_trains(history: &History) {
+    for &(ref name, _, ref events) in &history.trains {
+        let r = rolling::output::report::train_report(name, events);
+        match r.entry {
+            Some(entry) => {
+                let exit = r.exit.map(|t| format!("exit {:.1} s", t)).unwrap_or("still in model".to_string());
+                println!("  {}: entry {:.1} s, {}, running time {:.1} s, distance {:.1} m, average speed {:.1} m/s",
+                         name, entry, exit, r.running_time, r.distance,
+                         if r.running_time > 0.0 { r.distance / r.running_time } else { 0.0 });
+            },
+            None => println!("  {}: did not enter the model", name),
         }
+    }
+}
+
+fn simulate(opt: &Opt, input: &Input, sim: &SimOpt, output: &SimOutput) -> AppResult<()> {
+    let (infrastructure, names) = load_infrastructure(opt, &input.infrastructure)?;
+    let routes = load_routes(opt, &input.routes, &names)?;
+    let dispatch = load_dispatch(opt, &input.dispatch, Some(&routes))?;
 
-        // Eval -> history
-        let history = rolling::evaluate_plan(&infrastructure, &routes, &dispatch, opt.timestep);
+    // Check output options before running the simulation
+    let path = match output.timedistance {
+        Some(_) => Some(rolling::output::timedistance::path_from_names(&infrastructure, &names, &output.path)?),
+        None => None,
+    };
+
+    // Eval -> history
+    let json_lines = match output.json_lines {
+        Some(ref json_lines) => {
+            let writer = BufWriter::new(File::create(json_lines)?);
+            Some(rolling::output::json::JsonLinesLogger::new(&infrastructure, &names, writer)?)
+        },
+        None => None,
+    };
+    // When only streaming output is requested, the history is not kept in memory.
+    let keep_history = output.needs_history() || !opt.quiet;
+    let history = if keep_history { Some(History::default()) } else { None };
+    let log = Rc::new(RefCell::new((history, json_lines)));
+    rolling::evaluate_plan_logged(&infrastructure, &routes, &dispatch,
+                                  sim.timestep, sim.signalling, log.clone());
+    let (history, json_lines) = log.replace((None, None));
+    if let Some(json_lines) = json_lines {
+        json_lines.finish()?;
+    }
+    let history = match history {
+        Some(history) => history,
+        None => return Ok(()),
+    };
+
+    if !opt.quiet {
+        println!("Trains:");
+        print_trains(&history);
+    }
 
-        // Print
-        println!("# Infrastructure history:");
+    if let Some(ref file) = output.history {
+        use std::fmt::Write;
+        let mut s = String::new();
+        writeln!(s, "# Infrastructure history:")?;
         for x in &history.inf {
-            println!("> {:?}", x);
+            writeln!(s, "> {:?}", x)?;
         }
         for &(ref name,ref params, ref x) in &history.trains {
-            println!("## Train \"{}\" {:?}:", name, params);
+            writeln!(s, "## Train \"{}\" {:?}:", name, params)?;
             for x in x {
-                println!("> {:?}", x);
+                writeln!(s, "> {:?}", x)?;
             }
         }
+        write_output(Some(file), &s)?;
+    }
 
-        if let Some(ref json) = opt.json {
-            use std::fs::File;
-            use std::io::BufWriter;
-            let mut file = File::create(json)?;
-            let mut writer = BufWriter::new(&file);
-            rolling::output::json::json_history(&infrastructure, &names, &history, &mut writer)?;
+    if let Some(ref json) = output.json {
+        let file = File::create(json)?;
+        let mut writer = BufWriter::new(&file);
+        rolling::output::json::json_history(&infrastructure, &names, &history, &mut writer)?;
+    }
+
+    if let Some(ref javascript) = output.javascript {
+        let file = File::create(javascript)?;
+        let mut writer = BufWriter::new(&file);
+        rolling::output::json::javascript_history(&infrastructure, &names, &history, &mut writer)?;
+    }
+
+    if let Some(ref visits) = output.visits {
+        let string = rolling::output::history::visits(&names, &history)?;
+        write_output(Some(visits), &string)?;
+    }
+
+    if let (&Some(ref timedistance), &Some(ref path)) = (&output.timedistance, &path) {
+        let string = rolling::output::timedistance::time_distance_svg(&infrastructure, &names, &history, path)?;
+        write_output(Some(timedistance), &string)?;
+    }
+
+    if let Some(ref profile) = output.profile {
+        use rolling::output::profile::{profile_csv, profile_json};
+        let file = File::create(profile)?;
+        let mut writer = BufWriter::new(&file);
+        if output.profile_format == "json" {
+            profile_json(&history, output.resolution, &mut writer)?;
+        } else {
+            profile_csv(&history, output.resolution, &mut writer)?;
         }
+    }
 
-        if let Some(ref javascript) = opt.javascript {
-            use std::fs::File;
-            use std::io::BufWriter;
-            let mut file = File::create(javascript)?;
-            let mut writer = BufWriter::new(&file);
-            rolling::output::json::javascript_history(&infrastructure, &names, &history, &mut writer)?;
+    if let Some(ref energy) = output.energy {
+        use rolling::output::energy::{EnergyParams, energy_report};
+        let params = EnergyParams {
+            mass: output.mass,
+            efficiency: output.efficiency,
+            regeneration: output.regeneration,
+        };
+        let string = energy_report(&rolling::output::energy::energy(&infrastructure, &history, &params)?)?;
+        write_output(Some(energy), &string)?;
+    }
+
+    if let Some(ref report) = output.report {
+        use rolling::output::report::{report_json, report_table};
+        let r = rolling::output::report::report(&infrastructure, &names, &history);
+        if output.report_format == "json" {
+            let file = File::create(report)?;
+            report_json(&r, &mut BufWriter::new(&file))?;
+        } else {
+            write_output(Some(report), &report_table(&r)?)?;
         }
-        
-        if let Some(ref visits) = opt.visits {
-            use std::fs::File;
-            use std::io::BufWriter;
-            let mut file = File::create(visits)?;
-            let mut writer = BufWriter::new(&file);
-            let string = rolling::output::history::visits(&names, &history)?;
-            use std::io::Write;
-            write!(writer,"{}",string)?;
+    }
+
+    Ok(())
+}
+
+fn validate(opt: &Opt, infrastructure: &Path, routes: Option<&Path>, dispatch: Option<&Path>) -> AppResult<()> {
+    let (inf, names) = load_infrastructure(opt, infrastructure)?;
+    if !opt.quiet {
+        println!("{}: {} nodes, {} objects", infrastructure.display(), inf.nodes.len(), inf.objects.len());
+    }
+    let routes = match routes {
+        Some(path) => {
+            let routes = load_routes(opt, path, &names)?;
+            if !opt.quiet { println!("{}: {} routes", path.display(), routes.len()); }
+            Some(routes)
+        },
+        None => None,
+    };
+    if let Some(path) = dispatch {
+        let dispatch = load_dispatch(opt, path, routes.as_ref())?;
+        if !opt.quiet { println!("{}: {} actions", path.display(), dispatch.actions.len()); }
+    }
+    Ok(())
+}
+
+fn stats(opt: &Opt, infrastructure: &Path, routes: Option<&Path>, dispatch: Option<&Path>, sim: &SimOpt) -> AppResult<()> {
+    let (inf, names) = load_infrastructure(opt, infrastructure)?;
+    let routes = match routes {
+        Some(path) => Some(load_routes(opt, path, &names)?),
+        None => None,
+    };
+    let history = match dispatch {
+        Some(path) => {
+            let routes = routes.as_ref().ok_or_else(|| failure::err_msg("routes are required to simulate the dispatch"))?;
+            let dispatch = load_dispatch(opt, path, Some(routes))?;
+            Some(rolling::evaluate_plan(&inf, routes, &dispatch, sim.timestep, sim.signalling))
+        },
+        None => None,
+    };
+    if opt.quiet { return Ok(()); }
+
+    let mut signals = 0;
+    let mut switches = 0;
+    let mut sections = 0;
+    for obj in &inf.objects {
+        match *obj {
+            StaticObject::Signal { .. } => signals += 1,
+            StaticObject::Switch { .. } => switches += 1,
+            StaticObject::TVDSection => sections += 1,
+            _ => {},
         }
     }
+    let boundaries = inf.nodes.iter().filter(|n| match n.edges { Edges::ModelBoundary => true, _ => false }).count();
+    // Each linear edge is given from both of its nodes, and each switch leg
+    // also has a linear edge back to the switch.
+    let length = inf.nodes.iter().map(|n| match n.edges {
+        Edges::Single(_, d) => d,
+        _ => 0.0,
+    }).sum::<f64>() / 2.0;
 
-    if let Some(ref graphical) = opt.graphical {
-        use std::fs::File;
-        use std::io::BufWriter;
-        let string = rolling::output::graphical::graphical(&infrastructure, &names)?;
-        use std::io::Write;
-        let mut file = File::create(graphical)?;
-        let mut writer = BufWriter::new(&file);
-        write!(writer,"{}",string)?;
+    println!("Infrastructure:");
+    println!("  nodes: {}", inf.nodes.len());
+    println!("  boundaries: {}", boundaries);
+    println!("  signals: {}", signals);
+    println!("  switches: {}", switches);
+    println!("  detection sections: {}", sections);
+    println!("  track length: {:.1} m", length);
+
+    if let Some(ref routes) = routes {
+        let count = |f: &dyn Fn(&RouteEntryExit, &RouteEntryExit) -> bool| routes.values().filter(|r| f(&r.entry, &r.exit)).count();
+        println!("Routes:");
+        println!("  total: {}", routes.len());
+        println!("  model entry: {}", count(&|a, _| a.is_boundary()));
+        println!("  model exit: {}", count(&|_, b| b.is_boundary()));
+        println!("  signal to signal: {}", count(&|a, b| !a.is_boundary() && !b.is_boundary()));
+        println!("  with overlaps: {}", routes.values().filter(|r| r.overlaps.len() > 0).count());
+    }
+
+    if let Some(ref history) = history {
+        let report = rolling::output::report::report(&inf, &names, history);
+        println!("Simulation:");
+        print!("{}", rolling::output::report::report_table(&report)?);
+    }
+    Ok(())
+}
+
+fn timetable(opt: &Opt, infrastructure: &Path, routes: &Path, timetable: &Path, sim: &SimOpt,
+             output: &TimetableOutput) -> AppResult<()> {
+    use rolling::input::timetable::timetable_dispatch;
+    use rolling::output::punctuality::{punctuality, punctuality_report};
+    let (inf, names) = load_infrastructure(opt, infrastructure)?;
+    let routes = load_routes(opt, routes, &names)?;
+    let tt = get_timetable(timetable).map_err(|e| in_file(timetable, e))?;
+    let dispatch = timetable_dispatch(&tt, &inf, &names, &routes).map_err(|e| in_file(timetable, e.into()))?;
+    if opt.verbose >= 1 {
+        println!("Dispatch:");
+        for x in &dispatch.actions { println!("  - {:?}", x); }
+        println!("");
+    }
+    if let Some(ref file) = output.dispatch {
+        write_output(Some(file), &rolling::input::dispatch::dispatch_string(&dispatch))?;
     }
 
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, sim.timestep, sim.signalling);
+    let p = punctuality(&tt, &inf, &names, &history, output.tolerance).map_err(|e| in_file(timetable, e))?;
+    if output.output.is_some() || !opt.quiet {
+        write_output(path(&output.output), &punctuality_report(&p)?)?;
+    }
+    Ok(())
+}
+
+fn compare(opt: &Opt, a: (&Path, &Path), b: (&Path, &Path), dispatch: &Path, sim: &SimOpt,
+           output: &CompareOutput) -> AppResult<()> {
+    use rolling::output::diff::{diff, diff_report, Variant};
+    use rolling::output::timedistance::{path_from_names, time_distance_overlay_svg};
+    let (inf_a, names_a) = load_infrastructure(opt, a.0)?;
+    let routes_a = load_routes(opt, a.1, &names_a)?;
+    let (inf_b, names_b) = load_infrastructure(opt, b.0)?;
+    let routes_b = load_routes(opt, b.1, &names_b)?;
+    let dispatch_a = load_dispatch(opt, dispatch, Some(&routes_a))?;
+    let dispatch_b = load_dispatch(opt, dispatch, Some(&routes_b))?;
+    let via = &output.path;
+    let paths = match output.timedistance {
+        Some(_) => Some((path_from_names(&inf_a, &names_a, via).map_err(|e| in_file(a.0, e))?,
+                         path_from_names(&inf_b, &names_b, via).map_err(|e| in_file(b.0, e))?)),
+        None => None,
+    };
+
+    let history_a = rolling::evaluate_plan(&inf_a, &routes_a, &dispatch_a, sim.timestep, sim.signalling);
+    let history_b = rolling::evaluate_plan(&inf_b, &routes_b, &dispatch_b, sim.timestep, sim.signalling);
+
+    let d = diff(Variant { inf: &inf_a, names: &names_a, history: &history_a },
+                 Variant { inf: &inf_b, names: &names_b, history: &history_b });
+    if output.output.is_some() || !opt.quiet {
+        write_output(path(&output.output), &diff_report(&d)?)?;
+    }
+    if let (&Some(ref file), Some((path_a, path_b))) = (&output.timedistance, paths) {
+        let string = time_distance_overlay_svg(&inf_a, &names_a, &history_a, &path_a,
+                                               (&inf_b, &history_b, &path_b))?;
+        write_output(Some(file), &string)?;
+    }
     Ok(())
 }
 
+fn convert(opt: &Opt, railml: &Path, infrastructure: Option<&Path>, routes: Option<&Path>,
+           overlaps: Option<railml2dgraph::routes::OverlapOptions>) -> AppResult<()> {
+    use railml2dgraph::output;
+    let (doc, ns) = railml2dgraph::get_xml(railml, opt.verbose >= 1).map_err(failure::err_msg)?;
+    let (dgraph, _sections, diagnostics) = railml2dgraph::convert(&doc, &ns).map_err(failure::err_msg)?;
+    for d in &diagnostics {
+        eprintln!("Warning: {}", d);
+    }
+
+    if infrastructure.is_some() || routes.is_none() {
+        let mut buf = Vec::new();
+        output::print_rolling(&mut buf, &dgraph)?;
+        write_output(infrastructure, &String::from_utf8(buf)?)?;
+    }
+    if let Some(f) = routes {
+        let (routes, differences) = railml2dgraph::convert_routes(&doc, &ns, &dgraph, overlaps.as_ref()).map_err(failure::err_msg)?;
+        for d in &differences {
+            eprintln!("Warning: {}", d);
+        }
+        let mut buf = Vec::new();
+        output::print_routes(&mut buf, &dgraph, &routes)?;
+        write_output(Some(f), &String::from_utf8(buf)?)?;
+    }
+    Ok(())
+}
+
+fn path(x: &Option<PathBuf>) -> Option<&Path> {
+    x.as_ref().map(|x| x.as_path())
+}
+
+fn run(opt :&Opt) -> AppResult<()> {
+    match opt.command {
+        Command::Simulate { ref input, ref sim, ref output } =>
+            simulate(opt, input, sim, output),
+        Command::Validate { ref infrastructure, ref routes, ref dispatch } =>
+            validate(opt, infrastructure, path(routes), path(dispatch)),
+        Command::Graphical { ref infrastructure, ref output } => {
+            let (infrastructure, names) = load_infrastructure(opt, infrastructure)?;
+            let string = rolling::output::graphical::graphical(&infrastructure, &names)?;
+            write_output(path(output), &string)
+        },
+        Command::Routes { ref infrastructure, ref output } => {
+            let (infrastructure, names) = load_infrastructure(opt, infrastructure)?;
+            let string = rolling::output::routes::generate_routes(&infrastructure, &names)?;
+            write_output(path(output), &string)
+        },
+        Command::Stats { ref infrastructure, ref routes, ref dispatch, ref sim } =>
+            stats(opt, infrastructure, path(routes), path(dispatch), sim),
+        Command::Timetable { ref infrastructure, ref routes, ref timetable, ref sim, ref output } =>
+            self::timetable(opt, infrastructure, routes, timetable, sim, output),
+        Command::Compare { ref infrastructure_a, ref routes_a, ref infrastructure_b, ref routes_b,
+                           ref dispatch, ref sim, ref output } =>
+            compare(opt, (infrastructure_a, routes_a), (infrastructure_b, routes_b), dispatch, sim, output),
+        Command::Convert { ref railml, ref infrastructure, ref routes, overlap, overlap_timeout } => {
+            let overlaps = overlap.map(|length| railml2dgraph::routes::OverlapOptions {
+                length: length,
+                timeout: overlap_timeout,
+            });
+            convert(opt, railml, path(infrastructure), path(routes), overlaps)
+        },
+        Command::Export { ref infrastructure, ref output, version } => {
+            let (infrastructure, names) = load_infrastructure(opt, infrastructure)?;
+            let model = rolling::output::routes::dgraph_model(&infrastructure, &names)?;
+            let version = if version == 3 { railml2dgraph::RailMLVersion::V3 } else { railml2dgraph::RailMLVersion::V2 };
+            let string = railml2dgraph::export::railml_string(&model, version).map_err(failure::err_msg)?;
+            write_output(path(output), &string)
+        },
+    }
+}
+
 pub fn main() {
-    let opt = Opt::from_args();
-    println!("{:?}", opt);
+    let opt = match Opt::clap().get_matches_safe() {
+        Ok(matches) => Opt::from_clap(&matches),
+        Err(e) => match e.kind {
+            clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                std::process::exit(EXIT_USAGE);
+            },
+        },
+    };
+    if opt.verbose >= 2 {
+        println!("{:?}", opt);
+    }
     match run(&opt) {
         Ok(()) => {},
         Err(e) => {
-            println!("Error:\n{}", e.as_fail());
-            std::process::exit(1);
+            eprintln!("Error: {}", e);
+            let io_error = e.iter_chain().any(|c| c.downcast_ref::<std::io::Error>().is_some());
+            std::process::exit(if io_error { EXIT_IO } else { EXIT_INVALID_INPUT });
         },
     }
 }
@@ -0,0 +1,176 @@
+//! Comparison of two histories from the same dispatch plan on two model variants.
+//!
+//! Trains are aligned by name, node visits by node name and the number of earlier
+//! visits to the same node, and detection sections by name, so that the variants
+//! may have different nodes and objects.
+
+use failure::Error;
+use std::collections::HashMap;
+
+use input::staticinfrastructure::{StaticInfrastructure, InfNames};
+use output::history::{History, TrainLogEvent};
+use output::report::{self, Report, TrainReport};
+
+/// Time differences below this are not reported.
+const TOLERANCE: f64 = 0.05;
+
+#[derive(Clone, Debug)]
+pub struct VisitDiff {
+    pub node: String,
+    pub a: Option<f64>,
+    pub b: Option<f64>,
+}
+
+impl VisitDiff {
+    pub fn changed(&self) -> bool {
+        match (self.a, self.b) {
+            (Some(a), Some(b)) => (b - a).abs() > TOLERANCE,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrainDiff {
+    pub name: String,
+    pub a: Option<TrainReport>,
+    pub b: Option<TrainReport>,
+    pub visits: Vec<VisitDiff>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SectionDiff {
+    pub name: String,
+    /// Time occupied in each variant.
+    pub a: Option<f64>,
+    pub b: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryDiff {
+    pub trains: Vec<TrainDiff>,
+    pub sections: Vec<SectionDiff>,
+}
+
+/// A simulated model variant.
+#[derive(Copy, Clone)]
+pub struct Variant<'a> {
+    pub inf: &'a StaticInfrastructure,
+    pub names: &'a InfNames<String>,
+    pub history: &'a History,
+}
+
+/// Node visits of a train as (node name, time).
+fn node_visits(names: &InfNames<String>, events: &[TrainLogEvent]) -> Vec<(String, f64)> {
+    let node_names = names.node_names.iter().map(|(k, v)| (*v, k.as_str())).collect::<HashMap<_, _>>();
+    let mut visits = Vec::new();
+    let mut t = 0.0;
+    for ev in events {
+        match *ev {
+            TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt, _, _) => t += dt,
+            TrainLogEvent::Node(n) => visits.push((node_names.get(&n).cloned().unwrap_or("?").to_string(), t)),
+            _ => {},
+        }
+    }
+    visits
+}
+
+/// Align node visits by node name and occurrence, ordered by time.
+fn align_visits(a: Vec<(String, f64)>, b: Vec<(String, f64)>) -> Vec<VisitDiff> {
+    let key = |v: &[(String, f64)]| {
+        let mut count = HashMap::new();
+        v.iter().map(|&(ref n, t)| {
+            let c = count.entry(n.clone()).or_insert(0);
+            *c += 1;
+            ((n.clone(), *c), t)
+        }).collect::<Vec<_>>()
+    };
+    let a = key(&a);
+    let mut b = key(&b).into_iter().collect::<HashMap<_, _>>();
+    let mut visits = a.into_iter().map(|(k, t)| {
+        let tb = b.remove(&k);
+        VisitDiff { node: k.0, a: Some(t), b: tb }
+    }).collect::<Vec<_>>();
+    visits.extend(b.into_iter().map(|(k, t)| VisitDiff { node: k.0, a: None, b: Some(t) }));
+    visits.sort_by(|x, y| {
+        let tx = x.a.or(x.b).unwrap();
+        let ty = y.a.or(y.b).unwrap();
+        tx.partial_cmp(&ty).unwrap()
+    });
+    visits
+}
+
+/// Compare the histories of two model variants.
+pub fn diff(a: Variant, b: Variant) -> HistoryDiff {
+    let report_a = report::report(a.inf, a.names, a.history);
+    let report_b = report::report(b.inf, b.names, b.history);
+
+    let mut trains = Vec::new();
+    let mut names = a.history.trains.iter().map(|t| &t.0).collect::<Vec<_>>();
+    for t in &b.history.trains {
+        if !names.contains(&&t.0) { names.push(&t.0); }
+    }
+    for name in names {
+        let train = |r: &Report| r.trains.iter().find(|t| &t.name == name).cloned();
+        let visits = |v: &Variant| v.history.trains.iter().find(|t| &t.0 == name)
+            .map(|t| node_visits(v.names, &t.2)).unwrap_or_default();
+        trains.push(TrainDiff {
+            name: name.clone(),
+            a: train(&report_a),
+            b: train(&report_b),
+            visits: align_visits(visits(&a), visits(&b)),
+        });
+    }
+
+    let mut sections = report_a.sections.iter()
+        .map(|s| SectionDiff { name: s.name.clone(), a: Some(s.occupied_time), b: None })
+        .collect::<Vec<_>>();
+    for s in &report_b.sections {
+        match sections.iter().position(|x| x.name == s.name) {
+            Some(i) => sections[i].b = Some(s.occupied_time),
+            None => sections.push(SectionDiff { name: s.name.clone(), a: None, b: Some(s.occupied_time) }),
+        }
+    }
+    sections.sort_by(|x, y| x.name.cmp(&y.name));
+
+    HistoryDiff { trains: trains, sections: sections }
+}
+
+fn compare(a: Option<f64>, b: Option<f64>) -> String {
+    match (a, b) {
+        (Some(a), Some(b)) => format!("{:.1} s -> {:.1} s ({:+.1} s)", a, b, b - a),
+        (Some(a), None) => format!("{:.1} s -> -", a),
+        (None, Some(b)) => format!("- -> {:.1} s", b),
+        (None, None) => "-".to_string(),
+    }
+}
+
+/// Write the differences as text. Only node visits and sections which
+/// differ between the variants are listed.
+pub fn diff_report(d: &HistoryDiff) -> Result<String, Error> {
+    use std::fmt::Write;
+    let mut s = String::new();
+    writeln!(s, "Trains:")?;
+    for t in &d.trains {
+        match (&t.a, &t.b) {
+            (&Some(ref a), &Some(ref b)) => {
+                writeln!(s, "  {}: running time {}, signal stops {} -> {}", t.name,
+                         compare(a.entry.map(|_| a.running_time), b.entry.map(|_| b.running_time)),
+                         a.signal_stops, b.signal_stops)?;
+            },
+            (&Some(_), &None) => writeln!(s, "  {}: only in first variant", t.name)?,
+            (&None, _) => writeln!(s, "  {}: only in second variant", t.name)?,
+        }
+        for v in t.visits.iter().filter(|v| v.changed()) {
+            writeln!(s, "    {}: {}", v.node, compare(v.a, v.b))?;
+        }
+    }
+    writeln!(s, "Sections occupied:")?;
+    for x in d.sections.iter().filter(|x| match (x.a, x.b) {
+        (Some(a), Some(b)) => (b - a).abs() > TOLERANCE,
+        _ => true,
+    }) {
+        writeln!(s, "  {}: {}", x.name, compare(x.a, x.b))?;
+    }
+    Ok(s)
+}
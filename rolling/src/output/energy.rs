@@ -0,0 +1,143 @@
+//! Energy consumption estimated from train trajectories.
+//!
+//! The train model has no running resistance, so the mechanical energy at the wheel
+//! is the change in kinetic energy plus the change in potential energy on gradients,
+//! taken at the front of the train. Positive work is done by traction and negative
+//! work by braking. Coasting on level track uses no energy.
+
+use input::staticinfrastructure::StaticInfrastructure;
+use output::history::{History, TrainLogEvent};
+use failure;
+
+const GRAVITY: f64 = 9.81;
+
+/// Vehicle parameters for energy calculation.
+#[derive(Copy, Clone, Debug)]
+pub struct EnergyParams {
+    /// Train mass in kg.
+    pub mass: f64,
+    /// Efficiency of the traction system, from supply to wheel (0.0 - 1.0).
+    pub efficiency: f64,
+    /// Fraction of braking energy which is recovered by regenerative braking (0.0 - 1.0).
+    pub regeneration: f64,
+}
+
+impl EnergyParams {
+    /// Check that the mass is positive, the efficiency is in (0, 1] and the
+    /// regeneration is in [0, 1].
+    pub fn check(&self) -> Result<(), failure::Error> {
+        if !(self.mass > 0.0) {
+            return Err(failure::err_msg(format!("train mass must be positive: {}", self.mass)));
+        }
+        if !(self.efficiency > 0.0 && self.efficiency <= 1.0) {
+            return Err(failure::err_msg(format!("efficiency must be greater than 0 and at most 1: {}", self.efficiency)));
+        }
+        if !(self.regeneration >= 0.0 && self.regeneration <= 1.0) {
+            return Err(failure::err_msg(format!("regeneration must be from 0 to 1: {}", self.regeneration)));
+        }
+        Ok(())
+    }
+}
+
+/// Energy use of a single train, in joules.
+#[derive(Clone, Debug)]
+pub struct TrainEnergy {
+    pub name: String,
+    /// Mechanical energy at the wheel used for accelerating and climbing.
+    pub traction: f64,
+    /// Mechanical energy removed by braking.
+    pub braking: f64,
+    /// Energy taken from the supply for traction.
+    pub consumed: f64,
+    /// Energy returned to the supply by regenerative braking.
+    pub regenerated: f64,
+}
+
+impl TrainEnergy {
+    /// Net energy taken from the supply.
+    pub fn net(&self) -> f64 {
+        self.consumed - self.regenerated
+    }
+}
+
+/// Energy use of all trains in a history.
+#[derive(Clone, Debug)]
+pub struct EnergyReport {
+    pub trains: Vec<TrainEnergy>,
+}
+
+impl EnergyReport {
+    pub fn total(&self) -> TrainEnergy {
+        let mut total = TrainEnergy {
+            name: "total".to_string(),
+            traction: 0.0, braking: 0.0, consumed: 0.0, regenerated: 0.0,
+        };
+        for t in &self.trains {
+            total.traction += t.traction;
+            total.braking += t.braking;
+            total.consumed += t.consumed;
+            total.regenerated += t.regenerated;
+        }
+        total
+    }
+}
+
+/// Integrate the energy use of a train from its log events. The gradient of
+/// each edge is taken from the infrastructure.
+pub fn train_energy(inf: &StaticInfrastructure, name: &str, params: &EnergyParams,
+                    events: &[TrainLogEvent]) -> TrainEnergy {
+    let mut e = TrainEnergy {
+        name: name.to_string(),
+        traction: 0.0, braking: 0.0, consumed: 0.0, regenerated: 0.0,
+    };
+    let mut v = 0.0;
+    let mut gradient = 0.0;
+    for ev in events {
+        match *ev {
+            TrainLogEvent::Edge(start, _) => gradient = inf.nodes[start].gradient,
+            TrainLogEvent::Move(_dt, _action, update) => {
+                let kinetic = 0.5 * params.mass * (update.v * update.v - v * v);
+                let potential = params.mass * GRAVITY * update.dx * gradient / 1000.0;
+                let work = kinetic + potential;
+                if work > 0.0 { e.traction += work; } else { e.braking -= work; }
+                v = update.v;
+            }
+            _ => {},
+        }
+    }
+    e.consumed = e.traction / params.efficiency;
+    e.regenerated = e.braking * params.regeneration * params.efficiency;
+    e
+}
+
+/// Energy use of each train in the history, using the same vehicle parameters for all trains.
+pub fn energy(inf: &StaticInfrastructure, h: &History, params: &EnergyParams) -> Result<EnergyReport, failure::Error> {
+    energy_per_train(inf, h, |_name| *params)
+}
+
+/// Energy use of each train in the history, with vehicle parameters given by train name.
+pub fn energy_per_train<F: Fn(&str) -> EnergyParams>(inf: &StaticInfrastructure, h: &History, params: F)
+    -> Result<EnergyReport, failure::Error> {
+    let mut trains = Vec::new();
+    for &(ref name, ref _params, ref events) in &h.trains {
+        let params = params(name);
+        params.check()?;
+        trains.push(train_energy(inf, name, &params, events));
+    }
+    Ok(EnergyReport { trains })
+}
+
+/// Print one train per line on the following format, with energy in kWh:
+/// `trainname traction braking consumed regenerated net`.
+/// The last line gives the totals.
+pub fn energy_report(report: &EnergyReport) -> Result<String, failure::Error> {
+    use std::fmt::Write;
+    let kwh = 1.0 / 3.6e6;
+    let mut s = String::new();
+    for t in report.trains.iter().chain(Some(report.total()).iter()) {
+        write!(s, "{} {:.3} {:.3} {:.3} {:.3} {:.3}\n", t.name,
+               t.traction * kwh, t.braking * kwh, t.consumed * kwh,
+               t.regenerated * kwh, t.net() * kwh)?;
+    }
+    Ok(s)
+}
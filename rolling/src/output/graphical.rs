@@ -77,7 +77,7 @@ pub fn graphical(inf :&StaticInfrastructure, names :&InfNames<String>) -> Result
                 if !visited.contains(&opposite_down) { queue.push(opposite_down); }
             },
             Edges::Switchable(obj) => {
-                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side } = inf.objects[obj] {
+                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side, .. } = inf.objects[obj] {
                     // Switch in down direction == incoming switch
                     nodes.push((n, GNode::Sw(*branch_side, SwDir::Incoming, inf.nodes[n].other_node,
                                              (left_link.0, right_link.0))));
@@ -113,7 +113,7 @@ pub fn graphical(inf :&StaticInfrastructure, names :&InfNames<String>) -> Result
                 if !visited.contains(&a) { queue.push(a); }
             },
             Edges::Switchable(obj) => {
-                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side } = inf.objects[obj] {
+                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side, .. } = inf.objects[obj] {
                     // Switch in up direction == outgoing switch
                     nodes.push((upnode, GNode::Sw(*branch_side, SwDir::Outgoing, n,
                                              (left_link.0, right_link.0))));
@@ -15,12 +15,73 @@ impl Default for History {
     }
 }
 
+/// Receives the history of a simulation as it is produced, so that it
+/// can be written out without keeping the whole history in memory.
+pub trait HistoryLogger {
+    /// Simulation time has advanced by `dt` seconds.
+    fn time(&mut self, dt: f64);
+    fn infrastructure(&mut self, ev: InfrastructureLogEvent);
+    /// A train has been dispatched. Trains are numbered in the order they are dispatched.
+    fn train_start(&mut self, name: &str, params: &TrainParams);
+    fn train(&mut self, train: usize, ev: TrainLogEvent);
+}
+
+impl HistoryLogger for History {
+    fn time(&mut self, dt: f64) {
+        if dt > 0.0 { self.inf.push(InfrastructureLogEvent::Wait(dt)); }
+    }
+    fn infrastructure(&mut self, ev: InfrastructureLogEvent) {
+        self.inf.push(ev);
+    }
+    fn train_start(&mut self, name: &str, params: &TrainParams) {
+        self.trains.push((name.to_string(), *params, Vec::new()));
+    }
+    fn train(&mut self, train: usize, ev: TrainLogEvent) {
+        self.trains[train].2.push(ev);
+    }
+}
+
+impl<L: HistoryLogger> HistoryLogger for Option<L> {
+    fn time(&mut self, dt: f64) {
+        if let Some(ref mut l) = *self { l.time(dt); }
+    }
+    fn infrastructure(&mut self, ev: InfrastructureLogEvent) {
+        if let Some(ref mut l) = *self { l.infrastructure(ev); }
+    }
+    fn train_start(&mut self, name: &str, params: &TrainParams) {
+        if let Some(ref mut l) = *self { l.train_start(name, params); }
+    }
+    fn train(&mut self, train: usize, ev: TrainLogEvent) {
+        if let Some(ref mut l) = *self { l.train(train, ev); }
+    }
+}
+
+/// Send the history to two loggers.
+impl<A: HistoryLogger, B: HistoryLogger> HistoryLogger for (A, B) {
+    fn time(&mut self, dt: f64) {
+        self.0.time(dt);
+        self.1.time(dt);
+    }
+    fn infrastructure(&mut self, ev: InfrastructureLogEvent) {
+        self.0.infrastructure(ev.clone());
+        self.1.infrastructure(ev);
+    }
+    fn train_start(&mut self, name: &str, params: &TrainParams) {
+        self.0.train_start(name, params);
+        self.1.train_start(name, params);
+    }
+    fn train(&mut self, train: usize, ev: TrainLogEvent) {
+        self.0.train(train, ev.clone());
+        self.1.train(train, ev);
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum RouteStatus {
     Pending, Active, Released,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InfrastructureLogEvent {
     Wait(f64),
     Route(usize,RouteStatus), // TODO route identification is wrong?
@@ -30,7 +91,7 @@ pub enum InfrastructureLogEvent {
     Position(usize, SwitchPosition), // switch objectid
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TrainLogEvent {
     Wait(f64),
     Node(usize), // refer to nodeid
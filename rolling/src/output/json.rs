@@ -1,19 +1,368 @@
+//! JSON output of simulation histories.
+//!
+//! The complete history is written as a single JSON document by `json_history`,
+//! or as JavaScript (for the web viewer) by `javascript_history`.
+//! For long simulations, `JsonLinesLogger` writes the history while the simulation
+//! is running, as one JSON document per line.
+//!
+//! The schema is versioned by `SCHEMA_VERSION`, see the history section of the documentation.
+
 use failure::Error;
-use super::history;
-use railway::dynamics::DriverAction;
-
-use std::collections::HashMap;
-use input::staticinfrastructure::{StaticInfrastructure, SwitchPosition, InfNames};
-fn get(x: &HashMap<String, usize>, n: usize) -> &str {
-    for (k, v) in x.iter() {
-        if *v == n {
-            return k;
+use serde_json;
+use std::io;
+
+use super::history::{self, HistoryLogger, InfrastructureLogEvent, TrainLogEvent};
+use input::staticinfrastructure::{StaticInfrastructure, StaticObject, SwitchPosition, InfNames, NodeId, Edges};
+use railway::dynamics::{DriverAction, DistanceVelocity, TrainParams};
+
+use std::collections::BTreeMap;
+
+/// Version of the JSON history schema. Incremented on incompatible changes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Lookup table from node and object ids to names.
+#[derive(Clone, Debug)]
+pub struct Names {
+    nodes: Vec<String>,
+    objects: Vec<String>,
+    positions: Vec<Option<(String, f64)>>,
+    node_keys: Vec<String>,
+    object_keys: Vec<String>,
+}
+
+impl Names {
+    pub fn new(inf: &StaticInfrastructure, names: &InfNames<String>) -> Names {
+        let mut nodes = vec![String::new(); inf.nodes.len()];
+        for (name, &id) in names.node_names.iter() {
+            nodes[id] = name.clone();
+        }
+        let mut objects = vec![String::new(); inf.objects.len()];
+        for (name, &id) in names.object_names.iter() {
+            objects[id] = name.clone();
         }
+        let mut positions = vec![None; inf.nodes.len()];
+        for (&id, pos) in names.node_positions.iter() {
+            if let Some(abs_pos) = pos.abs_pos {
+                positions[id] = Some((pos.track.clone(), abs_pos));
+            }
+        }
+        let node_keys = nodes.iter().enumerate()
+            .map(|(i, n)| if n.is_empty() { format!("node#{}", i) } else { n.clone() }).collect();
+        let object_keys = objects.iter().enumerate()
+            .map(|(i, n)| if n.is_empty() { format!("object#{}", i) } else { n.clone() }).collect();
+        Names { nodes: nodes, objects: objects, positions: positions,
+                node_keys: node_keys, object_keys: object_keys }
+    }
+
+    /// Name of a node, or an empty string for unnamed nodes.
+    pub fn node(&self, n: NodeId) -> &str { &self.nodes[n] }
+    /// Name of an object, or an empty string for unnamed objects.
+    pub fn object(&self, n: usize) -> &str { &self.objects[n] }
+    /// Track id and absolute position in meters of a node, if known.
+    pub fn position(&self, n: NodeId) -> Option<(&str, f64)> {
+        self.positions[n].as_ref().map(|&(ref track, abs_pos)| (track.as_str(), abs_pos))
     }
-    panic!("unknown node/object {}", n);
+
+    /// Name of a node in the JSON output, where unnamed nodes are called `node#<id>`
+    /// so that they do not share a key.
+    fn node_key(&self, n: NodeId) -> &str { &self.node_keys[n] }
+    /// Name of an object in the JSON output, where unnamed objects are called `object#<id>`.
+    fn object_key(&self, n: usize) -> &str { &self.object_keys[n] }
 }
 
-use std::io;
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    other_node: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    km: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct JsonTVDLimit<'a> {
+    node: &'a str,
+    enter: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonObject<'a> {
+    Signal {
+        node: &'a str,
+    },
+    Switch {
+        node: &'a str,
+        left: &'a str,
+        right: &'a str,
+        branch_side: &'static str,
+        throw_time: f64,
+        branch_max_velocity: Option<f64>,
+    },
+    Tvd {
+        limits: Vec<JsonTVDLimit<'a>>,
+    },
+}
+
+#[derive(Serialize)]
+struct JsonSight<'a> {
+    node: &'a str,
+    signal: &'a str,
+    distance: f64,
+}
+
+#[derive(Serialize)]
+struct JsonInfrastructure<'a> {
+    nodes: BTreeMap<&'a str, JsonNode<'a>>,
+    objects: BTreeMap<&'a str, JsonObject<'a>>,
+    sights: Vec<JsonSight<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    events: Option<Vec<JsonInfEvent<'a>>>,
+}
+
+#[derive(Serialize)]
+struct JsonInfEvent<'a> {
+    time: f64,
+    event: &'static str,
+    #[serde(rename = "ref")]
+    reference: String,
+    value: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    train: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonParams {
+    length: f64,
+    max_acc: f64,
+    max_brk: f64,
+    max_vel: f64,
+}
+
+impl<'a> From<&'a TrainParams> for JsonParams {
+    fn from(p: &TrainParams) -> JsonParams {
+        JsonParams { length: p.length, max_acc: p.max_acc, max_brk: p.max_brk, max_vel: p.max_vel }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    n1: &'a str,
+    n2: Option<&'a str>,
+    start: f64,
+    end: f64,
+}
+
+#[derive(Serialize)]
+struct JsonTrainEvent<'a> {
+    time: f64,
+    action: &'static str,
+    x: f64,
+    dx: f64,
+    v: f64,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonTrain<'a> {
+    params: JsonParams,
+    events: Vec<JsonTrainEvent<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonHistory<'a> {
+    version: u32,
+    infrastructure: JsonInfrastructure<'a>,
+    trains: BTreeMap<&'a str, JsonTrain<'a>>,
+}
+
+fn side_name(side: SwitchPosition) -> &'static str {
+    match side {
+        SwitchPosition::Left => "left",
+        SwitchPosition::Right => "right",
+    }
+}
+
+fn action_name(action: DriverAction) -> &'static str {
+    match action {
+        DriverAction::Accel => "Accel",
+        DriverAction::Brake => "Brake",
+        DriverAction::Coast => "Coast",
+    }
+}
+
+fn infrastructure<'a>(inf: &StaticInfrastructure, names: &'a Names) -> JsonInfrastructure<'a> {
+    let mut nodes = BTreeMap::new();
+    let mut objects = BTreeMap::new();
+    let mut sights = Vec::new();
+    let mut limits = BTreeMap::new();
+    for (node_idx, node) in inf.nodes.iter().enumerate() {
+        let position = names.position(node_idx);
+        nodes.insert(names.node_key(node_idx), JsonNode {
+            other_node: names.node_key(node.other_node),
+            track: position.map(|(track, _)| track),
+            km: position.map(|(_, abs_pos)| abs_pos / 1000.0),
+        });
+
+        for &obj in &node.objects {
+            match inf.objects[obj] {
+                StaticObject::Signal { .. } => {
+                    objects.insert(names.object_key(obj), JsonObject::Signal { node: names.node_key(node_idx) });
+                }
+                StaticObject::Sight { distance, signal } => {
+                    sights.push(JsonSight {
+                        node: names.node_key(node_idx),
+                        signal: names.object_key(signal),
+                        distance: distance,
+                    });
+                }
+                StaticObject::TVDLimit { enter, exit } => {
+                    for (tvd, is_enter) in enter.map(|t| (t, true)).into_iter().chain(exit.map(|t| (t, false))) {
+                        limits.entry(tvd).or_insert_with(Vec::new)
+                            .push(JsonTVDLimit { node: names.node_key(node_idx), enter: is_enter });
+                    }
+                }
+                _ => {},
+            }
+        }
+
+        if let Edges::Switchable(sw) = node.edges {
+            if let StaticObject::Switch { left_link, right_link, branch_side, throw_time, branch_max_velocity } = inf.objects[sw] {
+                objects.insert(names.object_key(sw), JsonObject::Switch {
+                    node: names.node_key(node_idx),
+                    left: names.node_key(left_link.0),
+                    right: names.node_key(right_link.0),
+                    branch_side: side_name(branch_side),
+                    throw_time: throw_time,
+                    branch_max_velocity: branch_max_velocity,
+                });
+            }
+        }
+    }
+    for (tvd, limits) in limits {
+        objects.insert(names.object_key(tvd), JsonObject::Tvd { limits: limits });
+    }
+    JsonInfrastructure { nodes: nodes, objects: objects, sights: sights, events: None }
+}
+
+/// Converts infrastructure log events to JSON events, keeping track of time.
+struct InfTracker {
+    time: f64,
+}
+
+impl InfTracker {
+    fn event<'a, F: Fn(usize) -> &'a str>(&mut self, names: &'a Names, train_name: F,
+                                          ev: &InfrastructureLogEvent) -> Option<JsonInfEvent<'a>> {
+        use self::InfrastructureLogEvent::*;
+        let (event, reference, value, train) = match *ev {
+            Wait(dt) => { self.time += dt; return None; }
+            Authority(n, x) => ("signal", names.object_key(n).to_string(),
+                                if x.0.is_some() { "green" } else { "red" }, None),
+            Route(n, x) => ("route", n.to_string(), match x {
+                history::RouteStatus::Pending => "Pending",
+                history::RouteStatus::Active => "Active",
+                history::RouteStatus::Released => "Released",
+            }, None),
+            Reserved(n, x) => ("reserved", names.object_key(n).to_string(),
+                               if x { "true" } else { "false" }, None),
+            Occupied(n, x, _node, train) => ("occupied", names.object_key(n).to_string(),
+                                             if x { "true" } else { "false" }, Some(train_name(train))),
+            Position(n, pos) => ("position", names.object_key(n).to_string(), side_name(pos), None),
+        };
+        Some(JsonInfEvent { time: self.time, event: event, reference: reference, value: value, train: train })
+    }
+}
+
+/// Converts train log events to JSON events, keeping track of time, distance travelled,
+/// and the edges currently covered by the train.
+struct TrainTracker {
+    time: f64,
+    x: f64,
+    length: f64,
+    started: bool,
+    edges: Vec<((NodeId, Option<NodeId>), (f64, f64))>,
+}
+
+impl TrainTracker {
+    fn new(params: &TrainParams) -> TrainTracker {
+        TrainTracker { time: 0.0, x: 0.0, length: params.length, started: false, edges: Vec::new() }
+    }
+
+    fn json_event<'a>(&self, names: &'a Names, action: DriverAction, dx: f64, v: f64) -> JsonTrainEvent<'a> {
+        JsonTrainEvent {
+            time: self.time,
+            action: action_name(action),
+            x: self.x,
+            dx: dx,
+            v: v,
+            edges: self.edges.iter().map(|&((n1, n2), (a, b))| JsonEdge {
+                n1: names.node_key(n1),
+                n2: n2.map(|n| names.node_key(n)),
+                start: a,
+                end: b,
+            }).collect(),
+        }
+    }
+
+    /// The first movement also gives an event for the train's initial state.
+    fn event<'a>(&mut self, names: &'a Names, ev: &TrainLogEvent) -> Vec<JsonTrainEvent<'a>> {
+        let mut out = Vec::new();
+        match *ev {
+            TrainLogEvent::Wait(dt) => self.time += dt,
+            TrainLogEvent::Edge(n1, n2) => self.edges.insert(0, ((n1, n2), (0.0, 0.0))),
+            TrainLogEvent::Move(dt, action, DistanceVelocity { dx, v }) => {
+                if !self.started {
+                    self.started = true;
+                    out.push(self.json_event(names, DriverAction::Coast, 0.0, 0.0));
+                }
+
+                self.time += dt;
+                self.x += dx;
+
+                if self.edges.len() > 0 {
+                    (self.edges[0].1).1 += dx;
+                }
+
+                // Keep only the edges covered by the train's length
+                let mut l = self.length;
+                let mut edge_num = 0;
+                for edge in &mut self.edges {
+                    edge_num += 1;
+                    let interval = &mut edge.1;
+                    if interval.1 - interval.0 > l {
+                        interval.0 = interval.1 - l;
+                        break;
+                    } else {
+                        l -= interval.1 - interval.0;
+                    }
+                }
+                self.edges.truncate(edge_num);
+
+                out.push(self.json_event(names, action, dx, v));
+            }
+            _ => {},
+        }
+        out
+    }
+}
+
+fn history_json<'a>(inf: &StaticInfrastructure, names: &'a Names,
+                    history: &'a history::History) -> JsonHistory<'a> {
+    let mut infrastructure = infrastructure(inf, names);
+
+    let mut tracker = InfTracker { time: 0.0 };
+    let train_name = |i: usize| history.trains[i].0.as_str();
+    infrastructure.events = Some(history.inf.iter()
+        .filter_map(|ev| tracker.event(names, &train_name, ev)).collect());
+
+    let trains = history.trains.iter().map(|&(ref name, ref params, ref events)| {
+        let mut tracker = TrainTracker::new(params);
+        let events = events.iter().flat_map(|ev| tracker.event(names, ev)).collect();
+        (name.as_str(), JsonTrain { params: params.into(), events: events })
+    }).collect();
+
+    JsonHistory { version: SCHEMA_VERSION, infrastructure: infrastructure, trains: trains }
+}
 
 pub fn javascript_history<W: io::Write>(inf: &StaticInfrastructure,
                                         names :&InfNames<String>,
@@ -31,230 +380,106 @@ pub fn json_history<W: io::Write>(inf: &StaticInfrastructure,
                                   history: &history::History,
                                   f: &mut W)
                                   -> Result<(), Error> {
-    let w = |f: &mut W, t, e, r, v| {
-        write!(f,
-               "{{ \"time\": {}, \"event\": \"{}\", \"ref\": \"{}\", \"value\": \"{}\" }}",
-               t,
-               e,
-               r,
-               v)
-            .unwrap();
-    };
-
-    write!(f, "{{ \"infrastructure\": {{\n")?;
-
-    write!(f, "\"nodes\":{{")?;
-    let mut first = true;
-    for (node_idx,node) in inf.nodes.iter().enumerate() {
-        if first { first = false; } else { write!(f, ", ")?; }
-        write!(f, "\"{}\": {{ \"other_node\": \"{}\" }}", get(&names.node_names, node_idx), 
-               get(&names.node_names, node.other_node))?;
+    let names = Names::new(inf, names);
+    serde_json::to_writer(f, &history_json(inf, &names, history))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonLinesHeader<'a> {
+    version: u32,
+    infrastructure: JsonInfrastructure<'a>,
+}
+
+#[derive(Serialize)]
+struct JsonLinesTrainStart<'a> {
+    event: &'static str,
+    train: &'a str,
+    params: JsonParams,
+}
+
+#[derive(Serialize)]
+struct JsonLinesTrainEvent<'a> {
+    event: &'static str,
+    train: &'a str,
+    #[serde(flatten)]
+    data: JsonTrainEvent<'a>,
+}
+
+/// Writes the history as JSON lines while the simulation is running.
+/// The first line contains the schema version and the infrastructure,
+/// and each following line is an infrastructure event, the start of a train
+/// (`"event": "train_start"`), or a train movement (`"event": "train"`).
+pub struct JsonLinesLogger<W: io::Write> {
+    writer: W,
+    names: Names,
+    inf: InfTracker,
+    trains: Vec<(String, TrainTracker)>,
+    error: Option<Error>,
+}
+
+impl<W: io::Write> JsonLinesLogger<W> {
+    pub fn new(inf: &StaticInfrastructure, names: &InfNames<String>, mut writer: W) -> Result<Self, Error> {
+        let names = Names::new(inf, names);
+        serde_json::to_writer(&mut writer, &JsonLinesHeader {
+            version: SCHEMA_VERSION,
+            infrastructure: infrastructure(inf, &names),
+        })?;
+        write!(writer, "\n")?;
+        Ok(JsonLinesLogger {
+            writer: writer,
+            names: names,
+            inf: InfTracker { time: 0.0 },
+            trains: Vec::new(),
+            error: None,
+        })
     }
-    write!(f, "}},")?;
 
-    write!(f, "\"objects\":{{")?;
-    let mut first = true;
-    for (node_idx, node) in inf.nodes.iter().enumerate() {
-        for obj in &node.objects {
-            use staticinfrastructure::StaticObject;
-            match inf.objects[*obj] {
-                StaticObject::Signal { .. } => {
-                    if first { first = false; } else { write!(f, ", ")?; }
-                    write!(f, " \"{}\": {{ \"type\":\"signal\", \"node\": \"{}\" }} ",
-                           get(&names.object_names, *obj), get(&names.node_names, node_idx))?;
-                },
-                _ => {},
-            }
-        }
+    /// Finish writing, returning the first error that occurred while logging, if any.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if let Some(e) = self.error.take() { return Err(e); }
+        self.writer.flush()?;
+        Ok(self.writer)
     }
-    write!(f, "}},")?;
 
-    write!(f, "\"events\":[")?;
-    let mut t = 0.0;
-    let mut first = true;
-    for ev in &history.inf {
-        use output::history::InfrastructureLogEvent::*;
-        match *ev {
-            Wait(dt) => t += dt,
-            Authority(n, x) => {
-                if first {
-                    first = false;
-                } else {
-                    write!(f, ", ")?;
-                }
-                w(f,
-                  t,
-                  "signal",
-                  get(&names.object_names, n),
-                  if x.0.is_some() { "green" } else { "red" });
-            }
-            Route(n, x) => {
-                if first {
-                    first = false;
-                } else {
-                    write!(f, ", ")?;
-                }
-                write!(f,
-                       "{{ \"time\": {}, \"event\": \"{}\", \"ref\": \"{}\", \"value\": \
-                        \"{:?}\" }}",
-                       t,
-                       "route",
-                       n,
-                       x)?;
-            }
-            Reserved(n, x) => {
-                if first {
-                    first = false;
-                } else {
-                    write!(f, ", ")?;
-                }
-                w(f,
-                  t,
-                  "reserved",
-                  get(&names.object_names, n),
-                  if x { "true" } else { "false" });
-            }
-            Occupied(n, x, _node, _train) => {
-                if first {
-                    first = false;
-                } else {
-                    write!(f, ", ")?;
-                }
-                w(f,
-                  t,
-                  "occupied",
-                  get(&names.object_names, n),
-                  if x { "true" } else { "false" });
-            }
-            Position(n, pos) => {
-                if first {
-                    first = false;
-                } else {
-                    write!(f, ", ")?;
-                }
-                w(f,
-                  t,
-                  "position",
-                  get(&names.object_names, n),
-                  if let SwitchPosition::Left = pos {
-                      "left"
-                  } else {
-                      "right"
-                  });
-            }
-        }
+    fn write<T: ::serde::Serialize>(writer: &mut W, error: &mut Option<Error>, x: &T) {
+        if error.is_some() { return; }
+        let result = serde_json::to_writer(&mut *writer, x).map_err(Error::from)
+            .and_then(|_| writer.write_all(b"\n").map_err(Error::from));
+        if let Err(e) = result { *error = Some(e); }
     }
-    write!(f, " ]}}")?;
-
-    write!(f, ", \"trains\": {{ ")?;
-    let mut firsttrain = true;
-    for &(ref name, ref params, ref his) in &history.trains {
-        if firsttrain {
-            firsttrain = false;
-        } else {
-            write!(f, ", ")?;
-        }
-        write!(f, " \"{}\": {{ \"params\": {{ \"length\": {}, \"max_acc\": {}, \"max_brk\": {}, \"max_vel\":{} }}, \"events\":[",  name, params.length, params.max_acc, params.max_brk, params.max_vel )?;
-
-        let mut t = 0.0;
-        let mut first = true;
-        let mut x = 0.0;
-        let mut edges = Vec::new();
-        let trainlength = params.length;
-
-        for ev in his {
-            use output::history::TrainLogEvent::*;
-            use railway::dynamics::DistanceVelocity;
-            match *ev {
-                Wait(dt) => {
-                    t += dt;
-                }
-                Node(_n1) => {}
-                Edge(n1, n2) => {
-                    edges.insert(0, ((n1, n2), (0.0, 0.0)));
-                }
-                Sight(_s, _x) => {}
-                Move(dt, action, DistanceVelocity { dx, v }) => {
-                    if first {
-                        first = false;
-                        let edge_strings = edges.iter()
-                            .map(|&((n1, n2), (a, b))| {
-                                format!("{{\"n1\": \"{}\", \"n2\": {}, \"start\": {}, \
-                                         \"end\": {}}}",
-                                        get(&names.node_names, n1),
-                                        match n2 {
-                                            Some(n2) => format!("\"{}\"", get(&names.node_names, n2)),
-                                            None => format!("null"),
-                                        },
-                                        a,
-                                        b)
-                            })
-                            .collect::<Vec<String>>();
-                        let edge_string = format!("[{}]", edge_strings.join(", "));
-                        write!(f,
-                               "{{ \"time\" : {}, \"action\": \"{:?}\", \"x\": {}, \"dx\": {}, \
-                                \"v\": {}, \"edges\": {} }}",
-                               t,
-                               DriverAction::Coast,
-                               0.0,
-                               0.0,
-                               0.0,
-                               edge_string)?;
+}
 
-                    }
+impl<W: io::Write> HistoryLogger for JsonLinesLogger<W> {
+    fn time(&mut self, dt: f64) {
+        self.inf.time += dt;
+    }
 
-                    t += dt;
-                    x += dx;
+    fn infrastructure(&mut self, ev: InfrastructureLogEvent) {
+        let trains = &self.trains;
+        let train_name = |i: usize| trains[i].0.as_str();
+        if let Some(json) = self.inf.event(&self.names, &train_name, &ev) {
+            Self::write(&mut self.writer, &mut self.error, &json);
+        }
+    }
 
-                    let mut l = trainlength;
-                    if edges.len() > 0 {
-                        let mut frontier = &mut edges[0].1;
-                        frontier.1 += dx;
-                    }
+    fn train_start(&mut self, name: &str, params: &TrainParams) {
+        self.trains.push((name.to_string(), TrainTracker::new(params)));
+        Self::write(&mut self.writer, &mut self.error, &JsonLinesTrainStart {
+            event: "train_start",
+            train: name,
+            params: params.into(),
+        });
+    }
 
-                    let mut edge_num = 0;
-                    for edge in &mut edges {
-                        edge_num += 1;
-                        let mut interval = &mut edge.1;
-                        if interval.1 - interval.0 > l {
-                            interval.0 = interval.1 - l;
-                            break;
-                        } else {
-                            l -= interval.1 - interval.0;
-                        }
-                    }
-                    edges.truncate(edge_num);
-                    let edge_strings = edges.iter()
-                        .map(|&((n1, n2), (a, b))| {
-                            format!("{{\"n1\": \"{}\", \"n2\": {}, \"start\": {}, \"end\": \
-                                     {}}}",
-                                    get(&names.node_names, n1),
-                                    match n2 {
-                                        Some(n2) => format!("\"{}\"", get(&names.node_names, n2)),
-                                        None => format!("null"),
-                                    },
-                                    a,
-                                    b)
-                        })
-                        .collect::<Vec<String>>();
-                    let edge_string = format!("[{}]", edge_strings.join(", "));
-
-                    write!(f, ", ")?;
-                    write!(f,
-                           " {{ \"time\" : {}, \"action\": \"{:?}\", \"x\" : {}, \"dx\" : {}, \
-                            \"v\": {}, \"edges\": {} }}",
-                           t,
-                           action,
-                           x,
-                           dx,
-                           v,
-                           edge_string)?;
-                }
-            }
+    fn train(&mut self, train: usize, ev: TrainLogEvent) {
+        let (ref name, ref mut tracker) = self.trains[train];
+        for data in tracker.event(&self.names, &ev) {
+            Self::write(&mut self.writer, &mut self.error, &JsonLinesTrainEvent {
+                event: "train",
+                train: name,
+                data: data,
+            });
         }
-        write!(f, " ] }}")?;
     }
-    write!(f, " }} ")?;
-    write!(f, " }}")?;
-    Ok(())
 }
@@ -1,3 +1,10 @@
 pub mod history;
 pub mod json;
 pub mod graphical;
+pub mod energy;
+pub mod timedistance;
+pub mod profile;
+pub mod routes;
+pub mod report;
+pub mod diff;
+pub mod punctuality;
@@ -0,0 +1,161 @@
+//! Velocity-distance profiles sampled from train trajectories.
+
+use failure::{self, Error};
+use serde::ser::{Serialize, Serializer, SerializeMap};
+use serde_json;
+use std::io;
+
+use output::history::{History, TrainLogEvent};
+use railway::dynamics::{dynamic_update, DriverAction, DriverPlan, DistanceVelocity, TrainParams};
+
+#[derive(Copy, Clone, Debug)]
+pub struct ProfileSample {
+    /// Time since simulation start.
+    pub time: f64,
+    /// Distance travelled by the train's front since entering the model.
+    pub position: f64,
+    pub velocity: f64,
+    pub acceleration: f64,
+    pub action: DriverAction,
+}
+
+/// Reconstruct the acceleration used in a movement from its end points.
+fn move_params(v: f64, dt: f64, action: DriverAction, update: DistanceVelocity) -> TrainParams {
+    let acc = match action {
+        DriverAction::Accel => (update.v - v) / dt,
+        // Braking to a stop may end before the end of the time step.
+        DriverAction::Brake if update.v <= 0.0 && update.dx > 0.0 => v * v / (2.0 * update.dx),
+        DriverAction::Brake => (v - update.v) / dt,
+        DriverAction::Coast => 0.0,
+    };
+    TrainParams {
+        length: 0.0,
+        max_acc: acc,
+        max_brk: acc,
+        max_vel: 0.0,
+    }
+}
+
+/// Sample a train's trajectory at the given time interval (in seconds), starting
+/// when the train enters the model, and including the end of its last movement.
+/// The interval must be positive.
+pub fn train_profile(events: &[TrainLogEvent], resolution: f64) -> Vec<ProfileSample> {
+    assert!(resolution > 0.0, "profile resolution must be positive");
+    let mut samples = Vec::new();
+    let mut t = 0.0;
+    let mut x = 0.0;
+    let mut v = 0.0;
+    let mut last_action = DriverAction::Coast;
+    let mut next_sample = None;
+    for ev in events {
+        match *ev {
+            TrainLogEvent::Wait(dt) => t += dt,
+            TrainLogEvent::Move(dt, action, update) => {
+                let mut sample_time = next_sample.unwrap_or(t);
+                let params = move_params(v, dt, action, update);
+                let acceleration = match action {
+                    DriverAction::Accel => params.max_acc,
+                    DriverAction::Brake => -params.max_brk,
+                    DriverAction::Coast => 0.0,
+                };
+                while sample_time < t + dt {
+                    let s = dynamic_update(&params, v, DriverPlan { action: action, dt: sample_time - t });
+                    samples.push(ProfileSample {
+                        time: sample_time,
+                        position: x + s.dx,
+                        velocity: s.v,
+                        acceleration: acceleration,
+                        action: action,
+                    });
+                    sample_time += resolution;
+                }
+                next_sample = Some(sample_time);
+                t += dt;
+                x += update.dx;
+                v = update.v;
+                last_action = action;
+            }
+            _ => {},
+        }
+    }
+    if next_sample.is_some() && samples.last().map(|s| s.time < t - 1e-9).unwrap_or(true) {
+        samples.push(ProfileSample {
+            time: t,
+            position: x,
+            velocity: v,
+            acceleration: 0.0,
+            action: last_action,
+        });
+    }
+    samples
+}
+
+fn action_name(action: DriverAction) -> &'static str {
+    match action {
+        DriverAction::Accel => "accel",
+        DriverAction::Coast => "coast",
+        DriverAction::Brake => "brake",
+    }
+}
+
+fn check_resolution(resolution: f64) -> Result<(), Error> {
+    if !(resolution > 0.0) {
+        return Err(failure::err_msg(format!("profile resolution must be positive: {}", resolution)));
+    }
+    Ok(())
+}
+
+/// Write the profile of all trains as CSV with the header
+/// `train,time,position,velocity,acceleration,action`.
+pub fn profile_csv<W: io::Write>(h: &History, resolution: f64, f: &mut W) -> Result<(), Error> {
+    check_resolution(resolution)?;
+    write!(f, "train,time,position,velocity,acceleration,action\n")?;
+    for &(ref name, _, ref events) in &h.trains {
+        for s in train_profile(events, resolution) {
+            write!(f, "{},{},{},{},{},{}\n", name, s.time, s.position, s.velocity,
+                   s.acceleration, action_name(s.action))?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonSample {
+    time: f64,
+    position: f64,
+    velocity: f64,
+    acceleration: f64,
+    action: &'static str,
+}
+
+/// Samples of each train, serialized as an object keyed by train name
+/// in the order the trains were dispatched.
+struct JsonProfiles<'a>(Vec<(&'a str, Vec<JsonSample>)>);
+
+impl<'a> Serialize for JsonProfiles<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for &(name, ref samples) in &self.0 {
+            map.serialize_entry(name, samples)?;
+        }
+        map.end()
+    }
+}
+
+/// Write the profile of all trains as a JSON object with one array of samples per train.
+pub fn profile_json<W: io::Write>(h: &History, resolution: f64, f: &mut W) -> Result<(), Error> {
+    check_resolution(resolution)?;
+    let profiles = JsonProfiles(h.trains.iter().map(|&(ref name, _, ref events)| {
+        let samples = train_profile(events, resolution).iter().map(|s| JsonSample {
+            time: s.time,
+            position: s.position,
+            velocity: s.velocity,
+            acceleration: s.acceleration,
+            action: action_name(s.action),
+        }).collect();
+        (name.as_str(), samples)
+    }).collect());
+    serde_json::to_writer_pretty(&mut *f, &profiles)?;
+    writeln!(f, "")?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Punctuality of a simulation compared to its timetable.
+//!
+//! The actual time at a timing point is the time when the front of the train passes
+//! the location. For a planned stop (a timing point with a departure time), the actual
+//! arrival is when the train came to a stop before passing the location, since trains
+//! are held at the signal until the departure time. Locations with several nodes,
+//! such as platforms, are passed when the front of the train leaves them, and the
+//! train may stop anywhere before that.
+
+use failure::Error;
+
+use input::staticinfrastructure::{StaticInfrastructure, InfNames, NodeId};
+use input::timetable::{self, Timetable, TimingPoint};
+use output::history::{History, TrainLogEvent};
+
+const STOPPED: f64 = 1e-5;
+
+#[derive(Clone, Debug)]
+pub struct PointDelay {
+    pub point: TimingPoint,
+    pub arrival: Option<f64>,
+    pub departure: Option<f64>,
+}
+
+impl PointDelay {
+    pub fn arrival_delay(&self) -> Option<f64> {
+        match (self.point.arrival, self.arrival) {
+            (Some(planned), Some(actual)) => Some(actual - planned),
+            _ => None,
+        }
+    }
+
+    pub fn departure_delay(&self) -> Option<f64> {
+        match (self.point.departure, self.departure) {
+            (Some(planned), Some(actual)) => Some(actual - planned),
+            _ => None,
+        }
+    }
+
+    /// The largest of the arrival and departure delays.
+    pub fn delay(&self) -> Option<f64> {
+        match (self.arrival_delay(), self.departure_delay()) {
+            (Some(a), Some(d)) => Some(a.max(d)),
+            (a, d) => a.or(d),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrainPunctuality {
+    pub name: String,
+    pub points: Vec<PointDelay>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Punctuality {
+    pub trains: Vec<TrainPunctuality>,
+    /// Largest delay counted as on time, in seconds.
+    pub tolerance: f64,
+}
+
+impl Punctuality {
+    /// Number of timing points, and number of timing points reached on time.
+    pub fn on_time(&self) -> (usize, usize) {
+        let points = self.trains.iter().flat_map(|t| t.points.iter());
+        let (mut total, mut on_time) = (0, 0);
+        for p in points {
+            total += 1;
+            if p.delay().map(|d| d <= self.tolerance).unwrap_or(false) { on_time += 1; }
+        }
+        (total, on_time)
+    }
+}
+
+/// Times when the train passed each node, and when it last came to a stop before it.
+fn passings(events: &[TrainLogEvent]) -> Vec<(NodeId, f64, Option<f64>)> {
+    let mut passings = Vec::new();
+    let mut t = 0.0;
+    let mut v = 0.0;
+    let mut stopped = None;
+    for ev in events {
+        match *ev {
+            TrainLogEvent::Wait(dt) => t += dt,
+            TrainLogEvent::Move(dt, _, update) => {
+                if v >= STOPPED && update.v < STOPPED { stopped = Some(t + dt); }
+                t += dt;
+                v = update.v;
+            },
+            TrainLogEvent::Node(n) => {
+                passings.push((n, t, stopped));
+                stopped = None;
+            },
+            _ => {},
+        }
+    }
+    passings
+}
+
+/// Compare the node visits in a history to the planned times in the timetable.
+/// Trains are matched by name, and timing points which were not reached have no actual times.
+pub fn punctuality(tt: &Timetable, inf: &StaticInfrastructure, names: &InfNames<String>,
+                   h: &History, tolerance: f64) -> Result<Punctuality, Error> {
+    let mut trains = Vec::new();
+    for train in &tt.trains {
+        let passed = h.trains.iter().find(|t| t.0 == train.name)
+            .map(|t| passings(&t.2)).unwrap_or_default();
+        let mut points = Vec::new();
+        for p in &train.points {
+            let nodes = timetable::location_nodes(inf, names, &p.location)?;
+            // The first run of consecutive passings at the location, and the last stop in it.
+            let at_location = |&&(n, _, _): &&(NodeId, f64, Option<f64>)| nodes.contains(&n);
+            let run = passed.iter().skip_while(|x| !at_location(x)).take_while(at_location);
+            let passing = run.fold(None, |acc: Option<(f64, Option<f64>)>, &(_, t, stopped)| {
+                Some((t, stopped.or(acc.and_then(|a| a.1))))
+            });
+            let (arrival, departure) = match passing {
+                Some((t, stopped)) if p.departure.is_some() => (Some(stopped.unwrap_or(t)), Some(t)),
+                Some((t, _)) => (Some(t), None),
+                None => (None, None),
+            };
+            points.push(PointDelay { point: p.clone(), arrival: arrival, departure: departure });
+        }
+        trains.push(TrainPunctuality { name: train.name.clone(), points: points });
+    }
+    Ok(Punctuality { trains: trains, tolerance: tolerance })
+}
+
+fn time(t: Option<f64>) -> String {
+    t.map(|t| format!("{:.1}", t)).unwrap_or_else(|| "-".to_string())
+}
+
+fn delay(t: Option<f64>) -> String {
+    t.map(|t| format!("{:+.1}", t)).unwrap_or_else(|| "-".to_string())
+}
+
+/// Write the punctuality report as a table with planned and actual times and
+/// delays in seconds, followed by the share of timing points reached on time.
+pub fn punctuality_report(p: &Punctuality) -> Result<String, Error> {
+    use std::fmt::Write;
+    let mut s = String::new();
+    let width = p.trains.iter().flat_map(|t| t.points.iter().map(|x| x.point.location.len()))
+        .chain(p.trains.iter().map(|t| t.name.len()))
+        .fold(8, usize::max);
+    writeln!(s, "{:w$} {:w$} {:>8} {:>8} {:>7} {:>8} {:>8} {:>7}", "train", "location",
+             "arr.plan", "arr", "delay", "dep.plan", "dep", "delay", w = width)?;
+    for t in &p.trains {
+        for x in &t.points {
+            writeln!(s, "{:w$} {:w$} {:>8} {:>8} {:>7} {:>8} {:>8} {:>7}", t.name, x.point.location,
+                     time(x.point.arrival), time(x.arrival), delay(x.arrival_delay()),
+                     time(x.point.departure), time(x.departure), delay(x.departure_delay()), w = width)?;
+        }
+    }
+    let (total, on_time) = p.on_time();
+    writeln!(s, "")?;
+    writeln!(s, "On time (delay at most {:.0} s): {} of {} timing points", p.tolerance, on_time, total)?;
+    Ok(s)
+}
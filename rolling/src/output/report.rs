@@ -0,0 +1,272 @@
+//! Summary statistics of a simulation run.
+//!
+//! For each train, the report gives entry and exit times, running time, and the
+//! time lost to restrictive signals. Trains only stop at signals showing stop, so every
+//! stop inside the model is counted as a signal stop. This includes the planned stops of
+//! a timetable, where the train is held at a signal until its departure time (see
+//! `input::timetable`), and which are compared to the plan by `output::punctuality`.
+//! A braking phase is counted as signal braking when it ends with the train stopped or
+//! accelerating again, as opposed to braking for a speed restriction, which ends with
+//! the train holding the lower speed.
+//!
+//! For the infrastructure, the report gives the utilisation of each detection section,
+//! and the number of throws and time locked for each switch.
+
+use failure::Error;
+use serde_json;
+use std::collections::HashMap;
+use std::io;
+
+use input::staticinfrastructure::{StaticInfrastructure, StaticObject, InfNames};
+use output::history::{History, InfrastructureLogEvent, TrainLogEvent};
+use railway::dynamics::DriverAction;
+
+const STOPPED: f64 = 1e-5;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TrainReport {
+    pub name: String,
+    /// Time when the train started moving into the model.
+    pub entry: Option<f64>,
+    /// Time when the train had left the model, or `None` if it was still inside
+    /// at the end of the simulation.
+    pub exit: Option<f64>,
+    /// Time from entry until the train left the model or the simulation ended.
+    pub running_time: f64,
+    pub distance: f64,
+    /// Time standing still after entering the model.
+    pub stopped_time: f64,
+    /// Time spent braking for restrictive signals.
+    pub signal_braking_time: f64,
+    /// Number of stops caused by restrictive signals.
+    pub signal_stops: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SectionReport {
+    pub name: String,
+    /// Number of times the section became occupied.
+    pub occupations: usize,
+    pub occupied_time: f64,
+    /// Time the section was reserved by a route or overlap.
+    pub reserved_time: f64,
+    /// Fraction of the simulation duration the section was occupied.
+    pub utilisation: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SwitchReport {
+    pub name: String,
+    /// Number of completed switch movements.
+    pub throws: usize,
+    /// Time the switch was locked by a route or overlap.
+    pub locked_time: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    /// Simulation time until the last event.
+    pub duration: f64,
+    pub trains: Vec<TrainReport>,
+    pub sections: Vec<SectionReport>,
+    pub switches: Vec<SwitchReport>,
+}
+
+/// Summarize a train's log events.
+pub fn train_report(name: &str, events: &[TrainLogEvent]) -> TrainReport {
+    let mut r = TrainReport {
+        name: name.to_string(),
+        entry: None, exit: None,
+        running_time: 0.0, distance: 0.0, stopped_time: 0.0,
+        signal_braking_time: 0.0, signal_stops: 0,
+    };
+    let mut t = 0.0;
+    let mut v = 0.0;
+    let mut exiting = false;
+    // Length of the current braking phase.
+    let mut braking = None;
+    for ev in events {
+        match *ev {
+            TrainLogEvent::Wait(dt) => t += dt,
+            TrainLogEvent::Edge(_, None) => exiting = true,
+            TrainLogEvent::Move(dt, action, update) => {
+                if r.entry.is_none() { r.entry = Some(t); }
+                if v < STOPPED && update.v < STOPPED {
+                    r.stopped_time += dt;
+                }
+                match action {
+                    DriverAction::Brake => {
+                        let phase = braking.unwrap_or(0.0) + dt;
+                        if update.v < STOPPED {
+                            r.signal_braking_time += phase;
+                            r.signal_stops += 1;
+                            braking = None;
+                        } else {
+                            braking = Some(phase);
+                        }
+                    },
+                    DriverAction::Accel => {
+                        if let Some(phase) = braking.take() { r.signal_braking_time += phase; }
+                    },
+                    DriverAction::Coast if update.v >= STOPPED => braking = None,
+                    DriverAction::Coast => {},
+                }
+                t += dt;
+                r.distance += update.dx;
+                v = update.v;
+            },
+            _ => {},
+        }
+    }
+    if let Some(entry) = r.entry {
+        r.running_time = t - entry;
+        if exiting { r.exit = Some(t); }
+    }
+    r
+}
+
+/// Summarize the trains and infrastructure in a history.
+pub fn report(inf: &StaticInfrastructure, names: &InfNames<String>, h: &History) -> Report {
+    let mut object_names = vec![String::new(); inf.objects.len()];
+    for (name, &id) in names.object_names.iter() {
+        object_names[id] = name.clone();
+    }
+
+    let mut sections = HashMap::new();
+    let mut switches = HashMap::new();
+    for (id, obj) in inf.objects.iter().enumerate() {
+        match *obj {
+            StaticObject::TVDSection => {
+                sections.insert(id, SectionReport {
+                    name: object_names[id].clone(),
+                    occupations: 0, occupied_time: 0.0, reserved_time: 0.0, utilisation: 0.0,
+                });
+            },
+            StaticObject::Switch { .. } => {
+                switches.insert(id, SwitchReport {
+                    name: object_names[id].clone(),
+                    throws: 0, locked_time: 0.0,
+                });
+            },
+            _ => {},
+        }
+    }
+
+    // Start times of the current occupation and reservation of each object.
+    let mut occupied: HashMap<usize, f64> = HashMap::new();
+    let mut reserved: HashMap<usize, f64> = HashMap::new();
+    let mut t = 0.0;
+    for ev in &h.inf {
+        match *ev {
+            InfrastructureLogEvent::Wait(dt) => t += dt,
+            InfrastructureLogEvent::Occupied(id, true, _, _) => {
+                if !occupied.contains_key(&id) {
+                    occupied.insert(id, t);
+                    if let Some(s) = sections.get_mut(&id) { s.occupations += 1; }
+                }
+            },
+            InfrastructureLogEvent::Occupied(id, false, _, _) => {
+                if let (Some(start), Some(s)) = (occupied.remove(&id), sections.get_mut(&id)) {
+                    s.occupied_time += t - start;
+                }
+            },
+            InfrastructureLogEvent::Reserved(id, true) => {
+                reserved.entry(id).or_insert(t);
+            },
+            InfrastructureLogEvent::Reserved(id, false) => {
+                if let Some(start) = reserved.remove(&id) {
+                    add_reserved(&mut sections, &mut switches, id, t - start);
+                }
+            },
+            InfrastructureLogEvent::Position(id, _) => {
+                if let Some(s) = switches.get_mut(&id) { s.throws += 1; }
+            },
+            _ => {},
+        }
+    }
+
+    let trains: Vec<_> = h.trains.iter()
+        .map(|&(ref name, _, ref events)| train_report(name, events))
+        .collect();
+    let duration = trains.iter()
+        .filter_map(|r| r.entry.map(|e| e + r.running_time))
+        .fold(t, f64::max);
+
+    for (id, start) in occupied {
+        if let Some(s) = sections.get_mut(&id) { s.occupied_time += duration - start; }
+    }
+    for (id, start) in reserved {
+        add_reserved(&mut sections, &mut switches, id, duration - start);
+    }
+
+    let mut sections: Vec<_> = sections.into_iter().map(|(_, mut s)| {
+        s.utilisation = if duration > 0.0 { s.occupied_time / duration } else { 0.0 };
+        s
+    }).collect();
+    sections.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut switches: Vec<_> = switches.into_iter().map(|(_, s)| s).collect();
+    switches.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Report {
+        duration: duration,
+        trains: trains,
+        sections: sections,
+        switches: switches,
+    }
+}
+
+fn add_reserved(sections: &mut HashMap<usize, SectionReport>, switches: &mut HashMap<usize, SwitchReport>,
+                id: usize, dt: f64) {
+    if let Some(s) = sections.get_mut(&id) { s.reserved_time += dt; }
+    if let Some(s) = switches.get_mut(&id) { s.locked_time += dt; }
+}
+
+fn opt_time(t: Option<f64>) -> String {
+    t.map(|t| format!("{:.1}", t)).unwrap_or_else(|| "-".to_string())
+}
+
+/// Write the report as human-readable tables, with times in seconds and distances in meters.
+pub fn report_table(r: &Report) -> Result<String, Error> {
+    use std::fmt::Write;
+    let mut s = String::new();
+    let width = r.trains.iter().map(|t| t.name.len())
+        .chain(r.sections.iter().map(|x| x.name.len()))
+        .chain(r.switches.iter().map(|x| x.name.len()))
+        .fold(8, usize::max);
+
+    writeln!(s, "Duration: {:.1} s", r.duration)?;
+    writeln!(s, "")?;
+    writeln!(s, "{:w$} {:>8} {:>8} {:>8} {:>9} {:>8} {:>8} {:>6}", "train", "entry", "exit", "running",
+             "distance", "stopped", "braking", "stops", w = width)?;
+    for t in &r.trains {
+        writeln!(s, "{:w$} {:>8} {:>8} {:>8.1} {:>9.1} {:>8.1} {:>8.1} {:>6}", t.name,
+                 opt_time(t.entry), opt_time(t.exit), t.running_time, t.distance,
+                 t.stopped_time, t.signal_braking_time, t.signal_stops, w = width)?;
+    }
+
+    if r.sections.len() > 0 {
+        writeln!(s, "")?;
+        writeln!(s, "{:w$} {:>6} {:>8} {:>8} {:>6}", "section", "occ", "occupied", "reserved", "util",
+                 w = width)?;
+        for x in &r.sections {
+            writeln!(s, "{:w$} {:>6} {:>8.1} {:>8.1} {:>5.1}%", x.name, x.occupations, x.occupied_time,
+                     x.reserved_time, 100.0 * x.utilisation, w = width)?;
+        }
+    }
+
+    if r.switches.len() > 0 {
+        writeln!(s, "")?;
+        writeln!(s, "{:w$} {:>6} {:>8}", "switch", "throws", "locked", w = width)?;
+        for x in &r.switches {
+            writeln!(s, "{:w$} {:>6} {:>8.1}", x.name, x.throws, x.locked_time, w = width)?;
+        }
+    }
+    Ok(s)
+}
+
+/// Write the report as JSON.
+pub fn report_json<W: io::Write>(r: &Report, f: &mut W) -> Result<(), Error> {
+    serde_json::to_writer_pretty(&mut *f, r)?;
+    writeln!(f, "")?;
+    Ok(())
+}
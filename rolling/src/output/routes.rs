@@ -0,0 +1,115 @@
+//! Route generation for a static infrastructure.
+//!
+//! The infrastructure is converted to the D-graph model of `railml2dgraph`,
+//! and routes are found using its route search (routes between consecutive signals
+//! and model boundaries, with releases at detection section limits).
+
+use failure::{err_msg, Error};
+use railml2dgraph::base::{Dir, Side};
+use railml2dgraph::dgraph::{DGraphModel, DGraphNode, Edge, NodePart, NodePosition, PartNode, PartNodeIdx, PartNodeObject, StopLocation};
+use railml2dgraph::{output, routes};
+
+use input::staticinfrastructure::{StaticInfrastructure, StaticObject, Edges, InfNames, SwitchPosition};
+use std::collections::HashSet;
+
+/// Convert the static infrastructure to a D-graph model.
+pub fn dgraph_model(inf: &StaticInfrastructure, names: &InfNames<String>) -> Result<DGraphModel, Error> {
+    let mut node_names = vec![None; inf.nodes.len()];
+    for (name, &id) in names.node_names.iter() { node_names[id] = Some(name.as_str()); }
+    let mut object_names = vec![None; inf.objects.len()];
+    for (name, &id) in names.object_names.iter() { object_names[id] = Some(name.as_str()); }
+    let object_name = |id: usize| object_names[id].map(|n| n.to_string())
+        .ok_or_else(|| err_msg(format!("unnamed object {}", id)));
+
+    // Each pair of opposite nodes becomes one D-graph node.
+    let mut part_idx = vec![None; inf.nodes.len()];
+    let mut model = DGraphModel::default();
+    for (i, node) in inf.nodes.iter().enumerate() {
+        if part_idx[i].is_some() { continue; }
+        let idx = model.nodes.len();
+        part_idx[i] = Some(PartNodeIdx::from_node_part(idx, NodePart::A));
+        part_idx[node.other_node] = Some(PartNodeIdx::from_node_part(idx, NodePart::B));
+
+        let mut parts = Vec::new();
+        for &n in &[i, node.other_node] {
+            let mut objs = Vec::new();
+            for &obj in &inf.nodes[n].objects {
+                match inf.objects[obj] {
+                    StaticObject::Signal { .. } => objs.push(PartNodeObject::Signal(object_name(obj)?)),
+                    StaticObject::Sight { distance, signal } =>
+                        objs.push(PartNodeObject::Sight(object_name(signal)?, distance)),
+                    StaticObject::TVDLimit { enter, exit } => {
+                        if let Some(tvd) = exit { objs.push(PartNodeObject::TVDExit(object_name(tvd)?)); }
+                        if let Some(tvd) = enter { objs.push(PartNodeObject::TVDEnter(object_name(tvd)?)); }
+                    },
+                    StaticObject::SpeedLimit { max_velocity } => objs.push(PartNodeObject::Speed(max_velocity)),
+                    _ => {},
+                }
+            }
+            let name = node_names[n].ok_or_else(|| err_msg(format!("unnamed node {}", n)))?;
+            parts.push(PartNode { name: name.to_string(), objs: objs });
+        }
+        let b = parts.pop().unwrap();
+        let a = parts.pop().unwrap();
+        let is_limit = |p: &PartNode| p.objs.iter()
+            .any(|o| match *o { PartNodeObject::TVDEnter(_) | PartNodeObject::TVDExit(_) => true, _ => false });
+        let has_detector = is_limit(&a) || is_limit(&b);
+        let detector_dir = match (is_limit(&a), is_limit(&b)) {
+            (false, true) => Some(Dir::Up),
+            (true, false) => Some(Dir::Down),
+            _ => None,
+        };
+        let position = names.node_positions.get(&i)
+            .map(|p| NodePosition { track: p.track.clone(), pos: p.pos, abs_pos: p.abs_pos });
+        model.nodes.push(DGraphNode { name: None, a: a, b: b, has_detector: has_detector,
+                                      detector_dir: detector_dir, position: position });
+    }
+    let part = |n: usize| part_idx[n].unwrap();
+
+    // Linear edges are given in both directions in the static infrastructure,
+    // and the trailing legs of switches also have linear edges back to the switch.
+    let mut covered = HashSet::new();
+    for (i, node) in inf.nodes.iter().enumerate() {
+        if let Edges::Switchable(sw) = node.edges {
+            if let StaticObject::Switch { left_link, right_link, branch_side, .. } = inf.objects[sw] {
+                covered.insert((left_link.0, i));
+                covered.insert((right_link.0, i));
+                let side = match branch_side {
+                    SwitchPosition::Left => Side::Left,
+                    SwitchPosition::Right => Side::Right,
+                };
+                model.edges.push(Edge::Switch(object_name(sw)?, Some(side), part(i),
+                                              (part(left_link.0), left_link.1),
+                                              (part(right_link.0), right_link.1)));
+            }
+        }
+    }
+    for (i, node) in inf.nodes.iter().enumerate() {
+        match node.edges {
+            Edges::Single(other, d) => {
+                if covered.insert((i, other)) && covered.insert((other, i)) {
+                    model.edges.push(Edge::Linear(part(i), (part(other), d)));
+                    if node.gradient != 0.0 { model.gradients.insert(part(i), node.gradient); }
+                }
+            },
+            Edges::ModelBoundary => model.edges.push(Edge::Boundary(part(i))),
+            _ => {},
+        }
+    }
+
+    for (name, stop) in names.stop_locations.iter() {
+        model.stops.push(StopLocation { name: name.clone(), ocp: stop.ocp.clone(), track: stop.track.clone(),
+                                        pos: stop.pos, length: stop.length });
+    }
+
+    Ok(model)
+}
+
+/// Generate routes for the infrastructure in the Rolling route format.
+pub fn generate_routes(inf: &StaticInfrastructure, names: &InfNames<String>) -> Result<String, Error> {
+    let model = dgraph_model(inf, names)?;
+    let routes = routes::find_routes(&model);
+    let mut buf = Vec::new();
+    output::print_routes(&mut buf, &model, &routes)?;
+    Ok(String::from_utf8(buf)?)
+}
@@ -0,0 +1,332 @@
+//! Time-distance diagram (train graph) of a history along a path in the infrastructure,
+//! rendered as SVG. Distance runs horizontally along the path, and time runs downwards.
+//! Train lines are coloured by the driver action, and occupied detection sections are
+//! shown as grey blocks. The train lines of a second history may be overlaid for comparison.
+
+use failure::Error;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use input::staticinfrastructure::{StaticInfrastructure, InfNames, NodeId, ObjectId, Edges, StaticObject};
+use output::history::{History, InfrastructureLogEvent, TrainLogEvent};
+use railway::dynamics::DriverAction;
+
+#[derive(Debug, Fail)]
+pub enum TimeDistanceError {
+    #[fail(display = "unknown node: {}", _0)]
+    UnknownNode(String),
+    #[fail(display = "no path from {} to {}", _0, _1)]
+    NoPath(String, String),
+    #[fail(display = "path must contain at least two nodes")]
+    EmptyPath,
+}
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 1000.0;
+const MARGIN: f64 = 60.0;
+
+fn edges(inf: &StaticInfrastructure, node: NodeId) -> Vec<(NodeId, f64)> {
+    match inf.nodes[node].edges {
+        Edges::Single(other, d) => vec![(other, d)],
+        Edges::Switchable(sw) => match inf.objects[sw] {
+            StaticObject::Switch { left_link, right_link, .. } => vec![left_link, right_link],
+            _ => panic!("Not a switch"),
+        },
+        _ => vec![],
+    }
+}
+
+fn edge_length(inf: &StaticInfrastructure, a: NodeId, b: NodeId) -> Option<f64> {
+    edges(inf, a).into_iter().find(|&(n, _)| n == b).map(|(_, d)| d)
+}
+
+/// Shortest path between two nodes, following the edges of the double node graph.
+/// The path contains both sides of each double node passed.
+fn shortest_path(inf: &StaticInfrastructure, from: NodeId, to: NodeId) -> Option<Vec<NodeId>> {
+    // Search states are (node, arrived at node through an edge).
+    let mut dist: HashMap<(NodeId, bool), (f64, Option<(NodeId, bool)>)> = HashMap::new();
+    let mut queue = vec![(0.0, (from, true), None), (0.0, (from, false), None)];
+    while let Some(i) = (0..queue.len()).min_by(|&a, &b| queue[a].0.partial_cmp(&queue[b].0).unwrap()) {
+        let (d, state, prev) = queue.swap_remove(i);
+        if dist.contains_key(&state) { continue; }
+        dist.insert(state, (d, prev));
+        let (node, by_edge) = state;
+        if node == to {
+            let mut path = vec![node];
+            let mut s = prev;
+            while let Some(p) = s {
+                path.push(p.0);
+                s = dist[&p].1;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if by_edge {
+            queue.push((d, (inf.nodes[node].other_node, false), Some(state)));
+        } else {
+            for (next, l) in edges(inf, node) {
+                queue.push((d + l, (next, true), Some(state)));
+            }
+        }
+    }
+    None
+}
+
+/// Find the node path through the given named nodes, taking the shortest
+/// path between each consecutive pair of nodes.
+pub fn path_from_names(inf: &StaticInfrastructure, names: &InfNames<String>, via: &[String])
+                       -> Result<Vec<NodeId>, Error> {
+    if via.len() < 2 { return Err(TimeDistanceError::EmptyPath.into()); }
+    let ids = via.iter()
+        .map(|n| names.node_names.get(n).cloned().ok_or_else(|| TimeDistanceError::UnknownNode(n.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut path = vec![ids[0]];
+    for (i, w) in ids.windows(2).enumerate() {
+        let part = shortest_path(inf, w[0], w[1])
+            .ok_or_else(|| TimeDistanceError::NoPath(via[i].clone(), via[i + 1].clone()))?;
+        path.extend(part.into_iter().skip(1));
+    }
+    Ok(path)
+}
+
+/// Cumulative distance of each node on the path. Both sides of a double node
+/// are given the same distance.
+fn path_positions(inf: &StaticInfrastructure, path: &[NodeId]) -> HashMap<NodeId, f64> {
+    let mut pos = HashMap::new();
+    let mut x = 0.0;
+    for (i, &n) in path.iter().enumerate() {
+        if i > 0 {
+            x += edge_length(inf, path[i - 1], n).unwrap_or(0.0);
+        }
+        pos.insert(n, x);
+        pos.entry(inf.nodes[n].other_node).or_insert(x);
+    }
+    pos
+}
+
+fn action_color(action: DriverAction) -> &'static str {
+    match action {
+        DriverAction::Accel => "#2ca02c",
+        DriverAction::Coast => "#1f77b4",
+        DriverAction::Brake => "#d62728",
+    }
+}
+
+/// Train line segments as (action, points (time, distance)).
+fn train_lines(inf: &StaticInfrastructure, pos: &HashMap<NodeId, f64>, events: &[TrainLogEvent])
+               -> Vec<(DriverAction, Vec<(f64, f64)>)> {
+    let mut lines: Vec<(DriverAction, Vec<(f64, f64)>)> = Vec::new();
+    let mut t = 0.0;
+    // Current edge as (start distance, direction) along the path, and distance travelled on the edge.
+    let mut edge: Option<(f64, f64)> = None;
+    let mut travelled = 0.0;
+    let mut connected = false;
+    let mut v = 0.0;
+    for ev in events {
+        match *ev {
+            TrainLogEvent::Wait(dt) => { t += dt; connected = false; }
+            TrainLogEvent::Edge(a, Some(b)) => {
+                travelled = 0.0;
+                edge = match (pos.get(&a), pos.get(&b), edge_length(inf, a, b)) {
+                    (Some(&xa), Some(&xb), Some(l)) if ((xb - xa).abs() - l).abs() < 1e-3 =>
+                        Some((xa, (xb - xa).signum())),
+                    _ => None,
+                };
+                if edge.is_none() { connected = false; }
+            }
+            TrainLogEvent::Edge(_, None) => { edge = None; connected = false; }
+            TrainLogEvent::Move(dt, action, update) => {
+                if let Some((x0, dir)) = edge {
+                    // Sample the constant acceleration movement, scaled to the logged distance.
+                    let acc = if dt > 0.0 { (update.v - v) / dt } else { 0.0 };
+                    let full = v * dt + 0.5 * acc * dt * dt;
+                    let scale = if full > 0.0 { update.dx / full } else { 0.0 };
+                    let n = (dt.ceil() as usize).max(1);
+                    let mut points: Vec<(f64, f64)> = (0..n + 1).map(|i| {
+                        let tau = dt * (i as f64) / (n as f64);
+                        let dx = scale * (v * tau + 0.5 * acc * tau * tau);
+                        (t + tau, x0 + dir * (travelled + dx))
+                    }).collect();
+
+                    let same = match lines.last() {
+                        Some(&(a, _)) => connected && a as usize == action as usize,
+                        None => false,
+                    };
+                    if same {
+                        lines.last_mut().unwrap().1.extend(points.drain(1..));
+                    } else {
+                        if connected {
+                            // Start at the end of the previous line segment.
+                            let last = *lines.last().unwrap().1.last().unwrap();
+                            points[0] = last;
+                        }
+                        lines.push((action, points));
+                    }
+                    connected = true;
+                }
+                t += dt;
+                travelled += update.dx;
+                v = update.v;
+            }
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// Occupation of detection sections as (section, start time, end time).
+fn occupations(h: &History) -> (Vec<(ObjectId, f64, f64)>, f64) {
+    let mut blocks = Vec::new();
+    let mut count: HashMap<ObjectId, (usize, f64)> = HashMap::new();
+    let mut t = 0.0;
+    for ev in &h.inf {
+        match *ev {
+            InfrastructureLogEvent::Wait(dt) => t += dt,
+            InfrastructureLogEvent::Occupied(tvd, true, _, _) => {
+                let c = count.entry(tvd).or_insert((0, t));
+                if c.0 == 0 { c.1 = t; }
+                c.0 += 1;
+            }
+            InfrastructureLogEvent::Occupied(tvd, false, _, _) => {
+                let c = count.entry(tvd).or_insert((1, t));
+                c.0 = c.0.saturating_sub(1);
+                if c.0 == 0 { blocks.push((tvd, c.1, t)); }
+            }
+            _ => {}
+        }
+    }
+    for (tvd, (c, start)) in count {
+        if c > 0 { blocks.push((tvd, start, t)); }
+    }
+    (blocks, t)
+}
+
+/// Extent of each detection section along the path, from the section limits on the path nodes.
+fn section_extents(inf: &StaticInfrastructure, pos: &HashMap<NodeId, f64>) -> HashMap<ObjectId, (f64, f64)> {
+    let mut extents: HashMap<ObjectId, (f64, f64)> = HashMap::new();
+    for (&node, &x) in pos.iter() {
+        for &obj in inf.nodes[node].objects.iter() {
+            if let StaticObject::TVDLimit { enter, exit } = inf.objects[obj] {
+                for tvd in enter.into_iter().chain(exit.into_iter()) {
+                    let e = extents.entry(tvd).or_insert((x, x));
+                    e.0 = e.0.min(x);
+                    e.1 = e.1.max(x);
+                }
+            }
+        }
+    }
+    extents
+}
+
+fn name_of(x: &HashMap<String, usize>, n: usize) -> String {
+    escape(x.iter().find(|&(_, v)| *v == n).map(|(k, _)| k.as_str()).unwrap_or("?"))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Render the history as a time-distance diagram along the given node path
+/// (see `path_from_names`).
+pub fn time_distance_svg(inf: &StaticInfrastructure, names: &InfNames<String>,
+                         h: &History, path: &[NodeId]) -> Result<String, Error> {
+    render(inf, names, h, path, None)
+}
+
+/// Render the history as a time-distance diagram, overlaid with the train lines of
+/// another history on a variant of the infrastructure, drawn dashed.
+/// The path in the other infrastructure should pass the same locations.
+pub fn time_distance_overlay_svg(inf: &StaticInfrastructure, names: &InfNames<String>,
+                                 h: &History, path: &[NodeId],
+                                 other: (&StaticInfrastructure, &History, &[NodeId])) -> Result<String, Error> {
+    render(inf, names, h, path, Some(other))
+}
+
+fn render(inf: &StaticInfrastructure, names: &InfNames<String>, h: &History, path: &[NodeId],
+          other: Option<(&StaticInfrastructure, &History, &[NodeId])>) -> Result<String, Error> {
+    if path.len() < 2 { return Err(TimeDistanceError::EmptyPath.into()); }
+    let pos = path_positions(inf, path);
+    let length = pos.values().cloned().fold(0.0, f64::max).max(1.0);
+
+    let lines = h.trains.iter()
+        .map(|&(ref name, _, ref events)| (name, train_lines(inf, &pos, events)))
+        .collect::<Vec<_>>();
+    let other_lines = match other {
+        Some((other_inf, other_h, other_path)) => {
+            if other_path.len() < 2 { return Err(TimeDistanceError::EmptyPath.into()); }
+            let other_pos = path_positions(other_inf, other_path);
+            other_h.trains.iter()
+                .map(|&(ref name, _, ref events)| (name, train_lines(other_inf, &other_pos, events)))
+                .collect::<Vec<_>>()
+        },
+        None => Vec::new(),
+    };
+    let (blocks, inf_time) = occupations(h);
+    let end_time = lines.iter().chain(other_lines.iter())
+        .flat_map(|&(_, ref l)| l.iter().flat_map(|&(_, ref p)| p.iter().map(|&(t, _)| t)))
+        .fold(inf_time, f64::max).max(1.0);
+
+    let sx = |x: f64| MARGIN + x / length * WIDTH;
+    let sy = |t: f64| MARGIN + t / end_time * HEIGHT;
+
+    let mut s = String::new();
+    write!(s, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"10\">\n",
+           WIDTH + 2.0 * MARGIN, HEIGHT + 2.0 * MARGIN)?;
+
+    // Section occupation blocks
+    let extents = section_extents(inf, &pos);
+    for &(tvd, t0, t1) in &blocks {
+        if let Some(&(x0, x1)) = extents.get(&tvd) {
+            if x1 - x0 < 1e-3 { continue; }
+            write!(s, "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"#cccccc\" fill-opacity=\"0.5\"><title>{}</title></rect>\n",
+                   sx(x0), sy(t0), sx(x1) - sx(x0), sy(t1) - sy(t0), name_of(&names.object_names, tvd))?;
+        }
+    }
+
+    // Node axis
+    let mut labelled = Vec::new();
+    for &n in path {
+        let x = pos[&n];
+        if labelled.iter().any(|&l: &f64| (l - x).abs() < 1e-3) { continue; }
+        labelled.push(x);
+        write!(s, "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#eeeeee\"/>\n",
+               sx(x), sy(0.0), sx(x), sy(end_time))?;
+        write!(s, "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"end\" transform=\"rotate(-45 {:.2} {:.2})\">{}</text>\n",
+               sx(x), sy(0.0) - 5.0, sx(x), sy(0.0) - 5.0, name_of(&names.node_names, n))?;
+    }
+
+    // Time axis
+    let step = [1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0].iter()
+        .cloned().find(|&d| end_time / d <= 20.0).unwrap_or(3600.0);
+    let mut t = 0.0;
+    while t <= end_time {
+        write!(s, "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#eeeeee\"/>\n",
+               sx(0.0), sy(t), sx(length), sy(t))?;
+        write!(s, "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"end\">{}</text>\n",
+               sx(0.0) - 5.0, sy(t) + 3.0, t)?;
+        t += step;
+    }
+
+    // Train lines
+    for &(name, ref lines) in &lines {
+        for &(action, ref points) in lines {
+            let points = points.iter()
+                .map(|&(t, x)| format!("{:.2},{:.2}", sx(x), sy(t)))
+                .collect::<Vec<_>>().join(" ");
+            write!(s, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"><title>{}</title></polyline>\n",
+                   points, action_color(action), escape(name))?;
+        }
+    }
+    for &(name, ref lines) in &other_lines {
+        for &(action, ref points) in lines {
+            let points = points.iter()
+                .map(|&(t, x)| format!("{:.2},{:.2}", sx(x), sy(t)))
+                .collect::<Vec<_>>().join(" ");
+            write!(s, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\" stroke-dasharray=\"6,4\" stroke-opacity=\"0.7\"><title>{} (variant)</title></polyline>\n",
+                   points, action_color(action), escape(name))?;
+        }
+    }
+
+    write!(s, "</svg>\n")?;
+    Ok(s)
+}
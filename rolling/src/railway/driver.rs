@@ -4,7 +4,12 @@ use input::staticinfrastructure::*;
 use smallvec::SmallVec;
 use super::dynamics::*;
 use output::history::TrainLogEvent;
-use super::Sim;
+use super::{Sim, SignallingMode};
+use eventsim::observable::Observable;
+use std::f64::INFINITY;
+
+/// Gravitational acceleration in m/s^2.
+const GRAVITY: f64 = 9.81;
 
 enum ModelContainment {
     Inside,
@@ -17,20 +22,71 @@ enum Activation {
     Running,
 }
 
+/// How the driver chooses to run the train, independently of the rolling stock.
+#[derive(Copy, Clone, Debug)]
+pub struct DriverProfile {
+    /// Distance in meters to keep before the end of the movement authority.
+    /// The driver stops watching a signal when closer than half this distance.
+    pub safety_margin: f64,
+    /// Time in seconds from the need to brake until the brakes are applied.
+    /// The train coasts during the reaction time, and the driver anticipates it
+    /// for restrictions which are known in advance.
+    pub reaction_time: f64,
+    /// Energy saving strategy.
+    pub coasting: CoastingStrategy,
+    /// Fraction of the train's maximum acceleration which is used.
+    pub acceleration_fraction: f64,
+}
+
+impl Default for DriverProfile {
+    fn default() -> Self {
+        DriverProfile {
+            safety_margin: 20.0,
+            reaction_time: 0.0,
+            coasting: CoastingStrategy::Never,
+            acceleration_fraction: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CoastingStrategy {
+    /// Accelerate up to the maximum allowed velocity.
+    Never,
+    /// Stop accelerating at the given fraction of the allowed velocity,
+    /// and coast from there.
+    AtVelocity(f64),
+}
+
 #[derive(Debug)]
 struct Train {
     location: (NodeId, (Option<NodeId>, f64)),
     velocity: f64,
     params: TrainParams,
     under_train: SmallVec<[(NodeId, f64); 4]>,
+    travelled: f64,
+    speed_restrictions: SmallVec<[(f64, f64); 2]>,
+    /// Maximum velocity from the last speed limit passed.
+    line_speed: Option<f64>,
+    /// Gradient in per mille of the edge under the train's front.
+    gradient: f64,
 }
 
 pub struct Driver {
     id :usize,
     train: Train,
+    profile: DriverProfile,
     authority: f64,
     step: (DriverAction, f64),
+    /// Time when the driver saw the need to brake, while waiting for the reaction time.
+    brake_decision: Option<f64>,
     connected_signals: SmallVec<[(ObjectId, f64); 4]>,
+    authority_signals: SmallVec<[ObjectId; 4]>,
+    /// Signals ahead which have given movement authority to this train in
+    /// continuous signalling. The authority is not withdrawn when the train's own
+    /// detection makes the signal show stop before the train has passed it.
+    granted_signals: SmallVec<[ObjectId; 4]>,
+    authority_trains: SmallVec<[TrainId; 2]>,
     logger: Box<Fn(TrainLogEvent)>,
     activation: Activation,
     timestep: Option<f64>,
@@ -43,27 +99,46 @@ impl Driver {
                node: NodeId,
                auth: f64,
                params: TrainParams,
+               profile: DriverProfile,
                logger: Box<Fn(TrainLogEvent)>,
                timestep: Option<f64>)
                -> Self {
 
        //println!("INITIAL AUTHORITY {:?}", auth);
+        let params = TrainParams {
+            max_acc: params.max_acc * profile.acceleration_fraction,
+            .. params
+        };
         let train = Train {
             params: params,
             location: (0, (Some(node),0.0)),
             velocity: 0.0,
             under_train: SmallVec::new(),
+            travelled: 0.0,
+            speed_restrictions: SmallVec::new(),
+            line_speed: None,
+            gradient: 0.0,
         };
 
+        while sim.world.trains.len() <= id {
+            let position = Observable::new(&mut sim.scheduler, None);
+            sim.world.trains.push(position);
+        }
+
         let d = Driver {
             id: id,
             train: train,
-            authority: auth - 20.0,
+            profile: profile,
+            authority: auth - profile.safety_margin,
             step: (DriverAction::Coast, *sim.time()),
+            brake_decision: None,
             connected_signals: SmallVec::new(),
+            authority_signals: SmallVec::new(),
+            granted_signals: SmallVec::new(),
+            authority_trains: SmallVec::new(),
             logger: logger,
             activation: Activation::Wait(activated),
-            timestep: timestep
+            timestep: timestep,
         };
 
         d
@@ -90,11 +165,19 @@ impl Driver {
 
     fn arrive_front(&mut self, sim: &Sim, obj: ObjectId) {
         match sim.world.statics.objects[obj] {
-            StaticObject::Sight { distance, signal } => {
+            StaticObject::SpeedLimit { max_velocity } => {
+                // The previous limit applies until the rear of the train has passed.
+                if let Some(v) = self.train.line_speed {
+                    self.train.speed_restrictions.push((self.train.params.length, v));
+                }
+                self.train.line_speed = Some(max_velocity);
+            }
+            StaticObject::Sight { distance, signal } if sim.world.signalling == SignallingMode::Lineside => {
                 self.connected_signals.push((signal, distance));
                 (self.logger)(TrainLogEvent::Sight(signal,true));
             }
             StaticObject::Signal { .. } => {
+                self.granted_signals.retain(|s| *s != obj);
                 let log = &mut self.logger;
                 self.connected_signals.retain(|&mut (s, _d)| {
                     let lost = s == obj;
@@ -125,7 +208,7 @@ impl Driver {
     fn move_train_continuous(&mut self, sim :&mut Sim) {
         let (action, action_time) = self.step;
         let dt = *sim.time() - action_time;
-        let update = dynamic_update(&self.train.params, self.train.velocity, 
+        let update = dynamic_update(&self.params(), self.train.velocity, 
                                     DriverPlan { action: action, dt: dt, });
 
         //println!("DYNAMIC UPDATE {:?}", (action,dt));
@@ -135,12 +218,19 @@ impl Driver {
         self.train.velocity = update.v;
         //println!("train loc {:?}", self.train.location);
         (self.train.location.1).1 -= update.dx;
+        self.train.travelled += update.dx;
         //println!("train loc {:?}", self.train.location);
 
         // In case there are no signals in sight,
         // the remembered authority is updated.
         self.authority -= update.dx;
 
+        // Speed restrictions are lifted when the rear of the train has passed.
+        self.train.speed_restrictions.retain(|&mut (ref mut dist, _v)| {
+            *dist -= update.dx;
+            *dist > 1e-5
+        });
+
         let id = self.id;
         self.train.under_train.retain(|&mut (node, ref mut dist)| {
             *dist -= update.dx;
@@ -161,12 +251,13 @@ impl Driver {
 
         {
         let log = &mut self.logger;
+        let lost_sight = 0.5 * self.profile.safety_margin;
         self.connected_signals.retain(|&mut (obj, ref mut dist)| {
             *dist -= update.dx;
-            let lost = *dist < 10.0; // If closer than 10 m, signal should already be green
-                                     // and seeing a red for a very short time should be because
-                                     // detector is placed in front of signal and this should not 
-                                     // bother the driver.
+            let lost = *dist < lost_sight; // If closer than half the safety margin, signal should
+                                     // already be green and seeing a red for a very short time 
+                                     // should be because detector is placed in front of signal 
+                                     // and this should not bother the driver.
             if lost { log(TrainLogEvent::Sight(obj, false)); } 
             !lost
         });
@@ -182,16 +273,25 @@ impl Driver {
             (self.logger)(TrainLogEvent::Node(end_node.unwrap()));
             self.goto_node(sim, new_start);
             (self.logger)(TrainLogEvent::Node(new_start));
-            match sim.world.edge_from(new_start) {
+            let length = match sim.world.edge_from(new_start) {
                 Some((Some(new_end_node), d)) => {
                     self.train.location = (new_start, (Some(new_end_node), d));
+                    self.train.travelled = 0.0;
                     (self.logger)(TrainLogEvent::Edge(new_start, Some(new_end_node)));
+                    d
                 }
                 Some((None, d)) => {
                     self.train.location = (new_start, (None, d));
+                    self.train.travelled = 0.0;
                     (self.logger)(TrainLogEvent::Edge(new_start, None));
+                    d
                 }
                 None => panic!("Derailed"),
+            };
+            self.train.gradient = sim.world.statics.nodes[new_start].gradient;
+            if let Some(v) = branch_max_velocity(sim, new_start) {
+                // The whole train must pass the switch leg at the restricted speed.
+                self.train.speed_restrictions.push((length + self.train.params.length, v));
             }
         }
     }
@@ -216,6 +316,12 @@ impl Driver {
             max_dist = max_dist.min(d);
         }
 
+        // Authority is updated continuously from the interlocking
+        if sim.world.signalling != SignallingMode::Lineside {
+            self.authority = self.continuous_authority(sim) - self.profile.safety_margin;
+            if self.authority < 0.0 { self.authority = 0.0; }
+        }
+
         // Authority is updated by signals
         for &(sig, dist) in self.connected_signals.iter() {
             match sim.world.state[sig] {
@@ -223,12 +329,12 @@ impl Driver {
                     match *authority.get() {
                         (Some(auth_dist), distant_sig) => {
                             //println!("Signal green in sight dist{} sigauth{} self.auth{}", dist, d, dist+d-20.0);
-                            self.authority = dist + auth_dist + distant_sig.unwrap_or(0.0) - 20.0;
+                            self.authority = dist + auth_dist + distant_sig.unwrap_or(0.0) - self.profile.safety_margin;
                             if self.authority < 0.0 { self.authority = 0.0; }
                         }
                         (None,_) => {
                             //println!("Signal red in sight dist{} self.auth{}", dist,dist-20.0);
-                            self.authority = dist - 20.0;
+                            self.authority = dist - self.profile.safety_margin;
                             if self.authority < 0.0 { self.authority = 0.0; }
                             break;
                         }
@@ -242,19 +348,254 @@ impl Driver {
 
         // Static maximum speed profile ahead from current position
         // TODO: other speed limitations
+        let max_vel = self.train.params.max_vel;
+        let local_max_velocity = self.train.speed_restrictions.iter()
+            .fold(max_vel.min(self.train.line_speed.unwrap_or(max_vel)), |v, &(_, r)| v.min(r));
+        let coasting_velocity = match self.profile.coasting {
+            CoastingStrategy::Never => INFINITY,
+            CoastingStrategy::AtVelocity(f) => f * local_max_velocity,
+        };
+        let mut max_velocity_ahead = self.speeds_ahead(sim);
+        max_velocity_ahead.push(DistanceVelocity { dx: self.authority, v: 0.0 });
+
         let static_speed_profile = StaticMaximumVelocityProfile {
-            local_max_velocity: self.train.params.max_vel,
-            max_velocity_ahead: SmallVec::from_slice(&[DistanceVelocity {
-               dx: self.authority, v: 0.0}]),
+            local_max_velocity: local_max_velocity,
+            max_velocity_ahead: max_velocity_ahead,
         };
 
-        let plan = dynamic_plan_step(&self.train.params,
+        let plan = dynamic_plan_step(&self.params(),
                           max_dist,
                           self.train.velocity,
-                          &static_speed_profile);
+                          &static_speed_profile,
+                          self.profile.reaction_time,
+                          coasting_velocity);
 
         //println!("PLAN: {:?} {:?} {:?} {:?} {:?} ", self.train.params, max_dist, self.train.velocity, static_speed_profile,plan);
-        plan
+        self.react(*sim.time(), plan, max_dist)
+    }
+
+    /// Delay braking by the driver's reaction time. The train coasts from the time
+    /// when the need to brake is seen until the brakes are applied.
+    fn react(&mut self, time: f64, plan: DriverPlan, max_dist: f64) -> DriverPlan {
+        let braking = match (plan.action, self.step.0) {
+            (DriverAction::Brake, DriverAction::Brake) => false, // Already braking
+            (DriverAction::Brake, _) => true,
+            _ => false,
+        };
+        if !braking || self.train.velocity < 1e-5 || self.profile.reaction_time <= 0.0 {
+            self.brake_decision = None;
+            return plan;
+        }
+
+        let decision = *self.brake_decision.get_or_insert(time);
+        let remaining = decision + self.profile.reaction_time - time;
+        if remaining <= 1e-5 {
+            self.brake_decision = None;
+            return plan;
+        }
+        DriverPlan {
+            action: DriverAction::Coast,
+            dt: remaining.min(max_dist / self.train.velocity),
+        }
+    }
+
+    /// Train parameters on the current gradient. Acceleration is reduced uphill and
+    /// increased downhill by the component of gravity along the track, but is kept
+    /// above a tenth of the train's acceleration so that the train can always start.
+    /// Braking uses the service brake rate of the train on any gradient.
+    fn params(&self) -> TrainParams {
+        let params = self.train.params;
+        let acc = params.max_acc - GRAVITY * self.train.gradient / 1000.0;
+        TrainParams { max_acc: acc.max(0.1 * params.max_acc), .. params }
+    }
+
+    /// Speed restrictions ahead of the train from speed limits, and from switches
+    /// which will be passed on their branch side leg, following the current switch
+    /// positions within the movement authority. With continuous signalling, this is
+    /// the speed profile given with the authority to the end of the reserved routes.
+    fn speeds_ahead(&self, sim: &Sim) -> SmallVec<[DistanceVelocity; 4]> {
+        let mut restrictions = SmallVec::new();
+        let (_, (mut next_node, mut dist)) = self.train.location;
+        let mut visited = Vec::new();
+        while let Some(n) = next_node {
+            if dist > self.authority { break; }
+            let node = sim.world.statics.nodes[n].other_node;
+            if visited.contains(&node) { break; }
+            visited.push(node);
+
+            if let Some(v) = branch_max_velocity(sim, node) {
+                restrictions.push(DistanceVelocity { dx: dist, v: v });
+            }
+            for &obj in sim.world.statics.nodes[node].objects.iter() {
+                if let StaticObject::SpeedLimit { max_velocity } = sim.world.statics.objects[obj] {
+                    restrictions.push(DistanceVelocity { dx: dist, v: max_velocity });
+                }
+            }
+
+            match sim.world.edge_from(node) {
+                Some((n, d)) => {
+                    next_node = n;
+                    dist += d;
+                }
+                None => break,
+            }
+        }
+        restrictions
+    }
+
+    /// Distance to the end of the movement authority given by the interlocking,
+    /// found by following the current switch positions ahead of the train
+    /// up to the first signal which does not give authority.
+    /// In moving block mode, the authority also ends at the tail of the train ahead.
+    /// The signals and trains passed on the way are remembered, so that the
+    /// driver is woken up when their authority or position changes.
+    fn continuous_authority(&mut self, sim: &Sim) -> f64 {
+        self.authority_signals.clear();
+        self.authority_trains.clear();
+        let moving_block = sim.world.signalling == SignallingMode::MovingBlock;
+
+        // Trains ahead on the current edge
+        if moving_block {
+            if let Some(d) = self.train_ahead(sim, self.train.location.0, -self.train.travelled) {
+                return d;
+            }
+        }
+
+        let (_, (mut next_node, mut dist)) = self.train.location;
+        let mut visited = Vec::new();
+        loop {
+            let node = match next_node {
+                Some(n) => sim.world.statics.nodes[n].other_node,
+                None => return INFINITY, // Leaving the model
+            };
+            if visited.contains(&node) { return dist; }
+            visited.push(node);
+
+            for &obj in sim.world.statics.nodes[node].objects.iter() {
+                if let ObjectState::Signal { ref authority } = sim.world.state[obj] {
+                    self.authority_signals.push(obj);
+                    if authority.get().0.is_some() {
+                        if !self.granted_signals.contains(&obj) { self.granted_signals.push(obj); }
+                    } else if !self.granted_signals.contains(&obj) {
+                        if moving_block {
+                            // The signal shows stop as soon as the front of the train
+                            // ahead has passed it, but its tail may still be behind it.
+                            if let Some(d) = self.train_passing(sim, node, dist) {
+                                return d.min(dist);
+                            }
+                        }
+                        return dist;
+                    }
+                }
+            }
+
+            if moving_block {
+                if let Some(d) = self.train_ahead(sim, node, dist) {
+                    return d;
+                }
+            }
+
+            match sim.world.edge_from(node) {
+                Some((n, d)) => {
+                    next_node = n;
+                    dist += d;
+                }
+                None => return dist, // Switch is not in position
+            }
+        }
+    }
+
+    /// Distance to the tail of the nearest train whose front has passed the given node,
+    /// when the node is `dist` ahead of this train.
+    fn train_ahead(&mut self, sim: &Sim, node: NodeId, dist: f64) -> Option<f64> {
+        let mut tail = None;
+        let mut nearest = None;
+        for (id, position) in sim.world.trains.iter().enumerate() {
+            if id == self.id { continue; }
+            if let Some(p) = *position.get() {
+                if p.node != node || dist + p.travelled < 0.0 { continue; }
+                let d = (dist + p.travelled - p.length).max(0.0);
+                if tail.map(|t| d < t).unwrap_or(true) {
+                    tail = Some(d);
+                    nearest = Some(id);
+                }
+            }
+        }
+        self.authority_trains.extend(nearest);
+        tail
+    }
+
+    /// Distance to the tail of the nearest train which has passed the given node,
+    /// when the node is `dist` ahead of this train, also finding trains whose front
+    /// has passed later nodes. Follows the current switch positions ahead of the node
+    /// as far as the length of the longest train.
+    fn train_passing(&mut self, sim: &Sim, node: NodeId, dist: f64) -> Option<f64> {
+        let max_length = sim.world.trains.iter()
+            .filter_map(|p| p.get().map(|p| p.length))
+            .fold(0.0, f64::max);
+        let mut tail: Option<f64> = None;
+        let (mut node, mut d) = (node, dist);
+        let mut visited = Vec::new();
+        loop {
+            if let Some(t) = self.train_ahead(sim, node, d) {
+                tail = Some(tail.map(|x| x.min(t)).unwrap_or(t));
+            }
+            if d - dist >= max_length || visited.contains(&node) { break; }
+            visited.push(node);
+            match sim.world.edge_from(node) {
+                Some((Some(n), l)) => {
+                    node = sim.world.statics.nodes[n].other_node;
+                    d += l;
+                }
+                _ => break,
+            }
+        }
+        tail
+    }
+
+    fn publish_position(&mut self, sim: &mut Sim, inside: bool) {
+        let position = if inside {
+            Some(TrainPosition {
+                node: self.train.location.0,
+                travelled: self.train.travelled,
+                length: self.train.params.length,
+            })
+        } else {
+            None
+        };
+        sim.world.trains[self.id].set(&mut sim.scheduler, position);
+    }
+}
+
+/// Maximum velocity on the edge starting at the given node, if the edge
+/// is the branch side leg of a switch, in either direction of travel.
+fn branch_max_velocity(sim: &Sim, node: NodeId) -> Option<f64> {
+    let nodes = &sim.world.statics.nodes;
+    let objects = &sim.world.statics.objects;
+    match nodes[node].edges {
+        Edges::Switchable(sw) => {
+            if let StaticObject::Switch { branch_side, branch_max_velocity, .. } = objects[sw] {
+                match sim.world.state[sw] {
+                    ObjectState::Switch { ref position, .. } if *position.get() == Some(branch_side) => branch_max_velocity,
+                    _ => None,
+                }
+            } else {
+                panic!("Not a switch");
+            }
+        }
+        Edges::Single(other, _) => {
+            if let Edges::Switchable(sw) = nodes[other].edges {
+                if let StaticObject::Switch { left_link, right_link, branch_side, branch_max_velocity, .. } = objects[sw] {
+                    let branch = match branch_side {
+                        SwitchPosition::Left => left_link,
+                        SwitchPosition::Right => right_link,
+                    };
+                    if branch.0 == node { return branch_max_velocity; }
+                }
+            }
+            None
+        }
+        _ => None,
     }
 }
 
@@ -268,12 +609,20 @@ impl<'a> Process<Infrastructure<'a>> for Driver {
             Activation::Activate => {
                 self.activate(sim);
                 self.activation = Activation::Running;
+                if sim.world.signalling == SignallingMode::MovingBlock {
+                    self.publish_position(sim, true);
+                }
             },
             Activation::Running => { }
         };
 
         //println!("resume train");
+        let moved = *sim.time() - self.step.1 > 1e-5;
         let modelcontainment = self.move_train(sim);
+        if sim.world.signalling == SignallingMode::MovingBlock && moved {
+            let inside = if let ModelContainment::Inside = modelcontainment { true } else { false };
+            self.publish_position(sim, inside);
+        }
         match modelcontainment {
             ModelContainment::Outside => {
                 //println!("TRAIN FINISHED");
@@ -299,12 +648,17 @@ impl<'a> Process<Infrastructure<'a>> for Driver {
                     self.step.0 = DriverAction::Coast;
                 }
                 //println!("Connected signals: {:?}", self.connected_signals);
-                for &(ref sig, _) in self.connected_signals.iter() {
-                    match sim.world.state[*sig] {
+                let signals = self.connected_signals.iter().map(|&(s, _)| s)
+                    .chain(self.authority_signals.iter().cloned());
+                for sig in signals {
+                    match sim.world.state[sig] {
                         ObjectState::Signal { ref authority } => events.push(authority.event()),
                         _ => panic!("Object is not a signal"),
                     }
                 }
+                for &train in self.authority_trains.iter() {
+                    events.push(sim.world.trains[train].event());
+                }
                 ProcessState::Wait(events)
             }
         }
@@ -114,15 +114,28 @@ fn plan_brake_x(start: Point, dx: f64, acc: f64) -> Point {
     }
 }
 
+/// Accelerate until braking (after coasting for the reaction time) is needed
+/// to meet the restriction, then brake.
 fn plan_accel_brake_intersection(start: Point,
                                  restriction: DistanceVelocity,
                                  acc: f64,
-                                 brk: f64)
+                                 brk: f64,
+                                 reaction_time: f64)
                                  -> (Point, Point) {
-    let intersection_dx = (2.0 * brk * restriction.dx + restriction.v * restriction.v -
-                           start.v * start.v) / (2.0 * (acc + brk));
-
-    let intersection_v = (2.0 * acc * intersection_dx + start.v * start.v).sqrt();
+    let (intersection_dx, intersection_v) = if reaction_time > 0.0 {
+        // Solve for the velocity v at the end of acceleration:
+        // (v^2 - v0^2)/2a + v t_r + (v^2 - vr^2)/2b = dx
+        let a = 1.0 / (2.0 * acc) + 1.0 / (2.0 * brk);
+        let b = reaction_time;
+        let c = -(restriction.dx + start.v * start.v / (2.0 * acc) +
+                  restriction.v * restriction.v / (2.0 * brk));
+        let v = (-b + (b * b - 4.0 * a * c).sqrt()) / (2.0 * a);
+        ((v * v - start.v * start.v) / (2.0 * acc), v)
+    } else {
+        let dx = (2.0 * brk * restriction.dx + restriction.v * restriction.v -
+                  start.v * start.v) / (2.0 * (acc + brk));
+        (dx, (2.0 * acc * dx + start.v * start.v).sqrt())
+    };
     let intersection_dt = (intersection_v - start.v) / acc;
 
     let brake_dx = (start.v * start.v - restriction.v * restriction.v) / (2.0 * brk);
@@ -140,9 +153,9 @@ fn plan_accel_brake_intersection(start: Point,
              v: intersection_v,
          },
          Point {
-             // Then brake to
-             t: start.t + intersection_dt + brake_dt,
-             x: start.x + intersection_dx + brake_dx,
+             // Then coast for the reaction time and brake to
+             t: start.t + intersection_dt + reaction_time + brake_dt,
+             x: start.x + intersection_dx + intersection_v * reaction_time + brake_dx,
              v: restriction.v,
          })
     }
@@ -156,13 +169,16 @@ fn plan_coast_x(start: Point, dx: f64) -> Point {
     }
 }
 
+/// Coast until braking (after coasting for the reaction time) is needed
+/// to meet the restriction, then brake.
 fn plan_coast_brake_intersection(start: Point,
                                  restriction: DistanceVelocity,
-                                 brk: f64)
+                                 brk: f64,
+                                 reaction_time: f64)
                                  -> (Point, Point) {
     let brake_dx = (start.v * start.v - restriction.v * restriction.v) / (2.0 * brk);
     let brake_dt = (start.v - restriction.v) / brk;
-    let coast_dx = restriction.dx - brake_dx;
+    let coast_dx = restriction.dx - brake_dx - start.v * reaction_time;
     let coast_dt = if start.v == 0.0 { std::f64::INFINITY } else { coast_dx / start.v };
 
     (Point {
@@ -172,16 +188,21 @@ fn plan_coast_brake_intersection(start: Point,
          v: start.v,
      },
      Point {
-         t: start.t + coast_dt + brake_dt,
-         x: start.x + coast_dx + brake_dx,
+         t: start.t + coast_dt + reaction_time + brake_dt,
+         x: start.x + coast_dx + start.v * reaction_time + brake_dx,
          v: restriction.v,
      })
 }
 
+/// Plan the next driver action. Braking curves include coasting for the driver's
+/// reaction time before the brakes are applied, and the driver does not accelerate
+/// above `coasting_velocity`, but coasts from there.
 pub fn dynamic_plan_step(train: &TrainParams,
                          max_dist: f64,
                          current_velocity: f64,
-                         profile: &StaticMaximumVelocityProfile)
+                         profile: &StaticMaximumVelocityProfile,
+                         reaction_time: f64,
+                         coasting_velocity: f64)
                          -> DriverPlan {
 
     let tol = 1e-4;
@@ -221,7 +242,7 @@ pub fn dynamic_plan_step(train: &TrainParams,
     }
 
     // Acceleration is limited by current max speed
-    accel_plans.push(plan_accel_v(p, profile.local_max_velocity, train.max_acc));
+    accel_plans.push(plan_accel_v(p, profile.local_max_velocity.min(coasting_velocity), train.max_acc));
 
     // Acceleration is limited by maximum travel distance
     accel_plans.push(plan_accel_x(p, max_dist, train.max_acc));
@@ -236,13 +257,13 @@ pub fn dynamic_plan_step(train: &TrainParams,
     for &restriction in profile.max_velocity_ahead.iter() {
         // Acceleration limited by braking curve (+ braking curve)
         let (acc, brk) =
-            plan_accel_brake_intersection(p, restriction, train.max_acc, train.max_brk);
+            plan_accel_brake_intersection(p, restriction, train.max_acc, train.max_brk, reaction_time);
         //println!("ACCEL PLANX {:?} {:?} {:?}", restriction, acc, brk);
         accel_plans.push(acc);
         brake_plans.push(brk);
 
         // Coasting limited by braking curve (+ braking curve)
-        let (coast, brk) = plan_coast_brake_intersection(p, restriction, train.max_brk);
+        let (coast, brk) = plan_coast_brake_intersection(p, restriction, train.max_brk, reaction_time);
 
         coast_plans.push(coast);
         brake_plans.push(brk);
@@ -3,13 +3,15 @@ use eventsim::*;
 use eventsim::observable::Observable;
 use input::staticinfrastructure::*;
 use output::history::InfrastructureLogEvent;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 pub type TrainId = usize;
 pub type InfLogger = Box<Fn(InfrastructureLogEvent)>;
 
 
 use std::f64::INFINITY;
-use railway::{Sim, Proc};
+use railway::{Sim, Proc, SignallingMode};
 
 // pub trait Logger {
 //    fn output(&mut self, msg: InfrastructureLogEvent);
@@ -40,6 +42,7 @@ pub enum ObjectState {
         occupied: Observable<usize>,
     },
     TVDLimit,
+    SpeedLimit,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -59,7 +62,11 @@ impl<'a> Process<Infrastructure<'a>> for MoveSwitch {
     fn resume(&mut self, sim: &mut Sim) -> ProcessState {
         if !self.state {
             self.state = true;
-            ProcessState::Wait(SmallVec::from_slice(&[sim.create_timeout(5.0)]))
+            let throw_time = match sim.world.statics.objects[self.sw] {
+                StaticObject::Switch { throw_time, .. } => throw_time,
+                _ => panic!("Not a switch"),
+            };
+            ProcessState::Wait(SmallVec::from_slice(&[sim.create_timeout(throw_time)]))
         } else {
             //println!("SWITCH MOVED {:?} {:?}", self.sw, self.pos);
             match sim.world.state[self.sw] {
@@ -142,9 +149,27 @@ impl TrainVisitable for StaticObject {
     }
 }
 
+/// Location of a train's front, published to other trains for moving block separation.
+#[derive(Debug, Copy, Clone)]
+pub struct TrainPosition {
+    /// Node which the front of the train last passed.
+    pub node: NodeId,
+    /// Distance travelled by the front of the train since passing the node.
+    pub travelled: f64,
+    pub length: f64,
+}
+
 pub struct Infrastructure<'a> {
     pub statics: &'a StaticInfrastructure,
     pub state: Vec<ObjectState>,
+    pub trains: Vec<Observable<Option<TrainPosition>>>,
+    /// Number of active routes locking each switch. Only used in moving block,
+    /// where routes requiring the same switch position may share it.
+    pub switch_users: HashMap<ObjectId, usize>,
+    /// Overlap allocated past each signal, which is released by its timeout or
+    /// when the next route from the signal is set.
+    pub overlaps: HashMap<ObjectId, Rc<Overlap>>,
+    pub signalling: SignallingMode,
     pub logger: InfLogger,
 }
 
@@ -152,9 +177,10 @@ use std::fmt;
 impl<'a> fmt::Debug for Infrastructure<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
-               "Infrastructure {{ statics: {:?}, state: {:?} }}",
+               "Infrastructure {{ statics: {:?}, state: {:?}, trains: {:?} }}",
                self.statics,
-               self.state)
+               self.state,
+               self.trains)
     }
 }
 
@@ -162,6 +188,7 @@ impl<'a> fmt::Debug for Infrastructure<'a> {
 impl<'a> Infrastructure<'a> {
     pub fn new(scheduler: &mut Scheduler,
                infrastructure: &'a StaticInfrastructure,
+               signalling: SignallingMode,
                logger: InfLogger)
                -> Infrastructure<'a> {
         use input::staticinfrastructure::StaticObject::*;
@@ -173,6 +200,7 @@ impl<'a> Infrastructure<'a> {
                     ObjectState::Signal { authority: Observable::new(scheduler, (None,None)) }
                 }
                 TVDLimit { .. } => ObjectState::TVDLimit,
+                SpeedLimit { .. } => ObjectState::SpeedLimit,
                 TVDSection => {
                     ObjectState::TVDSection {
                         reserved: Observable::new(scheduler, TVDReservation::Free),
@@ -191,6 +219,10 @@ impl<'a> Infrastructure<'a> {
         Infrastructure {
             statics: infrastructure,
             state: state,
+            trains: Vec::new(),
+            switch_users: HashMap::new(),
+            overlaps: HashMap::new(),
+            signalling: signalling,
             logger: logger,
         }
     }
@@ -9,3 +9,37 @@ use super::eventsim;
 use railway::infrastructure::Infrastructure;
 type Sim<'a> = eventsim::Simulation<Infrastructure<'a>>;
 type Proc<'a> = eventsim::Process<Infrastructure<'a>>;
+
+use std::str::FromStr;
+
+/// How movement authority is communicated from the interlocking to the trains.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignallingMode {
+    /// Conventional lineside signalling: the driver learns the movement authority
+    /// from signals within sight distance.
+    Lineside,
+    /// In-cab signalling with continuous supervision (ETCS level 2 / CBTC): the train
+    /// receives movement authority up to the end of its reserved routes,
+    /// regardless of sight distance.
+    Continuous,
+    /// Continuous in-cab signalling with moving block (ETCS level 3): detection sections
+    /// are not locked by routes, and trains instead keep a safe braking distance
+    /// behind the tail of the train ahead. Routes only lock their switches.
+    MovingBlock,
+}
+
+impl Default for SignallingMode {
+    fn default() -> Self { SignallingMode::Lineside }
+}
+
+impl FromStr for SignallingMode {
+    type Err = String;
+    fn from_str(s :&str) -> Result<Self, String> {
+        match s {
+            "lineside" => Ok(SignallingMode::Lineside),
+            "continuous" => Ok(SignallingMode::Continuous),
+            "movingblock" => Ok(SignallingMode::MovingBlock),
+            x => Err(format!("unknown signalling mode: {}", x)),
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use eventsim::{Process, ProcessState, EventId, observable::*};
-use super::Sim;
+use super::{Sim, SignallingMode};
 use smallvec::SmallVec;
 use input::staticinfrastructure::*;
 use super::infrastructure::*;
 use output::history::{InfrastructureLogEvent, RouteStatus};
 use log::*;
+use std::rc::Rc;
 
 enum ActivateRouteState {
     Queued, // Waiting for conflicting routes to activate first
@@ -73,16 +74,63 @@ fn require_tvd(s :ObjectId, endpoint :Option<ObjectId>, inf :&Infrastructure) ->
     Ok(())
 }
 
+fn require_signal_stop(s :ObjectId, inf :&Infrastructure) -> Result<(), EventId> {
+    if let ObjectState::Signal { ref authority } = inf.state[s] {
+        if authority.get().0.is_some() {
+            return Err(authority.event());
+        }
+    } else {
+        panic!("Not a signal.");
+    }
+    Ok(())
+}
+
+/// In moving block, a switch which is already locked by another route may be
+/// shared by routes which require it in the same position.
+fn require_switch_shared(s :ObjectId, pos :SwitchPosition, inf :&Infrastructure) -> Result<(), EventId> {
+    if let ObjectState::Switch { ref reserved, ref position, ref throwing } = inf.state[s] {
+        if *reserved.get() {
+            if throwing.is_some() { return Err(position.event()); }
+            if *position.get() != Some(pos) { return Err(reserved.event()); }
+        }
+    } else {
+        panic!("Not a switch.");
+    }
+    Ok(())
+}
+
 fn unavailable_resource(r: &Route, overlap: Option<&Overlap>, infrastructure: &Infrastructure) -> Result<(),EventId> {
 
     let overlap_endpoint = if let RouteEntryExit::SignalTrigger { signal, .. } = r.entry { Some(signal) } else { None };
 
+    if infrastructure.signalling == SignallingMode::MovingBlock {
+        // Sections are not locked in moving block. The route cannot be set again
+        // before the previous train has passed its entry signal, and trains are kept
+        // apart by their movement authority.
+        if let RouteEntryExit::SignalTrigger { signal, .. } = r.entry {
+            require_signal_stop(signal, infrastructure)?;
+        }
+        for &(sw, pos) in r.resources.switch_positions.iter() {
+            require_switch_shared(sw, pos, infrastructure)?;
+        }
+        return Ok(());
+    }
+
     for s in r.resources.sections.iter() {
         require_tvd(*s, overlap_endpoint, infrastructure)?;
     }
 
+    // Switches of the overlap past the entry signal are released when the route is set.
+    let held_overlap = overlap_endpoint.and_then(|s| infrastructure.overlaps.get(&s));
+    let require_route_switch = |sw :ObjectId| {
+        if held_overlap.map(|o| o.switch_positions.iter().any(|&(x, _)| x == sw)).unwrap_or(false) {
+            return Ok(());
+        }
+        require_switch(sw, infrastructure)
+    };
+
     for &(sw, _pos) in r.resources.switch_positions.iter() {
-        require_switch(sw, infrastructure)?;
+        require_route_switch(sw)?;
     }
 
     if let Some(overlap) = overlap {
@@ -91,7 +139,7 @@ fn unavailable_resource(r: &Route, overlap: Option<&Overlap>, infrastructure: &I
         }
 
         for &(sw, _pos) in overlap.switch_positions.iter() {
-            require_switch(sw, infrastructure)?;
+            require_route_switch(sw)?;
         }
     }
     
@@ -105,8 +153,11 @@ fn release_overlap(overlap: &Overlap, sim :&mut Sim) {
     for s in overlap.sections.iter() {
         match state[*s] {
             ObjectState::TVDSection { ref mut reserved, .. } => {
-                reserved.set(scheduler, TVDReservation::Free);
-                logger(InfrastructureLogEvent::Reserved(*s,false));
+                // Sections which have been locked by a route since stay locked.
+                if let TVDReservation::Overlap(_) = *reserved.get() {
+                    reserved.set(scheduler, TVDReservation::Free);
+                    logger(InfrastructureLogEvent::Reserved(*s,false));
+                }
             }
             _ => panic!("Not a TVD"),
         };
@@ -149,6 +200,13 @@ fn allocate_overlap(overlap: &Overlap, exit :ObjectId, sim :&mut Sim) {
 }
 
 fn allocate_resources(r: &Route, sim :&mut Sim) {
+    if sim.world.signalling == SignallingMode::MovingBlock {
+        for &(sw, _pos) in r.resources.switch_positions.iter() {
+            lock_shared_switch(sw, sim);
+        }
+        return;
+    }
+
     let state = &mut sim.world.state;
     let logger = &mut sim.world.logger;
     let scheduler = &mut sim.scheduler;
@@ -173,6 +231,36 @@ fn allocate_resources(r: &Route, sim :&mut Sim) {
     }
 }
 
+/// Lock a switch for one more route in moving block.
+fn lock_shared_switch(sw :ObjectId, sim :&mut Sim) {
+    let users = sim.world.switch_users.entry(sw).or_insert(0);
+    *users += 1;
+    if *users == 1 {
+        match sim.world.state[sw] {
+            ObjectState::Switch { ref mut reserved, .. } => {
+                reserved.set(&mut sim.scheduler, true);
+                (sim.world.logger)(InfrastructureLogEvent::Reserved(sw,true));
+            }
+            _ => panic!("Not a switch"),
+        }
+    }
+}
+
+/// Release a switch from one route in moving block. The switch is unlocked
+/// when no other route uses it.
+fn release_shared_switch(sw :ObjectId, sim :&mut Sim) {
+    let users = sim.world.switch_users.get_mut(&sw).expect("Release of unlocked switch");
+    *users -= 1;
+    if *users == 0 {
+        match sim.world.state[sw] {
+            ObjectState::Switch { ref mut reserved, .. } => {
+                reserved.set(&mut sim.scheduler, false);
+                (sim.world.logger)(InfrastructureLogEvent::Reserved(sw,false));
+            }
+            _ => panic!("Not a switch"),
+        }
+    }
+}
 
 fn movable_events(r: &Route, sim: &mut Sim) -> Vec<EventId> {
     let throw = r.resources.switch_positions
@@ -218,7 +306,9 @@ fn movable_events(r: &Route, sim: &mut Sim) -> Vec<EventId> {
 
 impl<'a> Process<Infrastructure<'a>> for ActivateRoute {
     fn resume(&mut self, sim: &mut Sim) -> ProcessState {
-        let overlap = self.overlap.map(|i| self.route.overlaps[i].clone());
+        // Overlaps are not used in moving block.
+        let overlap = if sim.world.signalling == SignallingMode::MovingBlock { None }
+                      else { self.overlap.map(|i| self.route.overlaps[i].clone()) };
         (sim.world.logger)(InfrastructureLogEvent::Route(0, RouteStatus::Pending)); // TODO id from where?
 
         if let ActivateRouteState::Queued = self.state {
@@ -234,15 +324,23 @@ impl<'a> Process<Infrastructure<'a>> for ActivateRoute {
         if let ActivateRouteState::Allocate = self.state {
             match unavailable_resource(&self.route, overlap.as_ref(), &sim.world) {
                 Ok(()) => {
+                    if let RouteEntryExit::SignalTrigger { signal, .. } = self.route.entry {
+                        if let Some(held) = sim.world.overlaps.remove(&signal) {
+                            release_overlap(&held, sim);
+                        }
+                    }
                     allocate_resources(&self.route, sim);
                     if let Some(ref overlap) = overlap { 
                         if let RouteEntryExit::Signal(end) = self.route.exit {
                             debug!("ALLOCATING OVERLAP on {:?}", self.route);
                             allocate_overlap(overlap, end, sim); 
+                            let overlap = Rc::new(overlap.clone());
+                            sim.world.overlaps.insert(end, overlap.clone());
                             if let RouteEntryExit::SignalTrigger { ref trigger_section, .. } = self.route.entry {
                                 if let Some(t) = overlap.timeout {
                                     sim.start_process(Box::new(OverlapTimeout {
-                                        overlap: overlap.clone(),
+                                        overlap: overlap,
+                                        exit: end,
                                         trigger: *trigger_section,
                                         time: t,
                                         state: OverlapTimeoutState::Start,
@@ -289,6 +387,7 @@ impl<'a> Process<Infrastructure<'a>> for ActivateRoute {
                     state: CatchSignalState::Start,
                     distant_sig: distant_sig,
                     route_length: self.route.length,
+                    occupied: 0,
                 }));
            },
            _ =>  {},
@@ -296,10 +395,18 @@ impl<'a> Process<Infrastructure<'a>> for ActivateRoute {
 
         //println!("ROUTE RELEASES: {:?}", self.route.resources.releases);
         for release in self.route.resources.releases.iter() {
+            let mut resources = release.resources.clone().to_vec();
+            if sim.world.signalling == SignallingMode::MovingBlock {
+                // Only the switches were locked by the route.
+                let switches = &self.route.resources.switch_positions;
+                resources.retain(|obj| switches.iter().any(|&(sw, _)| sw == *obj));
+            }
             sim.start_process(Box::new(ReleaseRoute {
                 trigger: release.trigger,
-                resources: release.resources.clone().to_vec(),
+                resources,
                 state: ReleaseRouteState::Start,
+                occupied: 0,
+                ahead: 0,
             }));
         }
 
@@ -313,7 +420,8 @@ enum OverlapTimeoutState {
 }
 
 struct OverlapTimeout {
-    overlap: Overlap,
+    overlap: Rc<Overlap>,
+    exit: ObjectId,
     trigger: ObjectId,
     time: f64,
     state: OverlapTimeoutState,
@@ -335,7 +443,13 @@ impl<'a> Process<Infrastructure<'a>> for OverlapTimeout {
                 ProcessState::Wait(SmallVec::from_slice(&[sim.create_timeout(self.time)]))
             },
             OverlapTimeoutState::AwaitTimer => {
-                release_overlap(&self.overlap, sim);
+                // The overlap may already have been released by the next route from the exit signal.
+                let current = sim.world.overlaps.get(&self.exit)
+                    .map(|o| Rc::ptr_eq(o, &self.overlap)).unwrap_or(false);
+                if current {
+                    sim.world.overlaps.remove(&self.exit);
+                    release_overlap(&self.overlap, sim);
+                }
                 ProcessState::Finished
             },
         }
@@ -353,6 +467,7 @@ struct CatchSignal {
     state: CatchSignalState,
     distant_sig: Option<ObjectId>,
     route_length :f64,
+    occupied: usize,
 }
 impl CatchSignal {
     fn set_auth(&self, sim :&mut Sim, auth :(Option<f64>,Option<f64>)) {
@@ -382,10 +497,10 @@ impl CatchSignal {
         }
     }
 
-    fn was_tvd_triggered(&self, sim :&mut Sim) -> bool {
+    fn tvd_occupied(&self, sim :&mut Sim) -> usize {
         match sim.world.state[self.tvd] {
             ObjectState::TVDSection { ref occupied, .. } => {
-                *occupied.get() > 0
+                *occupied.get()
             }, _ => panic!("not a tvd section"),
         }
     }
@@ -397,6 +512,12 @@ impl<'a> Process<Infrastructure<'a>> for CatchSignal {
         match self.state {
             CatchSignalState::Start => {
                 self.state = CatchSignalState::AwaitTrigger;
+                // In moving block, the previous train may still occupy the section, so the
+                // signal is caught when a new train enters it. With locked sections,
+                // the section is always free when the route is set.
+                if sim.world.signalling == SignallingMode::MovingBlock {
+                    self.occupied = self.tvd_occupied(sim);
+                }
                 let tvd_event = self.get_tvd_event(sim);
                 if let Some((dist, dist_ev)) = self.get_distant(sim) {
                     self.set_auth(sim, (Some(self.route_length), dist));
@@ -407,15 +528,19 @@ impl<'a> Process<Infrastructure<'a>> for CatchSignal {
                 }
             }
             CatchSignalState::AwaitTrigger => {
-                if self.was_tvd_triggered(sim) {
+                let occupied = self.tvd_occupied(sim);
+                if occupied > self.occupied {
                     self.set_auth(sim, (None,None));
                     ProcessState::Finished
                 } else {
+                    self.occupied = occupied;
                     let tvd_event = self.get_tvd_event(sim);
-                    let (dist,dist_ev) = self.get_distant(sim).unwrap(); // must have distant
-                                            // to be woken up without tvd triggered.
-                    self.set_auth(sim, (Some(self.route_length), dist));
-                    ProcessState::Wait(SmallVec::from_slice(&[tvd_event, dist_ev]))
+                    if let Some((dist,dist_ev)) = self.get_distant(sim) {
+                        self.set_auth(sim, (Some(self.route_length), dist));
+                        ProcessState::Wait(SmallVec::from_slice(&[tvd_event, dist_ev]))
+                    } else {
+                        ProcessState::Wait(SmallVec::from_slice(&[tvd_event]))
+                    }
                 }
             }
         }
@@ -428,28 +553,64 @@ enum ReleaseRouteState {
     AwaitExit,
 }
 
+/// Releases the resources of a route when its train has left the trigger section.
+///
+/// In moving block, trains following each other may be in the trigger section at
+/// the same time. The trains are assumed to leave the section in the order they
+/// entered it, so that the route is released when the trains which were in the
+/// section before its own train have left, followed by its own train.
 struct ReleaseRoute {
     trigger: ObjectId,
     resources: Vec<ObjectId>,
     state: ReleaseRouteState,
+    /// Last seen number of trains in the trigger section (moving block).
+    occupied: usize,
+    /// Number of trains ahead of the route's own train in the trigger section (moving block).
+    ahead: usize,
 }
 
 impl<'a> Process<Infrastructure<'a>> for ReleaseRoute {
     fn resume(&mut self, sim: &mut Sim) -> ProcessState {
-        let event = match sim.world.state[self.trigger] {
-            ObjectState::TVDSection { ref mut occupied, .. } => occupied.event(),
+        let (event, occupied) = match sim.world.state[self.trigger] {
+            ObjectState::TVDSection { ref mut occupied, .. } => (occupied.event(), *occupied.get()),
             _ => panic!("Not a TVD section"),
         };
+        let moving_block = sim.world.signalling == SignallingMode::MovingBlock;
 
         match self.state {
             ReleaseRouteState::Start => {
                 self.state = ReleaseRouteState::AwaitEntry;
+                self.occupied = occupied;
+                ProcessState::Wait(SmallVec::from_slice(&[event]))
+            }
+            ReleaseRouteState::AwaitEntry if moving_block && occupied <= self.occupied => {
+                // A train ahead has left the section before the route's own train entered it.
+                self.occupied = occupied;
                 ProcessState::Wait(SmallVec::from_slice(&[event]))
             }
             ReleaseRouteState::AwaitEntry => {
                 self.state = ReleaseRouteState::AwaitExit;
+                self.ahead = occupied.saturating_sub(1);
+                self.occupied = occupied;
+                ProcessState::Wait(SmallVec::from_slice(&[event]))
+            }
+            ReleaseRouteState::AwaitExit if moving_block && occupied >= self.occupied => {
+                // A following train has entered the section.
+                self.occupied = occupied;
+                ProcessState::Wait(SmallVec::from_slice(&[event]))
+            }
+            ReleaseRouteState::AwaitExit if moving_block && self.ahead >= self.occupied - occupied => {
+                self.ahead -= self.occupied - occupied;
+                self.occupied = occupied;
                 ProcessState::Wait(SmallVec::from_slice(&[event]))
             }
+            ReleaseRouteState::AwaitExit if moving_block => {
+                for obj in &self.resources {
+                    release_shared_switch(*obj, sim);
+                }
+                (sim.world.logger)(InfrastructureLogEvent::Route(0,RouteStatus::Released));
+                ProcessState::Finished
+            }
             ReleaseRouteState::AwaitExit => {
                 for obj in &self.resources {
                     match sim.world.state[*obj] {
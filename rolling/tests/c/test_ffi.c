@@ -0,0 +1,118 @@
+/* Test of the C interface, linked with the rolling static library.
+ * Run with `make ctest` from the rolling directory. */
+
+#include <stdio.h>
+#include <string.h>
+#include "rolling.h"
+
+#define EXAMPLE "../docs/examples/overlap/"
+
+static int failures = 0;
+
+#define CHECK(x) do { if (!(x)) { \
+    fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #x); \
+    if (last_error_message()) fprintf(stderr, "  last error: %s\n", last_error_message()); \
+    failures++; } } while (0)
+
+static void test_errors(void) {
+    CHECK(parse_infrastructure_file(EXAMPLE "does-not-exist") == NULL);
+    CHECK(last_error_code() == ErrorCode_Io);
+    CHECK(last_error_message() != NULL);
+
+    CHECK(parse_infrastructure("node a -- ") == NULL);
+    CHECK(last_error_code() == ErrorCode_Parse);
+
+    CHECK(parse_dispatch(NULL) == NULL);
+    CHECK(last_error_code() == ErrorCode_NullPointer);
+
+    Dispatch *d = parse_dispatch("");
+    CHECK(d != NULL);
+    CHECK(last_error_code() == ErrorCode_Ok);
+    CHECK(last_error_message() == NULL);
+    free_dispatch(d);
+}
+
+static void test_simulation(void) {
+    Infrastructure *inf = parse_infrastructure_file(EXAMPLE "infrastructure");
+    CHECK(inf != NULL);
+    Routes *routes = parse_routes_file(inf, EXAMPLE "routes");
+    CHECK(routes != NULL);
+    Dispatch *dispatch = parse_dispatch_file(EXAMPLE "plan");
+    CHECK(dispatch != NULL);
+    if (!inf || !routes || !dispatch) return;
+
+    CHECK(eval_history(inf, routes, dispatch, 1.0, 42) == NULL);
+    CHECK(last_error_code() == ErrorCode_InvalidArgument);
+
+    History *history = eval_history(inf, routes, dispatch, 1.0, 0);
+    CHECK(history != NULL);
+    if (history) {
+        size_t num_trains = history_num_trains(history);
+        CHECK(num_trains > 0);
+        for (size_t i = 0; i < num_trains; i++) {
+            char *name = history_train_name(history, i);
+            CHECK(name != NULL);
+            TrainParams params;
+            CHECK(history_train_params(history, i, &params) == ErrorCode_Ok);
+            CHECK(params.length > 0.0);
+
+            double t = 0.0, x = 0.0;
+            size_t nodes = 0;
+            for (size_t j = 0; j < history_train_num_events(history, i); j++) {
+                TrainEvent ev;
+                CHECK(history_train_event(history, i, j, &ev) == ErrorCode_Ok);
+                if (ev.kind == TrainEventKind_Wait || ev.kind == TrainEventKind_Move) t += ev.dt;
+                if (ev.kind == TrainEventKind_Move) x += ev.dx;
+                if (ev.kind == TrainEventKind_Node) {
+                    char *node = node_name(inf, ev.id);
+                    CHECK(node != NULL);
+                    free_string(node);
+                    nodes++;
+                }
+            }
+            CHECK(nodes > 0);
+            CHECK(x > 0.0);
+            printf("train %s: %zu nodes, %.1f m, %.1f s\n", name, nodes, x, t);
+            free_string(name);
+        }
+        CHECK(history_train_name(history, num_trains) == NULL);
+        CHECK(last_error_code() == ErrorCode_InvalidArgument);
+
+        size_t num_inf = history_num_infrastructure_events(history);
+        CHECK(num_inf > 0);
+        for (size_t j = 0; j < num_inf; j++) {
+            InfrastructureEvent ev;
+            CHECK(history_infrastructure_event(history, j, &ev) == ErrorCode_Ok);
+            if (ev.kind == InfrastructureEventKind_Occupied) CHECK(ev.train < num_trains);
+        }
+
+        char *visits = history_visits(inf, history);
+        CHECK(visits != NULL && strlen(visits) > 0);
+        free_string(visits);
+
+        char *json = history_json(inf, history);
+        CHECK(json != NULL && strstr(json, "\"version\":") != NULL);
+        free_string(json);
+
+        free_history(history);
+    }
+
+    char *visits = eval_simplified_timestep(inf, routes, dispatch, 1.0);
+    CHECK(visits != NULL);
+    free_string(visits);
+
+    free_dispatch(dispatch);
+    free_routes(routes);
+    free_infrastructure(inf);
+}
+
+int main(void) {
+    test_errors();
+    test_simulation();
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
@@ -0,0 +1,619 @@
+extern crate rolling;
+extern crate railml2dgraph;
+
+use rolling::input::dispatch::parse_dispatch;
+use rolling::input::staticinfrastructure::{InfNames, RouteEntryExit, Routes, StaticObject, StopLocation, SwitchPosition};
+use rolling::output::history::{History, InfrastructureLogEvent, TrainLogEvent};
+use rolling::railway::SignallingMode;
+use railml2dgraph::RailMLVersion;
+use railml2dgraph::base::Side;
+use railml2dgraph::routes::{OverlapOptions, RouteBoundary};
+
+const RAILML3: &str = include_str!("../../docs/examples/railml3_switch/railmlinfrastructure");
+const RAILML2: &str = include_str!("../../livehtml/examples/test.railml");
+
+/// Convert a railML document to the D-graph and route formats.
+fn convert(railml: &str) -> (String, String, Vec<railml2dgraph::branching::Diagnostic>) {
+    let (doc, ns) = railml2dgraph::get_xml_string(railml, false).unwrap();
+    let (model, _, diagnostics) = railml2dgraph::convert(&doc, &ns).unwrap();
+    let (routes, _) = railml2dgraph::convert_routes(&doc, &ns, &model, None).unwrap();
+    let mut infrastructure = Vec::new();
+    railml2dgraph::output::print_rolling(&mut infrastructure, &model).unwrap();
+    let mut route_text = Vec::new();
+    railml2dgraph::output::print_routes(&mut route_text, &model, &routes).unwrap();
+    (String::from_utf8(infrastructure).unwrap(), String::from_utf8(route_text).unwrap(), diagnostics)
+}
+
+/// Name of the route from a boundary or signal to another boundary or signal.
+fn route_name(routes: &Routes<String>, names: &InfNames<String>, from: &str, to: &str) -> String {
+    let is = |x: &RouteEntryExit, name: &str| match *x {
+        RouteEntryExit::Boundary(Some(n)) => names.node_names.get(name) == Some(&n),
+        RouteEntryExit::Signal(o) | RouteEntryExit::SignalTrigger { signal: o, .. } =>
+            names.object_names.get(name) == Some(&o),
+        _ => false,
+    };
+    routes.iter().find(|&(_, r)| is(&r.entry, from) && is(&r.exit, to))
+        .map(|(name, _)| name.clone())
+        .unwrap_or_else(|| panic!("no route from {} to {}", from, to))
+}
+
+/// Sight distances to each signal with the track and position of the sight, and
+/// the tracks of the nodes.
+fn sight_and_tracks(infrastructure: &str) -> (Vec<(String, f64, String)>, Vec<String>) {
+    let (inf, names) = rolling::get_infrastructure_string(infrastructure).unwrap();
+    let signal_name = |id| names.object_names.iter().find(|&(_, &o)| o == id).unwrap().0.clone();
+    let mut sight = Vec::new();
+    for (node, n) in inf.nodes.iter().enumerate() {
+        for &obj in &n.objects {
+            if let StaticObject::Sight { distance, signal } = inf.objects[obj] {
+                let at = names.node_positions.get(&node).map(|p| format!("{} {:.1}", p.track, p.pos));
+                sight.push((signal_name(signal), distance, at.unwrap_or_default()));
+            }
+        }
+    }
+    sight.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut tracks = names.node_positions.values().map(|p| p.track.clone()).collect::<Vec<_>>();
+    tracks.sort();
+    tracks.dedup();
+    (sight, tracks)
+}
+
+/// Exporting a converted file to railML and converting it again keeps the
+/// signals' sight distances and the track ids.
+#[test]
+fn export_round_trip() {
+    let (infrastructure, _, _) = convert(RAILML2);
+    let (sight, tracks) = sight_and_tracks(&infrastructure);
+    assert!(sight.iter().all(|&(_, d, _)| d > 0.0), "{:?}", sight);
+
+    let (inf, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let model = rolling::output::routes::dgraph_model(&inf, &names).unwrap();
+    for &version in &[RailMLVersion::V2, RailMLVersion::V3] {
+        let railml = railml2dgraph::export::railml_string(&model, version).unwrap();
+        let (exported, _, _) = convert(&railml);
+        let (exported_sight, exported_tracks) = sight_and_tracks(&exported);
+        assert_eq!(exported_sight, sight);
+        assert!(tracks.iter().all(|t| exported_tracks.contains(t)), "{:?} {:?}", tracks, exported_tracks);
+    }
+}
+
+#[test]
+fn railml3_switch() {
+    let (infrastructure, route_text, diagnostics) = convert(RAILML3);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+    let (inf, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let routes = rolling::get_routes_string(&route_text, &names).unwrap();
+    assert_eq!(routes.len(), 10);
+    for &(from, to) in &[("b1", "sig1"), ("sig1", "sig2"), ("sig1", "sig3"), ("sig2", "b2"),
+                         ("b2", "sig4"), ("sig4", "sig6"), ("sig5", "sig6"), ("sig6", "b1")] {
+        route_name(&routes, &names, from, to);
+    }
+
+    // Routes between signals are taken from the interlocking, with their release
+    // groups and overlaps.
+    assert_eq!(route_name(&routes, &names, "sig1", "sig3"), "rt_sig1_sig3");
+    let route = &routes["rt_sig4_sig6"];
+    assert_eq!(route.resources.releases.len(), 1);
+    assert_eq!(route.resources.releases[0].length, 600.0);
+    assert_eq!(route.resources.releases[0].resources.len(), 3);
+    assert_eq!(route.overlaps.len(), 1);
+    assert_eq!(route.overlaps[0].timeout, Some(30.0));
+
+    // Drive through the branching leg of the switch, from b1 to b3.
+    let plan = format!("train t1 l=150.0 a=1.0 b=0.9 v=20.0 {}\nroute {}\nroute {}\n",
+                       route_name(&routes, &names, "b1", "sig1"),
+                       route_name(&routes, &names, "sig1", "sig3"),
+                       route_name(&routes, &names, "sig3", "b3"));
+    let dispatch = parse_dispatch(&plan).unwrap();
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+    let distance: f64 = history.trains[0].2.iter().map(|ev| match *ev {
+        TrainLogEvent::Move(_, _, update) => update.dx,
+        _ => 0.0,
+    }).sum();
+    assert!((distance - 1000.0 - 150.0).abs() < 1e-3, "distance {}", distance);
+}
+
+#[test]
+fn railml3_signal_without_direction() {
+    let railml = RAILML3.replace(r#"netElementRef="ne1" applicationDirection="reverse""#, r#"netElementRef="ne1""#);
+    let (infrastructure, route_text, diagnostics) = convert(&railml);
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0].element.ends_with(r#"signalIS[@id="sig6"]"#), "{}", diagnostics[0]);
+    assert!(!infrastructure.contains("signal sig6"));
+    // The interlocking routes to the missing signal have no path.
+    assert!(!route_text.contains("rt_sig4_sig6"));
+}
+
+/// The detection point `tde5` on `ne2` only detects trains running up. Trains
+/// running down pass the two sections on either side of it as one.
+#[test]
+fn railml3_directional_detector() {
+    let railml = RAILML3
+        .replace(r#"<spotLocation id="tde5_sl" netElementRef="ne2" pos="300.0"/>"#,
+                 r#"<spotLocation id="tde5_sl" netElementRef="ne2" applicationDirection="normal" pos="300.0"/>"#)
+        .replace(r#"applicationDirection="reverse" pos="100.0"/>
+          <isTrainMovementSignal type="main"/>
+        </signalIS>
+        <signalIS id="sig5""#, r#"applicationDirection="reverse" pos="100.0"/>
+          <isTrainMovementSignal type="distant"/>
+        </signalIS>
+        <signalIS id="sig5""#);
+    let (infrastructure, route_text, diagnostics) = convert(&railml);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+    let (inf, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let routes = rolling::get_routes_string(&route_text, &names).unwrap();
+    for path in &[&["b1", "sig1", "sig2", "b2"][..], &["b2", "sig6", "b1"][..]] {
+        let (entry, boundary) = (path[0], path[path.len() - 1]);
+        let plan = path.windows(2).enumerate().map(|(i, w)| {
+            let route = route_name(&routes, &names, w[0], w[1]);
+            if i == 0 { format!("train t1 l=150.0 a=1.0 b=0.9 v=20.0 {}\n", route) }
+            else { format!("route {}\n", route) }
+        }).collect::<String>();
+        let dispatch = parse_dispatch(&plan).unwrap();
+        let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+        let exited = history.trains[0].2.iter().any(|ev| match *ev {
+            TrainLogEvent::Edge(_, None) => true,
+            _ => false,
+        });
+        assert!(exited, "train from {} did not reach {}", entry, boundary);
+
+        // All sections are free after the train has left.
+        let mut occupied = std::collections::HashMap::new();
+        for ev in &history.inf {
+            if let InfrastructureLogEvent::Occupied(tvd, value, _, _) = *ev {
+                occupied.insert(tvd, value);
+            }
+        }
+        assert!(occupied.len() >= 4);
+        assert!(occupied.values().all(|&x| !x), "train from {}: {:?}", entry, occupied);
+    }
+
+    // On a track signalled in both directions, the detector detects both directions.
+    let railml = RAILML3.replace(r#"<spotLocation id="tde5_sl" netElementRef="ne2" pos="300.0"/>"#,
+                                 r#"<spotLocation id="tde5_sl" netElementRef="ne2" applicationDirection="normal" pos="300.0"/>"#);
+    let (infrastructure, _, diagnostics) = convert(&railml);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].element, "tde5");
+    let nodes = |inf: &str| {
+        let mut nodes = inf.lines().filter(|l| l.starts_with("node")).map(|l| l.to_string()).collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    };
+    assert_eq!(nodes(&infrastructure), nodes(&convert(RAILML3).0));
+}
+
+/// Track and station ids which are not identifiers in the D-graph format are
+/// written with `_` for the other characters.
+#[test]
+fn track_ids_as_identifiers() {
+    let (infrastructure, _, _) = convert(&RAILML3.replace("\"ne1\"", "\"ne-1\""));
+    let (_, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    assert!(names.node_positions.values().any(|p| p.track == "ne_1"));
+
+    let railml = RAILML2
+        .replace(r#"<track name="SP1">"#, r#"<track id="tr-1" name="SP1">"#)
+        .replace("<trainDetectionElements>", r#"<platformEdges>
+                    <platformEdge id="pe-1" pos="400.0" length="200.0" ocpRef="ocp-1"/>
+                </platformEdges>
+                <trainDetectionElements>"#);
+    let (infrastructure, _, _) = convert(&railml);
+    let (_, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let stop = &names.stop_locations["pe_1"];
+    assert_eq!(stop.track, "tr_1");
+    assert_eq!(stop.ocp, Some("ocp_1".to_string()));
+    assert_eq!(names.stop_nodes(stop).len(), 4);
+}
+
+#[test]
+fn railml3_invalid_number() {
+    let railml = RAILML3.replace(r#"<signalIS id="sig1" sight="150.0">"#, r#"<signalIS id="sig1" sight="far">"#);
+    let (doc, ns) = railml2dgraph::get_xml_string(&railml, false).unwrap();
+    let err = railml2dgraph::convert(&doc, &ns).err().unwrap();
+    assert!(err.ends_with(r#"signalsIS/signalIS[@id="sig1"]: attribute "sight" is not a number: "far""#), "{}", err);
+
+    let railml = RAILML3.replace(r#"id="b1_sl" netElementRef="ne1" intrinsicCoord="0""#,
+                                 r#"id="b1_sl" netElementRef="ne1" intrinsicCoord="begin""#);
+    let (doc, ns) = railml2dgraph::get_xml_string(&railml, false).unwrap();
+    let err = railml2dgraph::convert(&doc, &ns).err().unwrap();
+    assert!(err.ends_with(r#"border[@id="b1"]/spotLocation[@id="b1_sl"]: attribute "intrinsicCoord" is not a number: "begin""#), "{}", err);
+}
+
+/// Absolute positions of nodes are interpolated between the known absolute
+/// positions of the track, and absolute positions which are not numbers are ignored.
+#[test]
+fn absolute_position_interpolation() {
+    let railml = RAILML2
+        .replace(r#"<trackBegin pos="0.0">
+                    <openEnd id="b1" />"#, r#"<trackBegin pos="0.0" absPos="10000.0">
+                    <openEnd id="b1" />"#)
+        .replace(r#"<trackEnd pos="1000.0">
+                    <openEnd id="b2" />"#, r#"<trackEnd pos="1000.0" absPos="12000.0">
+                    <openEnd id="b2" />"#)
+        .replace(r#"pos="350.0" type="main""#, r#"pos="350.0" absPos="NaN" type="main""#);
+    let (infrastructure, _, _) = convert(&railml);
+    let (inf, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+
+    // The node of signal `sig2` at 250 m lies between the track begin and end.
+    let sig2 = names.object_names["sig2"];
+    let node = inf.nodes.iter().position(|n| n.objects.contains(&sig2)).unwrap();
+    let position = &names.node_positions[&node];
+    assert_eq!(position.pos, 250.0);
+    assert_eq!(position.abs_pos, Some(10500.0));
+    let node_name = names.node_names.iter().find(|&(_, &n)| n == node).unwrap().0;
+    assert!(infrastructure.lines().any(|l| l.contains(node_name.as_str()) &&
+                                         l.contains(&format!(" at {} 250 abs 10500", position.track))),
+            "{}", infrastructure);
+
+    let sp1 = names.node_positions.values().filter(|p| p.track == position.track).collect::<Vec<_>>();
+    assert!(sp1.len() > 2);
+    for p in sp1 {
+        assert_eq!(p.abs_pos, Some(10000.0 + 2.0 * p.pos), "{:?}", p);
+    }
+
+    // The JSON history gives the kilometrage of the node.
+    let history = History { inf: Vec::new(), trains: Vec::new() };
+    let mut json = Vec::new();
+    rolling::output::json::json_history(&inf, &names, &history, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains(&format!(r#""{}":{{"other_node":"#, node_name)));
+    assert!(json.contains(&format!(r#""track":"{}","km":10.5}}"#, position.track)), "{}", json);
+}
+
+/// Track `tr1` from `b1` to `b2` with crossing `x1` at 500 m. The crossing track
+/// runs from `b3` on `tr2` to `b4` on `tr3`. Signal `s1` on `tr1` and `s2` on `tr2`
+/// at the detectors 20 m before the crossing lead towards it.
+fn crossing_railml(kind: &str, in_course: &str, out_course: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<railml xmlns="http://www.railml.org/schemas/2013">
+  <infrastructure>
+    <tracks>
+      <track id="tr1" name="tr1">
+        <trackTopology>
+          <trackBegin pos="0.0"><openEnd id="b1"/></trackBegin>
+          <trackEnd pos="1000.0"><openEnd id="b2"/></trackEnd>
+          <connections>
+            <crossing id="x1" pos="500.0" type="{}">
+              <connection id="xc1" ref="t2e" orientation="incoming" {}/>
+              <connection id="xc2" ref="t3b" orientation="outgoing" {}/>
+            </crossing>
+          </connections>
+        </trackTopology>
+        <ocsElements>
+          <signals><signal id="s1" pos="480.0" dir="up" sight="200.0"/></signals>
+          <trainDetectionElements>
+            <trainDetector id="d1" pos="100.0"/>
+            <trainDetector id="d2" pos="480.0"/>
+            <trainDetector id="d3" pos="520.0"/>
+            <trainDetector id="d4" pos="900.0"/>
+          </trainDetectionElements>
+        </ocsElements>
+      </track>
+      <track id="tr2" name="tr2">
+        <trackTopology>
+          <trackBegin pos="0.0"><openEnd id="b3"/></trackBegin>
+          <trackEnd pos="500.0"><connection id="t2e" ref="xc1"/></trackEnd>
+        </trackTopology>
+        <ocsElements>
+          <signals><signal id="s2" pos="480.0" dir="up" sight="200.0"/></signals>
+          <trainDetectionElements>
+            <trainDetector id="d5" pos="100.0"/>
+            <trainDetector id="d6" pos="480.0"/>
+          </trainDetectionElements>
+        </ocsElements>
+      </track>
+      <track id="tr3" name="tr3">
+        <trackTopology>
+          <trackBegin pos="0.0"><connection id="t3b" ref="xc2"/></trackBegin>
+          <trackEnd pos="500.0"><openEnd id="b4"/></trackEnd>
+        </trackTopology>
+        <ocsElements>
+          <trainDetectionElements>
+            <trainDetector id="d7" pos="20.0"/>
+            <trainDetector id="d8" pos="400.0"/>
+          </trainDetectionElements>
+        </ocsElements>
+      </track>
+    </tracks>
+  </infrastructure>
+</railml>"#, kind, in_course, out_course)
+}
+
+/// Switches of a route with their positions, by name.
+fn route_switches(routes: &Routes<String>, names: &InfNames<String>, route: &str) -> Vec<(String, SwitchPosition)> {
+    let name = |id| names.object_names.iter().find(|&(_, &o)| o == id).unwrap().0.clone();
+    let mut switches = routes[route].resources.switch_positions.iter()
+        .map(|&(sw, pos)| (name(sw), pos)).collect::<Vec<_>>();
+    switches.sort_by(|a, b| a.0.cmp(&b.0));
+    switches
+}
+
+/// Both straight paths through a diamond crossing are in the same detection
+/// section, so that trains over the crossing on the two tracks are kept apart.
+#[test]
+fn crossing_diamond() {
+    let (infrastructure, route_text, _) = convert(&crossing_railml("simpleCrossing", "", ""));
+    let (inf, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let routes = rolling::get_routes_string(&route_text, &names).unwrap();
+
+    let straight = route_name(&routes, &names, "s1", "b2");
+    let crossing = route_name(&routes, &names, "s2", "b4");
+    assert!(route_switches(&routes, &names, &straight).is_empty());
+    assert!(route_switches(&routes, &names, &crossing).is_empty());
+    assert!(routes.iter().all(|(_, r)| r.resources.switch_positions.is_empty()));
+
+    // The routes over the crossing share exactly one section.
+    let shared = routes[&straight].resources.sections.iter()
+        .filter(|s| routes[&crossing].resources.sections.contains(s))
+        .cloned().collect::<Vec<_>>();
+    assert_eq!(shared.len(), 1, "{}", route_text);
+    let section = shared[0];
+
+    // Two trains reaching the crossing at the same time pass it one after the other.
+    let dispatch = parse_dispatch(&format!("train t1 l=100.0 a=1.0 b=1.0 v=20.0 {}
+route {}
+train t2 l=100.0 a=1.0 b=1.0 v=20.0 {}
+route {}",
+        route_name(&routes, &names, "b1", "s1"), straight,
+        route_name(&routes, &names, "b3", "s2"), crossing)).unwrap();
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+    for (name, _, events) in &history.trains {
+        let x: f64 = events.iter().map(|ev| match *ev { TrainLogEvent::Move(_, _, d) => d.dx, _ => 0.0 }).sum();
+        assert!((x - 1100.0).abs() < 1e-3, "{} stopped at {} m", name, x);
+    }
+    let occupations = history.inf.iter().filter_map(|ev| match *ev {
+        InfrastructureLogEvent::Occupied(s, occupied, _, train) if s == section => Some((occupied, train)),
+        _ => None,
+    }).collect::<Vec<_>>();
+    assert_eq!(occupations, vec![(true, 0), (false, 0), (true, 1), (false, 1)]);
+}
+
+/// A single slip has a curve from one track to the other, with a slip switch
+/// at each end of the curve.
+#[test]
+fn crossing_single_slip() {
+    let (infrastructure, route_text, _) = convert(&crossing_railml("simpleSwitchCrossing", "", r#"course="left""#));
+    let (_, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let routes = rolling::get_routes_string(&route_text, &names).unwrap();
+
+    let straight = route_name(&routes, &names, "s1", "b2");
+    let slip = route_name(&routes, &names, "s1", "b4");
+    let crossing = route_name(&routes, &names, "s2", "b4");
+    assert_eq!(route_switches(&routes, &names, &straight), vec![("x1_a".to_string(), SwitchPosition::Right)]);
+    assert_eq!(route_switches(&routes, &names, &slip),
+               vec![("x1_a".to_string(), SwitchPosition::Left), ("x1_d".to_string(), SwitchPosition::Right)]);
+    assert_eq!(route_switches(&routes, &names, &crossing), vec![("x1_d".to_string(), SwitchPosition::Left)]);
+    assert!(routes.iter().all(|(_, r)| !(r.entry == routes[&crossing].entry && r.exit == routes[&straight].exit)));
+
+    // All paths through the slip are in the same section.
+    let section = |r: &str| routes[r].resources.sections.iter().cloned().collect::<Vec<_>>();
+    let shared = section(&straight).into_iter()
+        .filter(|s| section(&slip).contains(s) && section(&crossing).contains(s))
+        .collect::<Vec<_>>();
+    assert_eq!(shared.len(), 1, "{}", route_text);
+}
+
+/// A double slip has curves between both pairs of crossing tracks, and a slip
+/// switch at each of the four ends.
+#[test]
+fn crossing_double_slip() {
+    let (infrastructure, route_text, _) = convert(&crossing_railml("doubleSwitchCrossing", r#"course="left""#, r#"course="left""#));
+    let (_, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let routes = rolling::get_routes_string(&route_text, &names).unwrap();
+
+    let paths = [("s1", "b2"), ("s1", "b4"), ("s2", "b4"), ("s2", "b2")];
+    let routes_through = paths.iter().map(|&(from, to)| route_name(&routes, &names, from, to)).collect::<Vec<_>>();
+    let mut switches = routes_through.iter()
+        .flat_map(|r| route_switches(&routes, &names, r).into_iter().map(|(sw, _)| sw))
+        .collect::<Vec<_>>();
+    switches.sort();
+    switches.dedup();
+    assert_eq!(switches, vec!["x1_a", "x1_b", "x1_c", "x1_d"]);
+    for r in &routes_through {
+        assert_eq!(route_switches(&routes, &names, r).len(), 2, "{}", r);
+    }
+
+    let section = |r: &str| routes[r].resources.sections.iter().cloned().collect::<Vec<_>>();
+    let shared = section(&routes_through[0]).into_iter()
+        .filter(|s| routes_through[1..].iter().all(|r| section(r).contains(s)))
+        .collect::<Vec<_>>();
+    assert_eq!(shared.len(), 1, "{}", route_text);
+}
+
+/// Track `tr1` from `b1` to `b2` with signals `s1` at 100 m and `s2` at 400 m, and
+/// a facing switch `sw` at 500 m leading left to `b3` on `tr2`.
+const OVERLAP_RAILML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<railml xmlns="http://www.railml.org/schemas/2013">
+  <infrastructure>
+    <tracks>
+      <track id="tr1" name="tr1">
+        <trackTopology>
+          <trackBegin pos="0.0"><openEnd id="b1"/></trackBegin>
+          <trackEnd pos="1000.0"><openEnd id="b2"/></trackEnd>
+          <connections>
+            <switch id="sw" pos="500.0" dir="up">
+              <connection id="swc" ref="t2b" course="left" orientation="outgoing"/>
+            </switch>
+          </connections>
+        </trackTopology>
+        <ocsElements>
+          <signals>
+            <signal id="s1" pos="100.0" dir="up" sight="100.0"/>
+            <signal id="s2" pos="400.0" dir="up" sight="100.0"/>
+          </signals>
+          <trainDetectionElements>
+            <trainDetector id="d1" pos="100.0"/>
+            <trainDetector id="d2" pos="400.0"/>
+            <trainDetector id="d3" pos="450.0"/>
+            <trainDetector id="d4" pos="600.0"/>
+            <trainDetector id="d5" pos="800.0"/>
+          </trainDetectionElements>
+        </ocsElements>
+      </track>
+      <track id="tr2" name="tr2">
+        <trackTopology>
+          <trackBegin pos="0.0"><connection id="t2b" ref="swc"/></trackBegin>
+          <trackEnd pos="500.0"><openEnd id="b3"/></trackEnd>
+        </trackTopology>
+        <ocsElements>
+          <trainDetectionElements>
+            <trainDetector id="d6" pos="200.0"/>
+            <trainDetector id="d7" pos="400.0"/>
+          </trainDetectionElements>
+        </ocsElements>
+      </track>
+    </tracks>
+  </infrastructure>
+</railml>"#;
+
+/// Overlaps of the converted routes with the given overlap length, as the entry
+/// and exit signal (or `None` for boundaries) and the number of sections and the
+/// switches of each overlap.
+fn converted_overlaps(length: f64) -> Vec<(Option<String>, Option<String>, Vec<(usize, Vec<(String, Side)>)>)> {
+    let (doc, ns) = railml2dgraph::get_xml_string(OVERLAP_RAILML, false).unwrap();
+    let (model, _, _) = railml2dgraph::convert(&doc, &ns).unwrap();
+    let opts = OverlapOptions { length, timeout: Some(30.0) };
+    let (routes, _) = railml2dgraph::convert_routes(&doc, &ns, &model, Some(&opts)).unwrap();
+    let signal = |b: &RouteBoundary| match *b {
+        RouteBoundary::Signal(ref s) => Some(s.clone()),
+        RouteBoundary::ModelBoundary(_) => None,
+    };
+    let mut overlaps = routes.iter().map(|r| {
+        assert!(r.overlaps.iter().all(|o| o.timeout == Some(30.0)));
+        let mut overlaps = r.overlaps.iter().map(|o| (o.sections.len(), o.switches.clone())).collect::<Vec<_>>();
+        overlaps.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+        (signal(&r.entry), signal(&r.exit), overlaps)
+    }).collect::<Vec<_>>();
+    overlaps.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+    overlaps
+}
+
+/// Overlaps are added to routes between signals only. They end at the overlap
+/// length, and branch into one overlap for each position of a facing switch.
+#[test]
+fn derived_overlaps() {
+    let s = |x: &str| Some(x.to_string());
+    let sw = |side| vec![("sw".to_string(), side)];
+
+    // Within 40 m of `s2`, only the section beginning at the signal is entered.
+    let overlaps = converted_overlaps(40.0);
+    assert_eq!(overlaps.iter().filter(|o| !o.2.is_empty()).count(), 1, "{:?}", overlaps);
+    assert!(overlaps.contains(&(s("s1"), s("s2"), vec![(1, vec![])])), "{:?}", overlaps);
+
+    // Within 150 m, the switch is passed in both positions, but no section beyond it is entered.
+    let overlaps = converted_overlaps(150.0);
+    assert!(overlaps.contains(&(s("s1"), s("s2"), vec![(2, sw(Side::Left)), (2, sw(Side::Right))])),
+            "{:?}", overlaps);
+
+    // Within 250 m, the section after the switch on the straight track is also entered.
+    let overlaps = converted_overlaps(250.0);
+    assert!(overlaps.contains(&(s("s1"), s("s2"), vec![(2, sw(Side::Left)), (3, sw(Side::Right))])),
+            "{:?}", overlaps);
+
+    // Routes from and to the model boundaries get no overlaps.
+    assert!(overlaps.iter().filter(|o| o.0.is_none() || o.1.is_none()).all(|o| o.2.is_empty()));
+    assert!(overlaps.iter().any(|o| o.0.is_none() && o.1 == s("s1")));
+    assert!(overlaps.iter().any(|o| o.0 == s("s2") && o.1.is_none()));
+}
+
+/// Speed changes apply in their own direction only, and gradient changes give
+/// the gradient of the edges up to the next gradient change.
+#[test]
+fn speed_and_gradient_changes() {
+    let railml = OVERLAP_RAILML.replacen("</trackTopology>", r#"</trackTopology>
+        <trackElements>
+          <speedChanges>
+            <speedChange id="sc1" pos="50.0" dir="up" vMax="36"/>
+            <speedChange id="sc2" pos="50.0" dir="down" vMax="18"/>
+          </speedChanges>
+          <gradientChanges>
+            <gradientChange id="g1" pos="600.0" slope="5.0" dir="down"/>
+          </gradientChanges>
+        </trackElements>"#, 1);
+    let (infrastructure, route_text, _) = convert(&railml);
+    let (inf, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let routes = rolling::get_routes_string(&route_text, &names).unwrap();
+
+    let limits = inf.objects.iter().filter_map(|o| match *o {
+        StaticObject::SpeedLimit { max_velocity } => Some(max_velocity),
+        _ => None,
+    }).collect::<Vec<_>>();
+    assert_eq!(limits.len(), 2);
+    assert!(limits.iter().any(|&v| (v - 10.0).abs() < 1e-9) && limits.iter().any(|&v| (v - 5.0).abs() < 1e-9));
+
+    // The gradient is downhill from 600 m in the direction of increasing positions,
+    // and uphill in the other direction.
+    let gradients = |from: f64, to: f64| names.node_positions.iter()
+        .filter(|&(_, p)| p.track == "tr1" && p.pos >= from && p.pos <= to)
+        .map(|(&n, _)| inf.nodes[n].gradient).filter(|&g| g != 0.0).collect::<Vec<_>>();
+    assert!(gradients(0.0, 599.0).is_empty());
+    assert_eq!(gradients(600.0, 600.0), vec![-5.0]);
+    let after = gradients(601.0, 1000.0);
+    assert!(after.contains(&5.0) && after.contains(&-5.0) && after.iter().all(|g| g.abs() == 5.0), "{:?}", after);
+
+    // A train running up the track keeps to the speed limit for that direction.
+    let dispatch = parse_dispatch(&format!("train t1 l=100.0 a=1.0 b=1.0 v=30.0 {}\nroute {}\nroute {}",
+        route_name(&routes, &names, "b1", "s1"), route_name(&routes, &names, "s1", "s2"),
+        route_name(&routes, &names, "s2", "b2"))).unwrap();
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+    let velocities = history.trains[0].2.iter().filter_map(|ev| match *ev {
+        TrainLogEvent::Move(_, _, d) => Some(d.v),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let max = velocities.iter().cloned().fold(0.0, f64::max);
+    assert!((max - 10.0).abs() < 1e-6, "maximum velocity {}", max);
+}
+
+const PLATFORM_RAILML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<railml xmlns="http://www.railml.org/schemas/2013">
+  <infrastructure>
+    <tracks>
+      <track id="tr1" name="tr1">
+        <trackTopology>
+          <trackBegin pos="0.0"><openEnd id="b1"/></trackBegin>
+          <trackEnd pos="1000.0"><openEnd id="b2"/></trackEnd>
+        </trackTopology>
+        <ocsElements>
+          <platformEdges>
+            <platformEdge id="pe1" name="2" ocpRef="ocp1" pos="100.0" length="200.0"/>
+            <platformEdge id="pe2" name="2" ocpRef="ocp1" pos="500.0" length="150.0"/>
+            <platformEdge id="pe3" pos="900.0" length="300.0"/>
+            <platformEdge id="pe4" name="4" ocpRef="ocp1" pos="700.0"/>
+          </platformEdges>
+        </ocsElements>
+      </track>
+    </tracks>
+    <operationControlPoints>
+      <ocp id="ocp1" name="Kolbotn"/>
+    </operationControlPoints>
+  </infrastructure>
+</railml>"#;
+
+/// Platform edges become stop locations named after the station and platform,
+/// or by their id when the name is taken or incomplete.
+#[test]
+fn platform_stop_locations() {
+    let (infrastructure, _, diagnostics) = convert(PLATFORM_RAILML);
+    let (_, names) = rolling::get_infrastructure_string(&infrastructure).unwrap();
+    let stop = |track: &str, pos: f64, length: f64, ocp: Option<&str>| StopLocation {
+        track: track.to_string(), pos, length, ocp: ocp.map(|o| o.to_string()),
+    };
+
+    assert_eq!(names.stop_locations.len(), 3, "{:?}", names.stop_locations);
+    assert_eq!(names.stop_locations["Kolbotn_2"], stop("tr1", 100.0, 200.0, Some("ocp1")));
+    assert_eq!(names.stop_locations["pe2"], stop("tr1", 500.0, 150.0, Some("ocp1")));
+    // The platform edge is cut off at the end of the track.
+    assert_eq!(names.stop_locations["pe3"], stop("tr1", 900.0, 100.0, None));
+
+    // Both sides of the nodes at the ends of the platform.
+    let nodes = names.stop_nodes(&names.stop_locations["Kolbotn_2"]);
+    let positions = nodes.iter().map(|n| names.node_positions[n].pos).collect::<Vec<_>>();
+    assert_eq!(nodes.len(), 4, "{:?}", positions);
+    assert!(positions.iter().all(|&p| p == 100.0 || p == 300.0), "{:?}", positions);
+
+    let messages = diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+    assert!(messages.iter().any(|m| m.contains("platformEdge") && m.contains("\"Kolbotn_2\" already exists")),
+            "{:?}", messages);
+    assert!(messages.iter().any(|m| m.contains("no length for platform edge")), "{:?}", messages);
+}
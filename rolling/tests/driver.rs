@@ -0,0 +1,199 @@
+extern crate rolling;
+
+use rolling::input::dispatch::parse_dispatch;
+use rolling::input::staticinfrastructure::{InfNames, SwitchPosition};
+use rolling::output::history::{History, InfrastructureLogEvent, TrainLogEvent};
+use rolling::railway::SignallingMode;
+use rolling::railway::dynamics::DriverAction;
+
+const LINE_ROUTES: &str = "
+    modelentry ri from b1 {
+      exit s1
+      length 2000.0
+      sections [] switches [] contains []
+    }
+    modelexit rx to b2 {
+      entry s1
+      entrysection a1
+      length 1000.0
+      sections [a1]
+      switches [] contains []
+    }
+";
+
+/// Acceleration of the first move of a train starting from rest, with the given
+/// gradient in per mille on the line from `b1` to signal `s1`.
+fn starting_acceleration(gradient: f64) -> f64 {
+    let (inf, names) = rolling::get_infrastructure_string(&format!("
+        boundary b1
+        node b1-n1
+        linear n1-n2 2000.0 (gradient {})
+        node n2-n2u(enter a1, signal s1)
+        linear n2u-n3 100.0
+        node n3-b2(exit a1)
+        boundary b2
+    ", gradient)).unwrap();
+    let routes = rolling::get_routes_string(LINE_ROUTES, &names).unwrap();
+    let dispatch = parse_dispatch("train t1 l=100.0 a=1.0 b=1.0 v=30.0 ri\nroute rx").unwrap();
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+    history.trains[0].2.iter().filter_map(|ev| match *ev {
+        TrainLogEvent::Move(dt, DriverAction::Accel, d) if dt > 0.0 => Some(d.v / dt),
+        _ => None,
+    }).next().unwrap()
+}
+
+/// Acceleration is reduced uphill and increased downhill by the component of
+/// gravity along the track, but kept above a tenth of the train's acceleration.
+#[test]
+fn gradient_acceleration() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    assert!(close(starting_acceleration(0.0), 1.0));
+    assert!(close(starting_acceleration(20.0), 1.0 - 9.81 * 0.02));
+    assert!(close(starting_acceleration(-20.0), 1.0 + 9.81 * 0.02));
+    assert!(close(starting_acceleration(200.0), 0.1));
+}
+
+/// Moves of a train with the given driver profile on a 2000 m line to signal `s1`,
+/// which is never cleared, as (duration, action, distance, velocity).
+fn line_moves(driver: &str) -> Vec<(f64, DriverAction, f64, f64)> {
+    let (inf, names) = rolling::get_infrastructure_string("
+        boundary b1
+        node b1-n1
+        linear n1-n2 2000.0
+        node n2-n2u(enter a1, signal s1)
+        linear n2u-n3 100.0
+        node n3-b2(exit a1)
+        boundary b2
+    ").unwrap();
+    let routes = rolling::get_routes_string(LINE_ROUTES, &names).unwrap();
+    let dispatch = parse_dispatch(&format!("driver d {}\ntrain t1 l=100.0 a=1.0 b=1.0 v=30.0 driver=d ri",
+                                           driver)).unwrap();
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+    history.trains[0].2.iter().filter_map(|ev| match *ev {
+        TrainLogEvent::Move(dt, action, d) => Some((dt, action, d.dx, d.v)),
+        _ => None,
+    }).collect()
+}
+
+/// The train stops the safety margin before the red signal, and coasts for the
+/// reaction time before braking.
+#[test]
+fn driver_reaction_time() {
+    let moves = line_moves("margin=50.0 reaction=2.0");
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+    assert!(close(moves.iter().map(|m| m.2).sum::<f64>(), 1950.0), "{:?}", moves);
+    let n = moves.len();
+    let (dt, action, dx, v) = moves[n - 2];
+    assert!(action as usize == DriverAction::Coast as usize && close(dt, 2.0) && close(dx, 60.0) && close(v, 30.0),
+            "{:?}", moves);
+    // Braking from 30 m/s at 1 m/s^2 takes 450 m.
+    let (dt, action, dx, v) = moves[n - 1];
+    assert!(action as usize == DriverAction::Brake as usize && close(dt, 30.0) && close(dx, 450.0) && close(v, 0.0),
+            "{:?}", moves);
+
+    // Without a reaction time, the train brakes right after coasting at the line speed,
+    // and stops closer to the signal.
+    let moves = line_moves("margin=20.0");
+    assert!(close(moves.iter().map(|m| m.2).sum::<f64>(), 1980.0), "{:?}", moves);
+    let coast = moves.iter().filter(|m| m.1 as usize == DriverAction::Coast as usize).collect::<Vec<_>>();
+    assert_eq!(coast.len(), 1, "{:?}", moves);
+}
+
+/// The train stops accelerating at half of the train's maximum velocity.
+#[test]
+fn driver_coasting() {
+    let max_velocity = |moves: &[(f64, DriverAction, f64, f64)]| moves.iter().map(|m| m.3).fold(0.0, f64::max);
+    let moves = line_moves("coast=0.5");
+    assert!((max_velocity(&moves) - 15.0).abs() < 1e-6, "{:?}", moves);
+    let (_, action, dx, v) = moves[moves.len() - 2];
+    assert!(action as usize == DriverAction::Coast as usize && dx > 1000.0 && (v - 15.0).abs() < 1e-6,
+            "{:?}", moves);
+    assert!((max_velocity(&line_moves("coast=never")) - 30.0).abs() < 1e-6);
+}
+
+/// Simulate a train from `b1` past signal `s1` and over switch `sw`, which has the
+/// given properties, on the route `rleft` or `rright`.
+fn switch_run(properties: &str, route: &str) -> (History, InfNames<String>) {
+    let (inf, names) = rolling::get_infrastructure_string(&format!("
+        boundary b1
+        node b1-n1(sight s1 1000.0)
+        linear n1-n2 1000.0
+        node n2-n3(signal s1, enter a1)
+        switch sw left n3-(n4 200.0, n5 200.0) {}
+        node n4-b2(exit a1)
+        node n5-b3(exit a1)
+        boundary b2
+        boundary b3
+    ", properties)).unwrap();
+    let routes = rolling::get_routes_string("
+        modelentry ri from b1 {
+          exit s1
+          length 1000.0
+          sections [] switches [] contains []
+        }
+        modelexit rleft to b2 {
+          entry s1
+          entrysection a1
+          length 1000.0
+          sections [a1]
+          switches [sw left] contains []
+        }
+        modelexit rright to b3 {
+          entry s1
+          entrysection a1
+          length 1000.0
+          sections [a1]
+          switches [sw right] contains []
+        }
+    ", &names).unwrap();
+    let dispatch = parse_dispatch(&format!("train t1 l=50.0 a=1.0 b=1.0 v=20.0 ri\nroute {}", route)).unwrap();
+    (rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside), names)
+}
+
+/// Time when the switch was thrown, and the train's velocity when entering the
+/// switch and its maximum velocity from there on.
+fn switch_passing(properties: &str, route: &str) -> (f64, f64, f64) {
+    let (history, names) = switch_run(properties, route);
+    let mut t = 0.0;
+    let mut thrown = None;
+    for ev in &history.inf {
+        match *ev {
+            InfrastructureLogEvent::Wait(dt) => t += dt,
+            InfrastructureLogEvent::Position(sw, pos) => {
+                assert_eq!(sw, names.object_names["sw"]);
+                assert_eq!(pos, if route == "rleft" { SwitchPosition::Left } else { SwitchPosition::Right });
+                thrown = Some(t);
+            },
+            _ => {},
+        }
+    }
+    let mut v = 0.0;
+    let mut entry = None;
+    let mut max = 0.0;
+    for ev in &history.trains[0].2 {
+        match *ev {
+            TrainLogEvent::Edge(n, _) if n == names.node_names["n3"] => entry = Some(v),
+            TrainLogEvent::Move(_, _, d) => {
+                v = d.v;
+                if entry.is_some() { max = f64::max(max, v); }
+            },
+            _ => {},
+        }
+    }
+    (thrown.unwrap(), entry.unwrap(), max)
+}
+
+#[test]
+fn switch_throw_time_and_branch_speed() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+    // The train slows down to pass the branch side, and keeps the speed until
+    // its rear has left the switch.
+    let (thrown, entry, max) = switch_passing("(throw 8.0, speed 5.0)", "rleft");
+    assert!(close(thrown, 8.0) && close(entry, 5.0) && close(max, 5.0), "{:?}", (thrown, entry, max));
+    // The straight side is not restricted.
+    let (thrown, entry, max) = switch_passing("(throw 8.0, speed 5.0)", "rright");
+    assert!(close(thrown, 8.0) && close(entry, 20.0) && close(max, 20.0), "{:?}", (thrown, entry, max));
+    // By default, switches take 5 s to throw and the branch side is not restricted.
+    let (thrown, entry, max) = switch_passing("", "rleft");
+    assert!(close(thrown, 5.0) && close(entry, 20.0) && close(max, 20.0), "{:?}", (thrown, entry, max));
+}
@@ -0,0 +1,448 @@
+extern crate rolling;
+extern crate serde_json;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use rolling::output::diff::{diff, diff_report, Variant};
+use rolling::output::energy::{energy, EnergyParams};
+use rolling::output::history::{History, HistoryLogger, InfrastructureLogEvent, TrainLogEvent};
+use rolling::output::json::{json_history, JsonLinesLogger, SCHEMA_VERSION};
+use rolling::input::timetable::parse_timetable;
+use rolling::output::punctuality::{punctuality, punctuality_report};
+use rolling::output::report::{report, report_table};
+use rolling::output::profile::{profile_csv, profile_json, train_profile};
+use rolling::output::timedistance::{path_from_names, time_distance_svg};
+use rolling::railway::dynamics::{DistanceVelocity, DriverAction, TrainParams};
+
+const PARAMS: TrainParams = TrainParams { length: 100.0, max_acc: 1.0, max_brk: 1.0, max_vel: 10.0 };
+
+fn mv(dt: f64, action: DriverAction, dx: f64, v: f64) -> TrainLogEvent {
+    TrainLogEvent::Move(dt, action, DistanceVelocity { dx, v })
+}
+
+/// A train of 1000 kg accelerates to 10 m/s over 50 m, runs 100 m at 10 m/s and
+/// brakes to a stop over 50 m, all on a 10 per mille uphill gradient.
+#[test]
+fn energy_on_gradient() {
+    let (inf, names) = rolling::get_infrastructure_string("
+        boundary b1
+        node b1-n1
+        linear n1-n2 1000.0 (gradient 10.0)
+        node n2-b2
+        boundary b2
+    ").unwrap();
+    let n1 = names.node_names["n1"];
+    let n2 = names.node_names["n2"];
+    let history = History {
+        inf: Vec::new(),
+        trains: vec![("t1".to_string(), PARAMS, vec![
+            TrainLogEvent::Edge(n1, Some(n2)),
+            mv(10.0, DriverAction::Accel, 50.0, 10.0),
+            mv(10.0, DriverAction::Coast, 100.0, 10.0),
+            mv(10.0, DriverAction::Brake, 50.0, 0.0),
+        ])],
+    };
+    let params = EnergyParams { mass: 1000.0, efficiency: 0.5, regeneration: 0.5 };
+    let report = energy(&inf, &history, &params).unwrap();
+    let t = &report.trains[0];
+
+    // Kinetic energy 0.5 * 1000 kg * (10 m/s)^2 = 50000 J, and potential energy
+    // 1000 kg * 9.81 m/s^2 * 0.01 = 98.1 J per meter.
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+    assert!(close(t.traction, 50000.0 + 150.0 * 98.1), "{:?}", t);
+    assert!(close(t.braking, 50000.0 - 50.0 * 98.1), "{:?}", t);
+    assert!(close(t.consumed, t.traction / 0.5), "{:?}", t);
+    assert!(close(t.regenerated, t.braking * 0.5 * 0.5), "{:?}", t);
+    assert!(close(t.net(), 129430.0 - 11273.75), "{:?}", t);
+
+    // Going downhill, the train brakes to keep its speed.
+    let downhill = History {
+        inf: Vec::new(),
+        trains: vec![("t1".to_string(), PARAMS, vec![
+            TrainLogEvent::Edge(n2, Some(n1)),
+            mv(10.0, DriverAction::Coast, 100.0, 0.0),
+        ])],
+    };
+    let t = &energy(&inf, &downhill, &params).unwrap().trains[0];
+    assert!(close(t.traction, 0.0) && close(t.braking, 100.0 * 98.1), "{:?}", t);
+}
+
+#[test]
+fn energy_params() {
+    let (inf, _) = rolling::get_infrastructure_string("boundary b1\nnode b1-b2\nboundary b2").unwrap();
+    let history = History { inf: Vec::new(), trains: vec![("t1".to_string(), PARAMS, Vec::new())] };
+    for &(efficiency, regeneration) in &[(0.0, 0.0), (1.5, 0.0), (0.9, -0.1), (0.9, 1.1)] {
+        let params = EnergyParams { mass: 1000.0, efficiency, regeneration };
+        assert!(energy(&inf, &history, &params).is_err(), "{:?}", params);
+    }
+    let params = EnergyParams { mass: 1000.0, efficiency: 1.0, regeneration: 1.0 };
+    assert!(energy(&inf, &history, &params).is_ok());
+}
+
+/// A train accelerates for 10 s over 50 m and coasts for 10 s over 100 m on a 1000 m
+/// path, drawn on an 800 x 1000 plot with a 60 px margin.
+#[test]
+fn time_distance_lines() {
+    let (inf, names) = rolling::get_infrastructure_string("
+        boundary b1
+        node b1-n1
+        linear n1-n2 1000.0
+        node n2-b2
+        boundary b2
+    ").unwrap();
+    let path = path_from_names(&inf, &names, &["n1".to_string(), "n2".to_string()]).unwrap();
+    assert_eq!(path, vec![names.node_names["n1"], names.node_names["n2"]]);
+    assert!(path_from_names(&inf, &names, &["n1".to_string(), "x".to_string()]).is_err());
+
+    let history = History {
+        inf: Vec::new(),
+        trains: vec![("<t1 & t2>".to_string(), PARAMS, vec![
+            TrainLogEvent::Edge(names.node_names["n1"], Some(names.node_names["n2"])),
+            mv(10.0, DriverAction::Accel, 50.0, 10.0),
+            mv(10.0, DriverAction::Coast, 100.0, 10.0),
+        ])],
+    };
+    let svg = time_distance_svg(&inf, &names, &history, &path).unwrap();
+    let lines = svg.lines().filter(|l| l.starts_with("<polyline")).collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2, "{}", svg);
+
+    // Accelerating from the start of the path at 0 s to 50 m at 10 s of 20 s.
+    assert!(lines[0].starts_with("<polyline points=\"60.00,60.00 "), "{}", lines[0]);
+    assert!(lines[0].contains(" 100.00,560.00\" fill=\"none\" stroke=\"#2ca02c\""), "{}", lines[0]);
+    // Coasting on to 150 m at 20 s.
+    assert!(lines[1].starts_with("<polyline points=\"100.00,560.00 "), "{}", lines[1]);
+    assert!(lines[1].contains(" 180.00,1060.00\" fill=\"none\" stroke=\"#1f77b4\""), "{}", lines[1]);
+
+    assert!(lines[0].contains("<title>&lt;t1 &amp; t2&gt;</title>"), "{}", lines[0]);
+    assert!(!svg.contains("<t1"), "{}", svg);
+}
+
+const STOP_INFRASTRUCTURE: &str = "
+    boundary b1
+    node b1-n1(enter a1,sight sig 100.0)
+    linear n1-n2 100.0
+    node n2-n3(signal sig, enter a2)
+    linear n3-n4 100.0
+    node n4-b2(exit a2)
+    boundary b2
+";
+
+#[test]
+fn json_unnamed_and_escaped() {
+    let (inf, mut names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let a1 = names.object_names.remove("a1").unwrap();
+    let a2 = names.object_names.remove("a2").unwrap();
+    let n1 = names.node_names.remove("n1").unwrap();
+    let n2 = names.node_names.remove("n2").unwrap();
+    let train = "say \"hi\"\n\\ <t1>";
+    let history = History {
+        inf: vec![
+            InfrastructureLogEvent::Wait(1.0),
+            InfrastructureLogEvent::Occupied(a1, true, names.node_names["b1"], 0),
+        ],
+        trains: vec![(train.to_string(), PARAMS, Vec::new())],
+    };
+    let mut out = Vec::new();
+    json_history(&inf, &names, &history, &mut out).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+    // Unnamed nodes and objects get their own keys.
+    let nodes = json["infrastructure"]["nodes"].as_object().unwrap();
+    assert_eq!(nodes.len(), inf.nodes.len());
+    assert_eq!(nodes["b1"]["other_node"], format!("node#{}", n1).as_str());
+    assert_eq!(nodes[&format!("node#{}", n2)]["other_node"], "n3");
+    let objects = json["infrastructure"]["objects"].as_object().unwrap();
+    assert_eq!(objects["sig"]["type"], "signal");
+    assert_eq!(objects[&format!("object#{}", a1)]["type"], "tvd");
+    assert_eq!(objects[&format!("object#{}", a2)]["type"], "tvd");
+    assert!(!objects.contains_key(""));
+
+    let events = json["infrastructure"]["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["time"], 1.0);
+    assert_eq!(events[0]["ref"], format!("object#{}", a1).as_str());
+    assert_eq!(events[0]["train"], train);
+    assert!(json["trains"].get(train).is_some(), "{}", json["trains"]);
+}
+
+/// Writer which can be read while a logger is writing to it.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl Shared {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        let s = String::from_utf8(self.0.borrow().clone()).unwrap();
+        assert!(s.is_empty() || s.ends_with('\n'));
+        s.lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+}
+
+#[test]
+fn json_lines_streaming() {
+    let (inf, names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let (n1, n2) = (names.node_names["n1"], names.node_names["n2"]);
+    let output = Shared::default();
+    let mut logger = JsonLinesLogger::new(&inf, &names, output.clone()).unwrap();
+
+    let lines = output.lines();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["version"], SCHEMA_VERSION);
+    assert_eq!(lines[0]["infrastructure"]["objects"]["a1"]["type"], "tvd");
+
+    logger.train_start("t1", &PARAMS);
+    let lines = output.lines();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["event"], "train_start");
+    assert_eq!(lines[1]["train"], "t1");
+    assert_eq!(lines[1]["params"]["length"], 100.0);
+
+    logger.time(2.0);
+    logger.infrastructure(InfrastructureLogEvent::Occupied(names.object_names["a1"], true, n1, 0));
+    let lines = output.lines();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[2]["time"], 2.0);
+    assert_eq!(lines[2]["event"], "occupied");
+    assert_eq!(lines[2]["ref"], "a1");
+    assert_eq!(lines[2]["value"], "true");
+    assert_eq!(lines[2]["train"], "t1");
+
+    // The first movement also writes the train's initial state.
+    logger.train(0, TrainLogEvent::Edge(n1, Some(n2)));
+    logger.train(0, TrainLogEvent::Wait(2.0));
+    assert_eq!(output.lines().len(), 3);
+    logger.train(0, mv(10.0, DriverAction::Accel, 50.0, 10.0));
+    let lines = output.lines();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[3]["event"], "train");
+    assert_eq!(lines[3]["time"], 2.0);
+    assert_eq!(lines[3]["x"], 0.0);
+    assert_eq!(lines[4]["train"], "t1");
+    assert_eq!(lines[4]["action"], "Accel");
+    assert_eq!(lines[4]["time"], 12.0);
+    assert_eq!(lines[4]["x"], 50.0);
+    assert_eq!(lines[4]["v"], 10.0);
+    assert_eq!(lines[4]["edges"], serde_json::json!([{ "n1": "n1", "n2": "n2", "start": 0.0, "end": 50.0 }]));
+
+    logger.finish().unwrap();
+    assert_eq!(output.lines().len(), 5);
+}
+
+/// A train waits 5 s, accelerates at 1 m/s^2 for 10 s and brakes at 2 m/s^2 to a stop.
+#[test]
+fn velocity_profile() {
+    let events = vec![
+        TrainLogEvent::Wait(5.0),
+        mv(10.0, DriverAction::Accel, 50.0, 10.0),
+        mv(5.0, DriverAction::Brake, 25.0, 0.0),
+    ];
+    let samples = train_profile(&events, 2.0);
+    let expected = [
+        (5.0, 0.0, 0.0, 1.0), (7.0, 2.0, 2.0, 1.0), (9.0, 8.0, 4.0, 1.0),
+        (11.0, 18.0, 6.0, 1.0), (13.0, 32.0, 8.0, 1.0),
+        (15.0, 50.0, 10.0, -2.0), (17.0, 66.0, 6.0, -2.0), (19.0, 74.0, 2.0, -2.0),
+        (20.0, 75.0, 0.0, 0.0),
+    ];
+    assert_eq!(samples.len(), expected.len(), "{:?}", samples);
+    for (s, &(time, position, velocity, acceleration)) in samples.iter().zip(expected.iter()) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(s.time, time) && close(s.position, position) && close(s.velocity, velocity)
+                && close(s.acceleration, acceleration), "{:?}", s);
+    }
+    assert_eq!(samples[4].action as usize, DriverAction::Accel as usize);
+    assert_eq!(samples[8].action as usize, DriverAction::Brake as usize);
+
+    let history = History {
+        inf: Vec::new(),
+        trains: vec![("t2".to_string(), PARAMS, events.clone()),
+                     ("t1".to_string(), PARAMS, vec![mv(1.0, DriverAction::Coast, 5.0, 5.0)])],
+    };
+    let mut csv = Vec::new();
+    profile_csv(&history, 2.0, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1 + 9 + 2);
+    assert_eq!(lines[0], "train,time,position,velocity,acceleration,action");
+    assert_eq!(lines[2], "t2,7,2,2,1,accel");
+    assert_eq!(lines[9], "t2,20,75,0,0,brake");
+    assert_eq!(lines[11], "t1,1,5,5,0,coast");
+
+    // Trains are listed in the order they were dispatched.
+    let mut json = Vec::new();
+    profile_json(&history, 2.0, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.find("\"t2\"").unwrap() < json.find("\"t1\"").unwrap(), "{}", json);
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["t2"][6], serde_json::json!({
+        "time": 17.0, "position": 66.0, "velocity": 6.0, "acceleration": -2.0, "action": "brake" }));
+
+    assert!(profile_csv(&history, 0.0, &mut Vec::new()).is_err());
+}
+
+/// In the second variant, train t1 accelerates for 2 s longer and occupies
+/// section a1 for 2 s longer, and train t2 is added.
+#[test]
+fn history_diff() {
+    let (inf, names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let node = |n: &str| names.node_names[n];
+    let a1 = names.object_names["a1"];
+    let run = |accel: f64| vec![
+        TrainLogEvent::Node(node("n1")),
+        mv(accel, DriverAction::Accel, 50.0, 10.0),
+        TrainLogEvent::Node(node("n2")),
+        mv(10.0, DriverAction::Coast, 100.0, 10.0),
+        TrainLogEvent::Edge(node("n4"), None),
+        TrainLogEvent::Node(node("b2")),
+    ];
+    let occupation = |dt: f64| vec![
+        InfrastructureLogEvent::Occupied(a1, true, node("n1"), 0),
+        InfrastructureLogEvent::Wait(dt),
+        InfrastructureLogEvent::Occupied(a1, false, node("n2"), 0),
+    ];
+    let history_a = History {
+        inf: occupation(5.0),
+        trains: vec![("t1".to_string(), PARAMS, run(10.0))],
+    };
+    let history_b = History {
+        inf: occupation(7.0),
+        trains: vec![("t1".to_string(), PARAMS, run(12.0)),
+                     ("t2".to_string(), PARAMS, vec![TrainLogEvent::Wait(1.0),
+                                                     mv(1.0, DriverAction::Accel, 0.5, 1.0)])],
+    };
+    let d = diff(Variant { inf: &inf, names: &names, history: &history_a },
+                 Variant { inf: &inf, names: &names, history: &history_b });
+
+    assert_eq!(d.trains.len(), 2);
+    let t1 = &d.trains[0];
+    assert_eq!(t1.a.as_ref().unwrap().running_time, 20.0);
+    assert_eq!(t1.b.as_ref().unwrap().running_time, 22.0);
+    let visits = t1.visits.iter().map(|v| (v.node.as_str(), v.a, v.b, v.changed())).collect::<Vec<_>>();
+    assert_eq!(visits, vec![("n1", Some(0.0), Some(0.0), false),
+                            ("n2", Some(10.0), Some(12.0), true),
+                            ("b2", Some(20.0), Some(22.0), true)]);
+    assert!(d.trains[1].a.is_none() && d.trains[1].b.is_some());
+
+    assert_eq!(diff_report(&d).unwrap(), "\
+Trains:
+  t1: running time 20.0 s -> 22.0 s (+2.0 s), signal stops 0 -> 0
+    n2: 10.0 s -> 12.0 s (+2.0 s)
+    b2: 20.0 s -> 22.0 s (+2.0 s)
+  t2: only in second variant
+Sections occupied:
+  a1: 5.0 s -> 7.0 s (+2.0 s)
+");
+}
+
+/// A train enters after 2 s, stops at a signal, waits 3 s, and later brakes for a
+/// speed restriction before leaving the model.
+#[test]
+fn summary_report() {
+    let (inf, names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let (a1, a2) = (names.object_names["a1"], names.object_names["a2"]);
+    let n1 = names.node_names["n1"];
+    let history = History {
+        inf: vec![
+            InfrastructureLogEvent::Reserved(a2, true),
+            InfrastructureLogEvent::Wait(1.0),
+            InfrastructureLogEvent::Occupied(a1, true, n1, 0),
+            InfrastructureLogEvent::Wait(3.0),
+            InfrastructureLogEvent::Reserved(a2, false),
+            InfrastructureLogEvent::Wait(4.0),
+            InfrastructureLogEvent::Occupied(a1, false, n1, 0),
+            InfrastructureLogEvent::Occupied(a1, true, n1, 0),
+        ],
+        trains: vec![("t1".to_string(), PARAMS, vec![
+            TrainLogEvent::Wait(2.0),
+            mv(10.0, DriverAction::Accel, 50.0, 10.0),
+            mv(5.0, DriverAction::Brake, 25.0, 0.0),
+            mv(3.0, DriverAction::Coast, 0.0, 0.0),
+            mv(10.0, DriverAction::Accel, 50.0, 10.0),
+            mv(2.5, DriverAction::Brake, 18.75, 5.0),
+            mv(2.0, DriverAction::Coast, 10.0, 5.0),
+            TrainLogEvent::Edge(n1, None),
+            mv(1.0, DriverAction::Coast, 5.0, 5.0),
+        ])],
+    };
+    let r = report(&inf, &names, &history);
+    let t = &r.trains[0];
+    assert_eq!((t.entry, t.exit, t.running_time), (Some(2.0), Some(35.5), 33.5));
+    assert_eq!((t.distance, t.stopped_time), (158.75, 3.0));
+    // Only the braking to a stop is caused by the signal.
+    assert_eq!((t.signal_braking_time, t.signal_stops), (5.0, 1));
+
+    // The section still occupied at the end is counted until the end of the simulation.
+    assert_eq!(r.duration, 35.5);
+    let sections = r.sections.iter().map(|s| (s.name.as_str(), s.occupations, s.occupied_time, s.reserved_time))
+        .collect::<Vec<_>>();
+    assert_eq!(sections, vec![("a1", 2, 34.5, 0.0), ("a2", 0, 0.0, 4.0)]);
+    assert!(r.switches.is_empty());
+
+    assert_eq!(report_table(&r).unwrap(), "\
+Duration: 35.5 s
+
+train       entry     exit  running  distance  stopped  braking  stops
+t1            2.0     35.5     33.5     158.8      3.0      5.0      1
+
+section     occ occupied reserved   util
+a1            2     34.5      0.0  97.2%
+a2            0      0.0      4.0   0.0%
+");
+}
+
+/// Train t1 passes n1 on time, stops before signal sig 7 s after its planned arrival,
+/// departs 2 s late and reaches b2 on time. Train t2 is not simulated.
+#[test]
+fn punctuality_delays() {
+    let (inf, names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let node = |n: &str| names.node_names[n];
+    let tt = parse_timetable("
+        train t1 at 0.0 l=100.0 a=1.0 b=1.0 v=10.0 routes ri re
+        arrive t1 n1 0.0
+        arrive t1 sig 8.0
+        depart t1 sig 20.0
+        arrive t1 b2 31.0
+        train t2 at 10.0 l=100.0 a=1.0 b=1.0 v=10.0 routes ri re
+        arrive t2 b2 50.0
+    ").unwrap();
+    let history = History {
+        inf: Vec::new(),
+        trains: vec![("t1".to_string(), PARAMS, vec![
+            TrainLogEvent::Node(node("n1")),
+            mv(10.0, DriverAction::Accel, 50.0, 10.0),
+            mv(5.0, DriverAction::Brake, 25.0, 0.0),
+            mv(7.0, DriverAction::Coast, 0.0, 0.0),
+            TrainLogEvent::Node(node("n2")),
+            TrainLogEvent::Node(node("n3")),
+            mv(8.0, DriverAction::Accel, 32.0, 8.0),
+            TrainLogEvent::Node(node("b2")),
+        ])],
+    };
+    let p = punctuality(&tt, &inf, &names, &history, 5.0).unwrap();
+    let t1 = p.trains[0].points.iter()
+        .map(|x| (x.point.location.as_str(), x.arrival, x.arrival_delay(), x.departure, x.departure_delay()))
+        .collect::<Vec<_>>();
+    assert_eq!(t1, vec![
+        ("n1", Some(0.0), Some(0.0), None, None),
+        ("sig", Some(15.0), Some(7.0), Some(22.0), Some(2.0)),
+        ("b2", Some(30.0), Some(-1.0), None, None),
+    ]);
+    assert_eq!(p.trains[0].points[1].delay(), Some(7.0));
+    assert_eq!(p.trains[1].points[0].arrival, None);
+    assert_eq!(p.on_time(), (4, 2));
+
+    assert_eq!(punctuality_report(&p).unwrap(), "\
+train    location arr.plan      arr   delay dep.plan      dep   delay
+t1       n1            0.0      0.0    +0.0        -        -       -
+t1       sig           8.0     15.0    +7.0     20.0     22.0    +2.0
+t1       b2           31.0     30.0    -1.0        -        -       -
+t2       b2           50.0        -       -        -        -       -
+
+On time (delay at most 5 s): 2 of 4 timing points
+");
+}
@@ -0,0 +1,90 @@
+extern crate rolling;
+
+use rolling::input::dispatch::dispatch_string;
+use rolling::input::timetable::{parse_timetable, timetable_dispatch};
+
+/// Malformed numbers in the infrastructure are reported as errors.
+#[test]
+fn infrastructure_invalid_number() {
+    for number in &["-1-2", "-1e", "1.2.3", "1-"] {
+        let err = rolling::get_infrastructure_string(&format!("
+            boundary b1
+            node b1-n1
+            linear n1-n2 100.0 (gradient {})
+            node n2-b2
+            boundary b2
+        ", number)).err().unwrap();
+        assert_eq!(err.to_string(), format!("invalid number at 3: {}", number.trim_start_matches('-')));
+    }
+}
+
+const STOP_INFRASTRUCTURE: &str = include_str!("../../docs/examples/simplestop/infrastructure");
+const STOP_ROUTES: &str = include_str!("../../docs/examples/simplestop/routes");
+
+#[test]
+fn timetable() {
+    let tt = parse_timetable("
+        driver cautious margin=50.0 reaction=2.0
+        train t1 at 5.0 l=35.0 a=1.0 b=0.5 v=10.0 driver=cautious routes ri re
+        arrive t1 sig 20.0
+        depart t1 sig 30.0
+        arrive t1 b2 45.5
+
+        train t2 at 40.0 l=35.0 a=1.0 b=1.0 v=10.0 routes ri re
+    ").unwrap();
+    assert_eq!(tt.trains.len(), 2);
+    let t1 = &tt.trains[0];
+    assert_eq!((t1.name.as_str(), t1.entry), ("t1", 5.0));
+    assert_eq!((t1.params.length, t1.params.max_acc, t1.params.max_brk, t1.params.max_vel), (35.0, 1.0, 0.5, 10.0));
+    assert_eq!((t1.profile.safety_margin, t1.profile.reaction_time), (50.0, 2.0));
+    assert_eq!(t1.routes, vec!["ri", "re"]);
+    // The arrival and departure at the same location form one timing point.
+    let points = t1.points.iter().map(|p| (p.location.as_str(), p.arrival, p.departure)).collect::<Vec<_>>();
+    assert_eq!(points, vec![("sig", Some(20.0), Some(30.0)), ("b2", Some(45.5), None)]);
+    assert!(tt.trains[1].points.is_empty());
+
+    // The route from the signal is requested at the planned departure.
+    let (inf, names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let routes = rolling::get_routes_string(STOP_ROUTES, &names).unwrap();
+    let dispatch = timetable_dispatch(&tt, &inf, &names, &routes).unwrap();
+    assert_eq!(dispatch_string(&dispatch), "\
+wait 5.0
+driver t1 margin=50.0 reaction=2.0 accel=1.0 coast=never
+train t1 l=35.0 a=1.0 b=0.5 v=10.0 driver=t1 ri
+wait 25.0
+route re
+wait 10.0
+train t2 l=35.0 a=1.0 b=1.0 v=10.0 ri
+route re
+");
+}
+
+#[test]
+fn timetable_malformed() {
+    let err = |s: &str| parse_timetable(s).err().map(|e| e.to_string());
+    let train = "train t1 at 0.0 l=35.0 a=1.0 b=1.0 v=10.0 routes ri re\n";
+    assert_eq!(err("train t1 at 0.0 l=35.0 routes ri re"),
+               Some("unrecognized dispatch: train t1 at 0.0 l=35.0 routes ri re".to_string()));
+    assert_eq!(err("train t1 at 1.2.3 l=35.0 a=1.0 b=1.0 v=10.0 routes ri"),
+               Some("error converting number".to_string()));
+    assert_eq!(err("train t1 at 0.0 l=35.0 a=1.0 b=1.0 v=10.0 driver=fast routes ri"),
+               Some("unknown driver profile: fast".to_string()));
+    assert_eq!(err(&format!("{}arrive t2 sig 20.0", train)), Some("unknown train: t2".to_string()));
+    assert_eq!(err(&format!("{}arrive t1 sig", train)), Some("unrecognized dispatch: arrive t1 sig".to_string()));
+    assert_eq!(err(&format!("{}depart t1 sig 1.0.0", train)), Some("error converting number".to_string()));
+}
+
+#[test]
+fn timetable_unknown_location() {
+    let (inf, names) = rolling::get_infrastructure_string(STOP_INFRASTRUCTURE).unwrap();
+    let routes = rolling::get_routes_string(STOP_ROUTES, &names).unwrap();
+    let dispatch = |s: &str| timetable_dispatch(&parse_timetable(s).unwrap(), &inf, &names, &routes)
+        .err().map(|e| e.to_string());
+    let train = "train t1 at 0.0 l=35.0 a=1.0 b=1.0 v=10.0 routes ri re\n";
+    assert_eq!(dispatch(&format!("{}depart t1 platform1 20.0", train)),
+               Some("unknown location: platform1".to_string()));
+    assert_eq!(dispatch("train t1 at 0.0 l=35.0 a=1.0 b=1.0 v=10.0 routes ri rx"),
+               Some("unknown route: rx".to_string()));
+    // Locations are only resolved for departures when converting to a dispatch plan.
+    assert_eq!(dispatch(&format!("{}arrive t1 sig 20.0\ndepart t1 sig 30.0", train)), None);
+}
@@ -0,0 +1,298 @@
+extern crate rolling;
+
+use rolling::input::dispatch::parse_dispatch;
+use rolling::output::history::{History, InfrastructureLogEvent, RouteStatus, TrainLogEvent};
+use rolling::railway::SignallingMode;
+use rolling::railway::dynamics::DriverAction;
+
+/// Time and distance travelled by the front of a train after each of its events.
+fn train_positions(history: &History, train: usize) -> Vec<(f64, f64)> {
+    let mut t = 0.0;
+    let mut x = 0.0;
+    let mut positions = vec![(t, x)];
+    for ev in &history.trains[train].2 {
+        match *ev {
+            TrainLogEvent::Wait(dt) => t += dt,
+            TrainLogEvent::Move(dt, _, update) => { t += dt; x += update.dx; }
+            _ => continue,
+        }
+        positions.push((t, x));
+    }
+    positions
+}
+
+/// Two trains entering from the same boundary follow each other through signal `s1`.
+/// The first train is slow, so the second train reaches the signal while the tail of
+/// the first train is still behind it.
+#[test]
+fn moving_block_following_trains() {
+    let (inf, names) = rolling::get_infrastructure_string("
+        boundary b1
+        node b1-n1
+        linear n1-n2 1000.0
+        node n2-n2u(enter a1, signal s1)
+        linear n2u-n3 1000.0
+        node n3-n3u(exit a1, enter a2)
+        linear n3u-n4 1000.0
+        node n4-b2(exit a2)
+        boundary b2
+    ").unwrap();
+    let routes = rolling::get_routes_string("
+        modelentry ri from b1 {
+          exit s1
+          length 1000.0
+          sections [] switches [] contains []
+        }
+        modelexit rx to b2 {
+          entry s1
+          entrysection a1
+          length 2000.0
+          sections [a1, a2]
+          switches [] contains []
+        }
+    ", &names).unwrap();
+    let dispatch = parse_dispatch("train t1 l=300.0 a=1.0 b=1.0 v=2.0 ri
+route rx
+train t2 l=300.0 a=1.0 b=1.0 v=30.0 ri
+wait 1000.0
+route rx").unwrap();
+
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::MovingBlock);
+    let first = train_positions(&history, 0);
+    let second = train_positions(&history, 1);
+    let length = history.trains[0].1.length;
+
+    // Both trains have left the model.
+    assert!((first.last().unwrap().1 - 3000.0 - length).abs() < 1e-3);
+    assert!((second.last().unwrap().1 - 3000.0 - length).abs() < 1e-3);
+
+    // The front of the second train stays behind the tail of the first train.
+    // Positions only increase, so the first train is at least as far as its
+    // position after its last event before each event of the second train.
+    let left = first.last().unwrap().0;
+    for &(t, x) in &second {
+        if x <= 0.0 || t > left { continue; }
+        let leader = first.iter().take_while(|&&(t1, _)| t1 <= t).last().unwrap().1;
+        assert!(x <= leader - length + 1e-3,
+                "second train at {} m is ahead of the tail of the first train at {} m at time {}",
+                x, leader - length, t);
+    }
+}
+
+/// A train runs from signal `s1` to `s2`, with an overlap past `s2` over switch `sw`
+/// in the other position than the route from `s2`.
+const OVERLAP_INFRASTRUCTURE: &str = "
+    boundary b1
+    node b1-n1
+    linear n1-n2 800.0
+    node n2-n2u(sight s1 200.0)
+    linear n2u-n3 200.0
+    node n3-n3u(enter a1, signal s1)
+    linear n3u-n4 800.0
+    node n4-n4u(sight s2 200.0)
+    linear n4u-n5 200.0
+    node n5-n5u(exit a1, enter a2, signal s2)
+    linear n5u-n6 100.0
+    node n6-n6u
+    switch sw left n6u-(n7 100.0, n8 100.0)
+    node n7-b2(exit a2)
+    boundary b2
+    node n8-b3(exit a2)
+    boundary b3
+";
+
+fn overlap_routes(timeout: &str) -> String {
+    format!("
+        modelentry ri from b1 {{
+          exit s1
+          length 1000.0
+          sections [] switches [] contains []
+        }}
+        route r1 {{
+          entry s1 exit s2
+          entrysection a1
+          length 1000.0
+          sections [a1]
+          switches [] contains []
+          release {{ length 1000.0 trigger a1 resources [a1] }}
+          overlap {{ sections [a2] switches [sw left] {} }}
+        }}
+        modelexit rx to b3 {{
+          entry s2
+          entrysection a2
+          length 1000.0
+          sections [a2]
+          switches [sw right] contains []
+        }}
+    ", timeout)
+}
+
+/// The route from the exit signal takes over the overlap, also when the overlap
+/// has no timeout, and a later overlap timeout does not release its switch.
+#[test]
+fn overlap_released_by_next_route() {
+    for &timeout in &["", "timeout 30.0"] {
+        let (inf, names) = rolling::get_infrastructure_string(OVERLAP_INFRASTRUCTURE).unwrap();
+        let routes = rolling::get_routes_string(&overlap_routes(timeout), &names).unwrap();
+        let dispatch = parse_dispatch("train t1 l=100.0 a=1.0 b=1.0 v=20.0 ri\nroute r1\nroute rx").unwrap();
+        let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::Lineside);
+
+        // The train has left the model through the switch.
+        let positions = train_positions(&history, 0);
+        assert!((positions.last().unwrap().1 - 2200.0 - 100.0).abs() < 1e-3,
+                "train stopped at {} m with overlap {:?}", positions.last().unwrap().1, timeout);
+
+        // The switch is released once when the route takes it over from the overlap,
+        // and stays locked until the train has cleared its section.
+        let sw = names.object_names["sw"];
+        let a2 = names.object_names["a2"];
+        let cleared = history.inf.iter().position(|ev| match *ev {
+            InfrastructureLogEvent::Occupied(s, false, _, _) => s == a2,
+            _ => false,
+        }).unwrap();
+        let released = history.inf[..cleared].iter().filter(|ev| match **ev {
+            InfrastructureLogEvent::Reserved(s, false) => s == sw,
+            _ => false,
+        }).count();
+        assert_eq!(released, 1, "with overlap {:?}", timeout);
+    }
+}
+
+/// Two trains follow each other over switch `sw` to `b2` in moving block, and a
+/// third train is then routed over the switch in the other position to `b3`.
+/// The second train enters the switch section before the first train has left it.
+#[test]
+fn moving_block_release_per_train() {
+    let (inf, names) = rolling::get_infrastructure_string("
+        boundary b1
+        node b1-n1
+        linear n1-n2 1000.0
+        node n2-n2u(enter a1, signal s1)
+        linear n2u-n3 1000.0
+        node n3-n3u
+        switch sw left n3u-(n4 1000.0, n5 1000.0)
+        node n4-b2(exit a1)
+        boundary b2
+        node n5-b3(exit a1)
+        boundary b3
+    ").unwrap();
+    let routes = rolling::get_routes_string("
+        modelentry ri from b1 {
+          exit s1
+          length 1000.0
+          sections [] switches [] contains []
+        }
+        modelexit rl to b2 {
+          entry s1
+          entrysection a1
+          length 2000.0
+          sections [a1]
+          switches [sw left] contains []
+          release { length 2000.0 trigger a1 resources [a1, sw] }
+        }
+        modelexit rr to b3 {
+          entry s1
+          entrysection a1
+          length 2000.0
+          sections [a1]
+          switches [sw right] contains []
+          release { length 2000.0 trigger a1 resources [a1, sw] }
+        }
+    ", &names).unwrap();
+    let dispatch = parse_dispatch("train t1 l=300.0 a=1.0 b=1.0 v=2.0 ri
+route rl
+wait 500.0
+train t2 l=300.0 a=1.0 b=1.0 v=30.0 ri
+route rl
+wait 100.0
+train t3 l=300.0 a=1.0 b=1.0 v=30.0 ri
+route rr").unwrap();
+
+    let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, SignallingMode::MovingBlock);
+
+    // All trains have left the model.
+    for train in 0..3 {
+        let positions = train_positions(&history, train);
+        assert!((positions.last().unwrap().1 - 3000.0 - 300.0).abs() < 1e-3,
+                "train {} stopped at {} m", train, positions.last().unwrap().1);
+    }
+
+    // The route of the first train is released when it leaves the section,
+    // while the second train is still in the section.
+    let a1 = names.object_names["a1"];
+    let released = history.inf.iter().position(|ev| match *ev {
+        InfrastructureLogEvent::Route(_, RouteStatus::Released) => true,
+        _ => false,
+    }).unwrap();
+    let cleared = history.inf.iter().position(|ev| match *ev {
+        InfrastructureLogEvent::Occupied(s, false, _, _) => s == a1,
+        _ => false,
+    }).unwrap();
+    assert!(released < cleared);
+
+    // The switch is unlocked once for the two trains to `b2` and once for the train to `b3`.
+    let sw = names.object_names["sw"];
+    let unlocked = history.inf.iter().filter(|ev| match **ev {
+        InfrastructureLogEvent::Reserved(s, false) => s == sw,
+        _ => false,
+    }).count();
+    assert_eq!(unlocked, 2);
+}
+
+/// A line from `b1` over signals `s1` and `s2` to `b2`, where the signals can only
+/// be seen from 50 m ahead.
+#[test]
+fn continuous_authority_past_signal() {
+    let (inf, names) = rolling::get_infrastructure_string("
+        boundary b1
+        node b1-n1
+        linear n1-n2 950.0
+        node n2-n2u(sight s1 50.0)
+        linear n2u-n3 50.0
+        node n3-n3u(enter a1, signal s1)
+        linear n3u-n4 950.0
+        node n4-n4u(sight s2 50.0)
+        linear n4u-n5 50.0
+        node n5-n5u(exit a1, enter a2, signal s2)
+        linear n5u-n6 1000.0
+        node n6-b2(exit a2)
+        boundary b2
+    ").unwrap();
+    let routes = rolling::get_routes_string("
+        modelentry ri from b1 {
+          exit s1
+          length 1000.0
+          sections [] switches [] contains []
+        }
+        route r1 {
+          entry s1 exit s2
+          entrysection a1
+          length 1000.0
+          sections [a1]
+          switches [] contains []
+        }
+        modelexit rx to b2 {
+          entry s2
+          entrysection a2
+          length 2000.0
+          sections [a2]
+          switches [] contains []
+        }
+    ", &names).unwrap();
+    let dispatch = parse_dispatch("train t1 l=100.0 a=1.0 b=1.0 v=30.0 ri\nroute r1\nroute rx").unwrap();
+
+    let braking = |mode| {
+        let history = rolling::evaluate_plan(&inf, &routes, &dispatch, None, mode);
+        let positions = train_positions(&history, 0);
+        assert!((positions.last().unwrap().1 - 3000.0 - 100.0).abs() < 1e-3);
+        history.trains[0].2.iter().any(|ev| match *ev {
+            TrainLogEvent::Move(_, DriverAction::Brake, _) => true,
+            _ => false,
+        })
+    };
+
+    // With lineside signalling, the driver brakes for the signals until they are seen.
+    assert!(braking(SignallingMode::Lineside));
+    // With continuous signalling, the authority reaches past the signals from the start.
+    assert!(!braking(SignallingMode::Continuous));
+}
@@ -281,7 +281,7 @@ fn gnode(inf :&StaticInfrastructure, turns :&HashSet<usize>) -> Result<GNodeData
             },
 
             Edges::Switchable(obj) => {
-                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side } = inf.objects[obj] {
+                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side, .. } = inf.objects[obj] {
                     // Switch in down direction == incoming switch == down
                     nodes.insert(n, GNode::Switch(sw_side(branch_side), Dir::Down, inf.nodes[n].other_node,
                                              (left_link.0, right_link.0)));
@@ -312,7 +312,7 @@ fn gnode(inf :&StaticInfrastructure, turns :&HashSet<usize>) -> Result<GNodeData
                 if !visited.contains(&a) { queue.push_back(a);}
             },
             Edges::Switchable(obj) => {
-                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side } = inf.objects[obj] {
+                if let StaticObject::Switch { ref left_link, ref right_link, ref branch_side, .. } = inf.objects[obj] {
                     // Switch in up direction == outgoing switch
                     nodes.insert(upnode, GNode::Switch(sw_side(branch_side), Dir::Up, n, 
                                                       (left_link.0, right_link.0)));
@@ -38,6 +38,8 @@ pub fn convert_dgraph(inf :&StaticInfrastructure) -> Result<Schematic, String> {
     let names = InfNames {
         node_names: inf.nodes.iter().enumerate().map(|(i,_)| (format!("node_{}",i),i )).collect(),
         object_names: inf.objects.iter().enumerate().map(|(i,_)| (format!("obj_{}",i),i )).collect(),
+        node_positions: HashMap::new(),
+        stop_locations: HashMap::new(),
     };
 
     let (visgraph_str, original_edges, pos_range) = convert::convert(inf, &names).map_err(|e| format!("{:?}", e))?;